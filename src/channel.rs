@@ -3,26 +3,67 @@ use std::collections::VecDeque;
 use log::debug;
 use serenity::all::ChannelId;
 
-use crate::claude::{Content, Interaction, Role};
+use crate::claude::tokens::estimate_interaction;
+use crate::claude::{Content, ImageSource, Interaction, Role};
 
 /// Represents a Discord channel with conversation history.
 /// Each channel maintains its own conversation context and history limits for Claude AI interactions.
 pub struct Channel {
   hist: VecDeque<Interaction>,
   limit: Option<usize>,
+  /// Uploaded files referenced by history that has since been dropped.
+  dropped_files: Vec<String>,
 }
 
 impl Channel {
-  /// Instantiate a new channel with an optional limit on the number of messages in the history window.
+  /// Instantiate a new channel with an optional limit on the number of tokens in the history window.
+  /// Without a limit, history is only trimmed to fit the model's context window.
   pub fn new(id: ChannelId, limit: Option<usize>) -> Self {
-    debug!(
-      "Creating new channel {:?} with interaction limit {:?}",
-      id, limit
-    );
+    debug!("Creating new channel {:?} with token limit {:?}", id, limit);
 
     Self {
       hist: VecDeque::new(),
       limit,
+      dropped_files: vec![],
+    }
+  }
+
+  /// Takes the IDs of uploaded files that no longer appear in the history, so they can be deleted.
+  pub fn take_dropped_files(&mut self) -> Vec<String> {
+    std::mem::take(&mut self.dropped_files)
+  }
+
+  /// Uploaded files the history refers to.
+  pub fn files(&self) -> Vec<String> {
+    self.hist.iter().flat_map(Self::interaction_files).collect()
+  }
+
+  /// Every uploaded file the channel refers to, for when the channel itself is dropped.
+  pub fn into_files(mut self) -> Vec<String> {
+    let files = self.files();
+    self.dropped_files.extend(files);
+    self.dropped_files
+  }
+
+  fn interaction_files(interaction: &Interaction) -> impl Iterator<Item = String> + '_ {
+    interaction
+      .content
+      .iter()
+      .filter_map(|content| match content {
+        Content::Image {
+          source: ImageSource::File { file_id },
+          ..
+        } => Some(file_id.clone()),
+        _ => None,
+      })
+  }
+
+  /// Removes the oldest interaction, remembering any files it referred to.
+  fn drop_front(&mut self) {
+    if let Some(interaction) = self.hist.pop_front() {
+      self
+        .dropped_files
+        .extend(Self::interaction_files(&interaction));
     }
   }
 
@@ -41,15 +82,90 @@ impl Channel {
       .any(|interaction| interaction.content.iter().any(|content| content.is_image()))
   }
 
+  /// Collects Claude's reasoning from its most recent reply, including any tool use
+  /// round trips. Returns the visible thinking text and whether any of it was redacted.
+  pub fn last_thinking(&self) -> Option<(String, bool)> {
+    let mut thoughts = vec![];
+    let mut redacted = false;
+
+    for interaction in self.hist.iter().rev() {
+      match interaction.role {
+        Role::User if interaction.content.iter().all(Content::is_tool_result) => continue,
+        Role::User => break,
+        Role::Assistant => {}
+      }
+
+      for content in interaction.content.iter().rev() {
+        match content {
+          Content::Thinking { thinking, .. } => thoughts.push(thinking.as_str()),
+          Content::RedactedThinking { .. } => redacted = true,
+          _ => {}
+        }
+      }
+    }
+
+    if thoughts.is_empty() && !redacted {
+      return None;
+    }
+
+    thoughts.reverse();
+    Some((thoughts.join("\n\n"), redacted))
+  }
+
   /// Appends a bot (assistant) response to the conversation history.
   pub fn bot_message(&mut self, interaction: Interaction) {
     self.hist.push_back(interaction);
   }
 
+  /// Prepares the history for Claude to carry on with its last reply, which is sent back
+  /// as the start of the response. The API rejects a prefill that ends in whitespace.
+  pub fn prepare_continuation(&mut self) {
+    if let Some(Interaction {
+      role: Role::Assistant,
+      content,
+    }) = self.hist.back_mut()
+      && let Some(Content::Text { text, .. }) = content.last_mut()
+    {
+      text.truncate(text.trim_end().len());
+    }
+  }
+
+  /// Appends the rest of a reply that was cut short to the assistant turn it continues.
+  /// Text picks up exactly where the previous text block left off.
+  pub fn continue_assistant(&mut self, new_content: Vec<Content>) {
+    let Some(Interaction {
+      role: Role::Assistant,
+      content,
+    }) = self.hist.back_mut()
+    else {
+      return self.bot_message(Interaction {
+        role: Role::Assistant,
+        content: new_content,
+      });
+    };
+
+    let mut new_content = new_content.into_iter().peekable();
+    if let Some(Content::Text { text, .. }) = content.last_mut()
+      && let Some(Content::Text {
+        text: more,
+        citations: None,
+        ..
+      }) = new_content.peek()
+    {
+      text.push_str(more);
+      new_content.next();
+    }
+    content.extend(new_content);
+  }
+
   /// Removes the most recent interaction from history.
   /// Used for error recovery when a message processing fails.
   pub fn undo_last(&mut self) {
-    self.hist.pop_back();
+    if let Some(interaction) = self.hist.pop_back() {
+      self
+        .dropped_files
+        .extend(Self::interaction_files(&interaction));
+    }
   }
 
   /// Adds user content to the conversation history.
@@ -76,17 +192,55 @@ impl Channel {
     }
   }
 
-  /// Enforces the conversation history size limit by removing old messages.
-  /// Messages are removed in pairs (user+assistant) to maintain conversation coherence
-  /// and prevent leaving the history in an invalid state for Claude processing.
-  pub fn shrink(&mut self) {
-    if let Some(limit) = self.limit {
-      while self.hist.len() > limit {
-        self.hist.drain(..2);
-      }
+  /// Estimated size of the conversation history in tokens.
+  pub fn estimated_tokens(&self) -> usize {
+    self.hist.iter().map(estimate_interaction).sum()
+  }
+
+  /// The number of tokens the history may use, given how many are `available` in the request.
+  pub fn budget(&self, available: usize) -> usize {
+    self.limit.map_or(available, |limit| limit.min(available))
+  }
+
+  /// Enforces the conversation history size limit by removing the oldest exchanges
+  /// until the history fits in `budget(available)` tokens. Sizes come from the local
+  /// estimator, multiplied by `scale` so they can be calibrated against a real count.
+  /// Whole exchanges are removed so tool use is never separated from its result,
+  /// and the most recent user message is always kept.
+  pub fn shrink(&mut self, available: usize, scale: f64) {
+    let budget = self.budget(available) as f64;
+    let mut size = self.estimated_tokens() as f64 * scale;
+
+    while size > budget {
+      // an exchange runs from one user message up to the next one.
+      let Some(end) = self
+        .hist
+        .iter()
+        .skip(1)
+        .position(Self::starts_exchange)
+        .map(|pos| pos + 1)
+      else {
+        break;
+      };
+
+      let removed = self
+        .hist
+        .drain(..end)
+        .map(|interaction| {
+          self
+            .dropped_files
+            .extend(Self::interaction_files(&interaction));
+          estimate_interaction(&interaction)
+        })
+        .sum::<usize>();
+      size -= removed as f64 * scale;
     }
   }
 
+  fn starts_exchange(interaction: &Interaction) -> bool {
+    interaction.role == Role::User && !interaction.content.iter().all(Content::is_tool_result)
+  }
+
   /// Validates and cleans the conversation history for Claude API consumption.
   /// Removes leading assistant messages and empty/tool-only user messages that
   /// would cause Claude API errors due to invalid conversation structure.
@@ -98,14 +252,14 @@ impl Channel {
           role: Role::Assistant,
           ..
         }) => {
-          self.hist.pop_front();
+          self.drop_front();
         }
         Some(Interaction {
           role: Role::User,
           content,
         }) => match content.first() {
           None | Some(Content::ToolResult { .. }) => {
-            self.hist.pop_front();
+            self.drop_front();
           }
           _ => break,
         },
@@ -132,4 +286,153 @@ mod tests {
       vec![Content::text("Hello"), Content::text("World")]
     );
   }
+
+  #[test]
+  fn test_shrink_by_tokens() {
+    let mut channel = Channel::new(ChannelId::new(123), Some(200));
+    let reply = |text: &str| Interaction {
+      role: Role::Assistant,
+      content: vec![Content::text(text)],
+    };
+
+    // ~80 tokens in an exchange that includes a tool round trip.
+    channel.user_message(vec![Content::text("a".repeat(100))]);
+    channel.bot_message(Interaction {
+      role: Role::Assistant,
+      content: vec![Content::tool_use(
+        "toolu_1",
+        "fetch_url",
+        serde_json::json!({}),
+      )],
+    });
+    channel.bot_message(Interaction {
+      role: Role::User,
+      content: vec![Content::tool_result(
+        "toolu_1",
+        vec![Content::text("ok ".repeat(20))],
+        false,
+      )],
+    });
+    channel.bot_message(reply("done"));
+    // ~35 tokens.
+    channel.user_message(vec![Content::text("b".repeat(100))]);
+    channel.bot_message(reply("ok"));
+    channel.user_message(vec![Content::text("c")]);
+
+    channel.shrink(1_000, 1.0);
+    assert_eq!(channel.history().len(), 7);
+
+    // the channel's own limit applies even when the model has room to spare.
+    channel.shrink(1_000, 2.0);
+    assert_eq!(channel.history().len(), 3);
+    assert_eq!(
+      channel.history()[0].content,
+      vec![Content::text("b".repeat(100))]
+    );
+
+    // the latest user message survives even if it doesn't fit on its own.
+    channel.shrink(0, 1.0);
+    assert_eq!(channel.history().len(), 1);
+    assert_eq!(channel.history()[0].content, vec![Content::text("c")]);
+  }
+
+  #[test]
+  fn test_dropped_files() {
+    let mut channel = Channel::new(ChannelId::new(123), None);
+    let image = |id: &str| Content::image(ImageSource::File { file_id: id.into() });
+
+    channel.user_message(vec![Content::text("look"), image("file_1")]);
+    channel.bot_message(Interaction {
+      role: Role::Assistant,
+      content: vec![Content::text("nice")],
+    });
+    channel.user_message(vec![image("file_2")]);
+    channel.user_message(vec![image("file_3")]);
+    assert!(channel.take_dropped_files().is_empty());
+
+    channel.shrink(0, 1.0);
+    assert_eq!(channel.take_dropped_files(), vec!["file_1"]);
+    assert!(channel.take_dropped_files().is_empty());
+
+    channel.undo_last();
+    assert_eq!(channel.take_dropped_files(), vec!["file_2", "file_3"]);
+
+    channel.user_message(vec![image("file_4")]);
+    assert_eq!(channel.files(), vec!["file_4"]);
+    assert_eq!(channel.into_files(), vec!["file_4"]);
+  }
+
+  #[test]
+  fn test_continue_assistant() {
+    let mut channel = Channel::new(ChannelId::new(123), None);
+    channel.user_message(vec![Content::text("Tell me a story")]);
+    channel.bot_message(Interaction {
+      role: Role::Assistant,
+      content: vec![Content::text("Once upon a \n")],
+    });
+
+    channel.prepare_continuation();
+    assert_eq!(
+      channel.history()[1].content,
+      vec![Content::text("Once upon a")]
+    );
+
+    channel.continue_assistant(vec![
+      Content::text(" time."),
+      Content::tool_use("toolu_1", "fetch_url", serde_json::json!({})),
+    ]);
+    assert_eq!(channel.history().len(), 2);
+    assert_eq!(
+      channel.history()[1].content,
+      vec![
+        Content::text("Once upon a time."),
+        Content::tool_use("toolu_1", "fetch_url", serde_json::json!({})),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_last_thinking() {
+    let mut channel = Channel::new(ChannelId::new(123), None);
+    let thinking = |text: &str| Content::Thinking {
+      thinking: text.into(),
+      signature: "sig".into(),
+    };
+
+    channel.user_message(vec![Content::text("Hello")]);
+    channel.bot_message(Interaction {
+      role: Role::Assistant,
+      content: vec![thinking("old"), Content::text("Hi")],
+    });
+    assert_eq!(channel.last_thinking(), Some(("old".into(), false)));
+
+    channel.user_message(vec![Content::text("Look this up")]);
+    channel.bot_message(Interaction {
+      role: Role::Assistant,
+      content: vec![
+        thinking("first"),
+        Content::tool_use("toolu_1", "fetch_url", serde_json::json!({})),
+      ],
+    });
+    channel.bot_message(Interaction {
+      role: Role::User,
+      content: vec![Content::tool_result(
+        "toolu_1",
+        vec![Content::text("ok")],
+        false,
+      )],
+    });
+    channel.bot_message(Interaction {
+      role: Role::Assistant,
+      content: vec![
+        thinking("second"),
+        Content::RedactedThinking { data: "x".into() },
+      ],
+    });
+
+    assert_eq!(
+      channel.last_thinking(),
+      Some(("first\n\nsecond".into(), true))
+    );
+  }
 }
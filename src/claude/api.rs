@@ -1,12 +1,21 @@
 use super::Content;
+use super::Model;
 use super::Schema;
-use super::retry::Retry5xx;
+use super::content::CacheControl;
+use super::error::{Details, parse};
+use super::ratelimit::{RateLimitMiddleware, RateLimitStatus, RateLimits};
+use super::retry::{Retry5xx, STATUS_OVERLOADED};
+use super::stream::{Accumulator, Delta, Event, EventParser};
 
-use reqwest_middleware::ClientBuilder;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest_middleware::{ClientBuilder as MiddlewareBuilder, ClientWithMiddleware, Middleware};
 use reqwest_retry::RetryTransientMiddleware;
 use reqwest_retry::policies::ExponentialBackoff;
 use serde::{Deserialize, Serialize};
 use std::fmt::Display;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
 
 #[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
@@ -15,25 +24,6 @@ pub enum Role {
   Assistant,
 }
 
-#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
-pub enum Model {
-  #[serde(rename = "claude-3-haiku-20240307")]
-  Haiku,
-  #[serde(rename = "claude-3-sonnet-20240229")]
-  Sonnet,
-  #[serde(rename = "claude-3-5-sonnet-latest")]
-  Sonnet35,
-  #[serde(rename = "claude-3-7-sonnet-latest")]
-  Sonnet37,
-  #[serde(rename = "claude-sonnet-4-20250514")]
-  Sonnet4,
-  #[serde(rename = "claude-sonnet-4-5-20250929")]
-  Sonnet45,
-  #[serde(rename = "claude-3-5-haiku-latest")]
-  Haiku35,
-  #[serde(rename = "claude-haiku-4-5-20251001")]
-  Haiku45,
-}
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Interaction {
   pub role: Role,
@@ -48,15 +38,21 @@ pub enum Tool {
     name: String,
     max_uses: usize,
     blocked_domains: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cache_control: Option<CacheControl>,
   },
   CodeExecution {
     r#type: String,
     name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cache_control: Option<CacheControl>,
   },
   Custom {
     name: String,
     description: String,
-    input_schema: Schema,
+    input_schema: Box<Schema>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cache_control: Option<CacheControl>,
   },
 }
 
@@ -75,6 +71,7 @@ impl Tool {
       name: "web_search".into(),
       max_uses,
       blocked_domains,
+      cache_control: None,
     }
   }
 
@@ -82,39 +79,241 @@ impl Tool {
     Tool::CodeExecution {
       r#type: "code_execution_20250522".into(),
       name: "code_execution".into(),
+      cache_control: None,
+    }
+  }
+
+  /// Sets a cache breakpoint after this tool definition.
+  pub fn set_cache_control(&mut self, control: CacheControl) {
+    match self {
+      Tool::WebSearch { cache_control, .. }
+      | Tool::CodeExecution { cache_control, .. }
+      | Tool::Custom { cache_control, .. } => *cache_control = Some(control),
     }
   }
 }
 
-#[derive(Serialize)]
-struct Request<'a> {
+/// Controls whether and which tools the model uses for a request.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ToolChoice {
+  /// The model decides whether to use tools.
+  Auto {
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    disable_parallel_tool_use: bool,
+  },
+  /// The model must use one of the tools, but may pick which.
+  Any {
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    disable_parallel_tool_use: bool,
+  },
+  /// The model must use the named tool.
+  Tool {
+    name: String,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    disable_parallel_tool_use: bool,
+  },
+  /// The model may not use any tools.
+  None,
+}
+
+impl ToolChoice {
+  /// Parses a configured choice: `auto`, `any`, `none`, or the name of a tool to force.
+  pub fn parse(choice: &str) -> Self {
+    match choice.trim() {
+      "auto" => ToolChoice::Auto {
+        disable_parallel_tool_use: false,
+      },
+      "any" => ToolChoice::Any {
+        disable_parallel_tool_use: false,
+      },
+      "none" => ToolChoice::None,
+      name => ToolChoice::Tool {
+        name: name.into(),
+        disable_parallel_tool_use: false,
+      },
+    }
+  }
+
+  /// Whether the model is required to use a tool.
+  pub fn forces_tool_use(&self) -> bool {
+    matches!(self, ToolChoice::Any { .. } | ToolChoice::Tool { .. })
+  }
+
+  fn with_parallel_tool_use(mut self, allowed: bool) -> Self {
+    match &mut self {
+      ToolChoice::Auto {
+        disable_parallel_tool_use,
+      }
+      | ToolChoice::Any {
+        disable_parallel_tool_use,
+      }
+      | ToolChoice::Tool {
+        disable_parallel_tool_use,
+        ..
+      } => *disable_parallel_tool_use = !allowed,
+      ToolChoice::None => {}
+    }
+    self
+  }
+}
+
+/// Per-call settings for `create_message` that aren't tied to the conversation itself.
+#[derive(Default, Clone, Debug)]
+pub struct MessageOptions {
+  /// Token budget for extended thinking. Thinking is disabled when unset.
+  pub thinking_budget: Option<usize>,
+  /// Tokens for the visible response, on top of any thinking budget.
+  /// Defaults to `RESPONSE_TOKENS`, and is capped at what the model can produce.
+  pub max_tokens: Option<usize>,
+  /// Which tools the model may use. The API defaults to `auto` when unset.
+  pub tool_choice: Option<ToolChoice>,
+  /// Limits the model to one tool call per response.
+  pub disable_parallel_tool_use: bool,
+  /// Abandons the call, returning `Error::Cancelled`, once the token is cancelled.
+  pub cancel: Option<CancellationToken>,
+  /// Randomness of the response, from 0 (most deterministic) to 1.
+  pub temperature: Option<f64>,
+  /// Nucleus sampling cutoff, from 0 to 1. Ignored when `temperature` is also set,
+  /// since newer models only accept one of the two.
+  pub top_p: Option<f64>,
+  /// Only sample from this many of the likeliest tokens.
+  pub top_k: Option<usize>,
+  /// Custom text that ends the response when the model produces it.
+  pub stop_sequences: Vec<String>,
+  /// An opaque ID for the end user, which the API uses to detect abuse. Never send anything identifying.
+  pub user_id: Option<String>,
+}
+
+impl MessageOptions {
+  /// The `max_tokens` sent with the request: room for thinking plus the visible response.
+  pub fn max_tokens(&self) -> usize {
+    self.thinking_budget.unwrap_or(0) + self.max_tokens.unwrap_or(RESPONSE_TOKENS)
+  }
+
+  /// The `tool_choice` sent with the request, if anything differs from the API's default.
+  fn tool_choice(&self) -> Option<ToolChoice> {
+    let choice = match (&self.tool_choice, self.disable_parallel_tool_use) {
+      (Some(choice), _) => choice.clone(),
+      (None, true) => ToolChoice::parse("auto"),
+      (None, false) => return None,
+    };
+    Some(choice.with_parallel_tool_use(!self.disable_parallel_tool_use))
+  }
+}
+
+/// Default tokens for the visible response on top of any thinking budget.
+const RESPONSE_TOKENS: usize = 1024;
+
+/// The smallest thinking budget the API accepts.
+const MIN_THINKING_BUDGET: usize = 1024;
+
+#[derive(Serialize, Debug)]
+struct Metadata {
+  user_id: String,
+}
+
+/// The smallest `top_p` the API accepts while thinking is enabled.
+const MIN_THINKING_TOP_P: f64 = 0.95;
+
+#[derive(Serialize, Debug)]
+struct Thinking {
+  r#type: String,
+  budget_tokens: usize,
+}
+
+/// Runs `call`, unless the options' cancellation token fires first.
+pub(super) async fn cancellable<T>(
+  options: &MessageOptions,
+  call: impl Future<Output = Result<T, super::Error>>,
+) -> Result<T, super::Error> {
+  match &options.cancel {
+    Some(token) => tokio::select! {
+      result = call => result,
+      _ = token.cancelled() => Err(super::Error::Cancelled),
+    },
+    None => call.await,
+  }
+}
+
+/// A fully prepared Messages API request.
+#[derive(Serialize, Debug)]
+pub struct Request {
   model: Model,
   max_tokens: usize,
-  system: String,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  system: Vec<Content>,
   messages: Vec<Interaction>,
+  tools: Vec<Tool>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  tool_choice: Option<ToolChoice>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  thinking: Option<Thinking>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  temperature: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  top_p: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  top_k: Option<usize>,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  stop_sequences: Vec<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  metadata: Option<Metadata>,
+  #[serde(skip_serializing_if = "std::ops::Not::not")]
+  stream: bool,
+}
+
+/// The subset of a `Request` accepted by the `count_tokens` endpoint.
+#[derive(Serialize)]
+struct CountTokensRequest<'a> {
+  model: &'a Model,
+  #[serde(skip_serializing_if = "<[_]>::is_empty")]
+  system: &'a [Content],
+  messages: &'a [Interaction],
+  #[serde(skip_serializing_if = "<[_]>::is_empty")]
   tools: &'a [Tool],
+  #[serde(skip_serializing_if = "Option::is_none")]
+  tool_choice: &'a Option<ToolChoice>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  thinking: &'a Option<Thinking>,
 }
 
-#[derive(Deserialize, Debug)]
-pub struct Usage {
-  pub input_tokens: usize,
-  pub output_tokens: usize,
+#[derive(Deserialize)]
+struct CountTokensResponse {
+  input_tokens: usize,
 }
 
-#[derive(Deserialize, Debug)]
-pub struct ModelDetails {
-  pub id: String,
-  pub display_name: String,
-  pub r#type: String,
-  pub created_at: String,
+impl Request {
+  /// Places cache breakpoints on the system prompt, the last tool definition, and the
+  /// last turn of the conversation. History is append-only between trims, so everything
+  /// up to the final turn is a prefix of the next request and can be read back from the cache.
+  fn cache_prefix(&mut self) {
+    if let Some(block) = self.system.last_mut() {
+      block.set_cache_control(CacheControl::ephemeral());
+    }
+
+    if let Some(tool) = self.tools.last_mut() {
+      tool.set_cache_control(CacheControl::ephemeral());
+    }
+
+    if let Some(turn) = self.messages.last_mut() {
+      for block in turn.content.iter_mut().rev() {
+        if block.set_cache_control(CacheControl::ephemeral()) {
+          break;
+        }
+      }
+    }
+  }
 }
 
-#[derive(Deserialize, Debug)]
-pub struct ListModelsResponse {
-  pub data: Vec<ModelDetails>,
-  pub first_id: String,
-  pub has_more: bool,
-  pub last_id: String,
+#[derive(Deserialize, Clone, Debug)]
+pub struct Usage {
+  pub input_tokens: usize,
+  pub output_tokens: usize,
+  #[serde(default)]
+  pub cache_creation_input_tokens: Option<usize>,
+  #[serde(default)]
+  pub cache_read_input_tokens: Option<usize>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -164,100 +363,909 @@ pub enum Response {
 
 impl std::error::Error for APIError {}
 
-const API_URL: &'static str = "https://api.anthropic.com/v1/messages";
+const DEFAULT_BASE_URL: &str = "https://api.anthropic.com";
+const DEFAULT_API_VERSION: &str = "2023-06-01";
+const DEFAULT_BETA: &str = "code-execution-2025-05-22,tools-2024-05-16,files-api-2025-04-14";
+
+/// Default time allowed to establish a connection.
+pub(super) const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default time allowed between reads. Streams send pings well within this; non-streaming
+/// requests with long outputs can take minutes before the first byte comes back.
+pub(super) const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// How often idle HTTP/2 connections are pinged, so dead ones are noticed before they're reused.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(30);
 
 pub struct Client {
+  model: Model,
+  base_url: String,
+  prompt_caching: bool,
+  rate_limits: RateLimits,
+  /// Shared by every call, so connections and TLS sessions are reused.
+  http: ClientWithMiddleware,
+}
+
+/// Configures a `Client`. Everything except the API key and default model has a
+/// sensible default, so `Client::new` is equivalent to `Client::builder(..).build()`.
+pub struct ClientBuilder {
   api_key: String,
   model: Model,
+  base_url: String,
+  api_version: String,
+  headers: HeaderMap,
+  read_timeout: Duration,
+  max_retries: u32,
+  retry_bounds: (Duration, Duration),
+  prompt_caching: bool,
+  middleware: Vec<Arc<dyn Middleware>>,
+}
+
+impl ClientBuilder {
+  /// Sets the API root, e.g. `https://api.anthropic.com` or a local test server.
+  pub fn base_url<S: Into<String>>(mut self, url: S) -> Self {
+    self.base_url = url.into().trim_end_matches('/').to_owned();
+    self
+  }
+
+  /// Sets the `Anthropic-Version` header sent with every request.
+  pub fn api_version<S: Into<String>>(mut self, version: S) -> Self {
+    self.api_version = version.into();
+    self
+  }
+
+  /// Adds a header sent with every request, replacing any existing value.
+  pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+    self.headers.insert(name, value);
+    self
+  }
+
+  /// Sets the time allowed to wait for more of a response before giving up on it.
+  pub fn read_timeout(mut self, timeout: Duration) -> Self {
+    self.read_timeout = timeout;
+    self
+  }
+
+  /// Sets how many times a transient failure is retried before giving up.
+  pub fn max_retries(mut self, retries: u32) -> Self {
+    self.max_retries = retries;
+    self
+  }
+
+  /// Sets the minimum and maximum delay between retries.
+  pub fn retry_bounds(mut self, min: Duration, max: Duration) -> Self {
+    self.retry_bounds = (min, max);
+    self
+  }
+
+  /// Enables or disables automatic cache breakpoints. Enabled by default.
+  pub fn prompt_caching(mut self, enabled: bool) -> Self {
+    self.prompt_caching = enabled;
+    self
+  }
+
+  /// Adds middleware underneath retries and rate limiting, closest to the network.
+  /// Every attempt at a request passes through it.
+  pub fn middleware<M: Middleware>(mut self, middleware: M) -> Self {
+    self.middleware.push(Arc::new(middleware));
+    self
+  }
+
+  pub fn build(self) -> Client {
+    let mut headers = self.headers;
+    if let Ok(mut key) = HeaderValue::from_str(&self.api_key) {
+      key.set_sensitive(true);
+      headers.insert("X-API-Key", key);
+    }
+    if let Ok(version) = HeaderValue::from_str(&self.api_version) {
+      headers.insert("Anthropic-Version", version);
+    }
+
+    // no overall timeout, since streamed responses can run for a long time;
+    // the read timeout catches hung connections.
+    let http = reqwest::Client::builder()
+      .default_headers(headers)
+      .connect_timeout(DEFAULT_CONNECT_TIMEOUT)
+      .read_timeout(self.read_timeout)
+      .tcp_keepalive(KEEP_ALIVE_INTERVAL)
+      .http2_keep_alive_interval(KEEP_ALIVE_INTERVAL)
+      .http2_keep_alive_while_idle(true)
+      .build()
+      .expect("failed to build the HTTP client");
+
+    let (min, max) = self.retry_bounds;
+    let retry_policy = ExponentialBackoff::builder()
+      .base(2)
+      .retry_bounds(min, max)
+      .build_with_max_retries(self.max_retries);
+    let rate_limits = RateLimits::default();
+
+    let mut http = MiddlewareBuilder::new(http)
+      .with(RetryTransientMiddleware::new_with_policy_and_strategy(
+        retry_policy,
+        Retry5xx {},
+      ))
+      .with(RateLimitMiddleware {
+        limits: rate_limits.clone(),
+        max_retries: self.max_retries,
+        retry_bounds: self.retry_bounds,
+      });
+    for middleware in self.middleware {
+      http = http.with_arc(middleware);
+    }
+    let http = http.build();
+
+    Client {
+      model: self.model,
+      base_url: self.base_url,
+      prompt_caching: self.prompt_caching,
+      rate_limits,
+      http,
+    }
+  }
 }
 
 impl Client {
   pub fn new<S: Into<String>>(api_key: S, model: Model) -> Self {
-    Self {
+    Self::builder(api_key, model).build()
+  }
+
+  pub fn builder<S: Into<String>>(api_key: S, model: Model) -> ClientBuilder {
+    let mut headers = HeaderMap::new();
+    headers.insert("Anthropic-Beta", HeaderValue::from_static(DEFAULT_BETA));
+
+    ClientBuilder {
       api_key: api_key.into(),
       model,
+      base_url: DEFAULT_BASE_URL.into(),
+      api_version: DEFAULT_API_VERSION.into(),
+      headers,
+      read_timeout: DEFAULT_READ_TIMEOUT,
+      max_retries: 3,
+      retry_bounds: (Duration::from_secs(1), Duration::from_secs(30)),
+      prompt_caching: true,
+      middleware: vec![],
     }
   }
 
-  // this function is only for testing and may panic
-  pub async fn models(&self) -> ListModelsResponse {
-    let client = reqwest::Client::new();
-    let resp = client
-      .get("https://api.anthropic.com/v1/models")
-      .header("X-API-Key", &self.api_key)
-      .header("Anthropic-Version", "2023-06-01")
-      .send()
-      .await
-      .unwrap();
+  /// The rate limits most recently reported by the API, and how often we've been throttled.
+  pub fn rate_limits(&self) -> RateLimitStatus {
+    self.rate_limits.lock().unwrap().clone()
+  }
 
-    if !resp.status().is_success() {
-      panic!();
-    }
+  /// The model used when a call doesn't override it.
+  pub fn model(&self) -> Model {
+    self.model.clone()
+  }
 
-    let body = resp
-      .text()
-      .await
-      .map_err(|e| reqwest_middleware::Error::Reqwest(e))
-      .unwrap();
+  pub async fn create_message(
+    &self,
+    model_override: Option<Model>,
+    messages: &[Interaction],
+    tools: &[Tool],
+    prompt: String,
+    options: &MessageOptions,
+  ) -> Result<Response, super::Error> {
+    let payload = self.request(model_override, messages, tools, prompt, options, false);
 
-    let resp: ListModelsResponse = serde_json::from_str(&body).unwrap();
-    resp
+    cancellable(options, async {
+      let resp = self.send(&payload).await?;
+      let body = resp
+        .text()
+        .await
+        .map_err(reqwest_middleware::Error::Reqwest)?;
+
+      match parse(&body)? {
+        Response::Error { error } => Err(error.into()),
+        resp => Ok(resp),
+      }
+    })
+    .await
   }
 
-  pub async fn create_message(
+  /// Same as `create_message`, but consumes the server-sent event stream and invokes
+  /// `on_text` with each text fragment as it arrives. The returned response contains
+  /// the fully assembled content blocks, including any tool use requests.
+  pub async fn create_message_stream<F: FnMut(&str)>(
     &self,
     model_override: Option<Model>,
     messages: &[Interaction],
     tools: &[Tool],
     prompt: String,
+    options: &MessageOptions,
+    mut on_text: F,
   ) -> Result<Response, super::Error> {
-    let payload = Request {
-      model: model_override.unwrap_or(self.model),
-      max_tokens: 1024,
-      system: prompt,
+    let payload = self.request(model_override, messages, tools, prompt, options, true);
+
+    cancellable(options, async {
+      let mut resp = self.send(&payload).await?;
+      let mut parser = EventParser::default();
+      let mut acc = Accumulator::default();
+
+      while let Some(chunk) = resp
+        .chunk()
+        .await
+        .map_err(reqwest_middleware::Error::Reqwest)?
+      {
+        for event in parser.push(&chunk)? {
+          if let Event::ContentBlockDelta {
+            delta: Delta::Text { text },
+            ..
+          } = &event
+          {
+            on_text(text);
+          }
+          acc.apply(event)?;
+        }
+      }
+
+      acc.finish()
+    })
+    .await
+  }
+
+  fn request(
+    &self,
+    model_override: Option<Model>,
+    messages: &[Interaction],
+    tools: &[Tool],
+    prompt: String,
+    options: &MessageOptions,
+    stream: bool,
+  ) -> Request {
+    let system = if prompt.is_empty() {
+      vec![]
+    } else {
+      vec![Content::text(prompt)]
+    };
+
+    let model = model_override.unwrap_or_else(|| self.model.clone());
+    let max_output = model.capabilities().max_output;
+
+    // a tool choice means nothing without tools, and the API won't think while forced to use one.
+    let tool_choice = options.tool_choice().filter(|_| !tools.is_empty());
+    let forced = tool_choice
+      .as_ref()
+      .is_some_and(ToolChoice::forces_tool_use);
+
+    // the budget has to fit inside `max_tokens`, with room left over for the answer.
+    let thinking = options
+      .thinking_budget
+      .filter(|_| !forced)
+      .map(|budget| Thinking {
+        r#type: "enabled".into(),
+        budget_tokens: budget
+          .min(max_output.saturating_sub(RESPONSE_TOKENS))
+          .max(MIN_THINKING_BUDGET),
+      });
+    let max_tokens = (thinking.as_ref().map_or(0, |t| t.budget_tokens)
+      + options.max_tokens.unwrap_or(RESPONSE_TOKENS).max(1))
+    .min(max_output);
+    // thinking only works with the default temperature and top_k, and a high top_p.
+    let sampling = thinking.is_none();
+
+    let mut request = Request {
+      model,
+      max_tokens,
+      system,
       messages: messages.into(),
-      tools,
+      tools: tools.into(),
+      tool_choice,
+      thinking,
+      temperature: options.temperature.filter(|_| sampling),
+      top_p: options
+        .top_p
+        .filter(|&p| options.temperature.is_none() && (sampling || p >= MIN_THINKING_TOP_P)),
+      top_k: options.top_k.filter(|_| sampling),
+      stop_sequences: options.stop_sequences.clone(),
+      metadata: options.user_id.clone().map(|user_id| Metadata { user_id }),
+      stream,
     };
 
+    if self.prompt_caching {
+      request.cache_prefix();
+    }
+
+    request
+  }
+
+  /// Counts the input tokens `create_message` would use for the same arguments, without running the model.
+  pub async fn count_tokens(
+    &self,
+    model_override: Option<Model>,
+    messages: &[Interaction],
+    tools: &[Tool],
+    prompt: String,
+    options: &MessageOptions,
+  ) -> Result<usize, super::Error> {
+    let request = self.request(model_override, messages, tools, prompt, options, false);
+    let payload = CountTokensRequest {
+      model: &request.model,
+      system: &request.system,
+      messages: &request.messages,
+      tools: &request.tools,
+      tool_choice: &request.tool_choice,
+      thinking: &request.thinking,
+    };
     let body = serde_json::to_string(&payload)?;
-    let retry_policy = ExponentialBackoff::builder()
-      .base(2)
-      .build_with_max_retries(3);
 
-    let client = ClientBuilder::new(reqwest::Client::new())
-      .with(RetryTransientMiddleware::new_with_policy_and_strategy(
-        retry_policy,
-        Retry5xx {},
-      ))
+    let resp = self
+      .call(
+        reqwest::Method::POST,
+        "/v1/messages/count_tokens",
+        Some(body),
+      )
+      .await?;
+    let body = resp
+      .text()
+      .await
+      .map_err(reqwest_middleware::Error::Reqwest)?;
+
+    let counted: CountTokensResponse = parse(&body)?;
+    Ok(counted.input_tokens)
+  }
+
+  /// Builds the request `create_message` would send, without sending it.
+  /// Used to queue work up for a message batch.
+  pub fn batch_request(
+    &self,
+    model_override: Option<Model>,
+    messages: &[Interaction],
+    tools: &[Tool],
+    prompt: String,
+    options: &MessageOptions,
+  ) -> Request {
+    self.request(model_override, messages, tools, prompt, options, false)
+  }
+
+  /// Sends a request to the Messages API.
+  async fn send(&self, payload: &Request) -> Result<reqwest::Response, super::Error> {
+    let body = serde_json::to_string(payload)?;
+    self
+      .call(reqwest::Method::POST, "/v1/messages", Some(body))
+      .await
+  }
+
+  /// Sends a request to any API endpoint, waiting for rate limit quota and retrying
+  /// transient server failures.
+  /// Client errors are decoded into an `APIError` so callers only ever see a successful response.
+  pub(super) async fn call(
+    &self,
+    method: reqwest::Method,
+    path: &str,
+    body: Option<String>,
+  ) -> Result<reqwest::Response, super::Error> {
+    let body = body.map(|body| ("application/json".to_owned(), body.into_bytes()));
+    self.call_with_body(method, path, body).await
+  }
+
+  /// Same as `call`, for bodies that aren't JSON. `body` is the content type and the raw bytes.
+  pub(super) async fn call_with_body(
+    &self,
+    method: reqwest::Method,
+    path: &str,
+    body: Option<(String, Vec<u8>)>,
+  ) -> Result<reqwest::Response, super::Error> {
+    let mut req = self
+      .http
+      .request(method, format!("{}{}", self.base_url, path));
+
+    if let Some((content_type, body)) = body {
+      req = req.header("Content-Type", content_type).body(body);
+    }
+
+    let resp = req.send().await?;
+
+    if let Err(err) = resp.error_for_status_ref() {
+      let status = resp.status();
+      let request_id = resp
+        .headers()
+        .get("request-id")
+        .and_then(|v| v.to_str().ok())
+        .map(String::from);
+      let body = resp.text().await.ok();
+      let details = Details {
+        status: Some(status.as_u16()),
+        request_id,
+        body: None,
+      };
+
+      let api_error = body
+        .as_deref()
+        .filter(|_| status.is_client_error() || status.as_u16() == STATUS_OVERLOADED)
+        .and_then(|body| serde_json::from_str(body).ok());
+
+      return match api_error {
+        Some(Response::Error { error }) => Err(super::Error::APIError(error, details)),
+        _ => Err(super::Error::HttpError(
+          err.into(),
+          Details { body, ..details },
+        )),
+      };
+    }
+
+    Ok(resp)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::claude::testing::{FakeServer, Scripted};
+  use chrono::{TimeDelta, Utc};
+
+  fn hello() -> Vec<Interaction> {
+    vec![Interaction {
+      role: Role::User,
+      content: vec![Content::text("Someone: hello")],
+    }]
+  }
+
+  #[tokio::test]
+  async fn test_create_message() {
+    let server = FakeServer::start(vec![Scripted::message(vec![Content::text("hi!")])]).await;
+    let client = Client::builder("test-key", Model::HAIKU_45)
+      .base_url(server.url())
+      .api_version("2099-01-01")
+      .header(
+        HeaderName::from_static("x-test"),
+        HeaderValue::from_static("yes"),
+      )
       .build();
 
     let resp = client
-      .post(API_URL)
-      .header("Content-Type", "application/json")
-      .header("X-API-Key", &self.api_key)
-      .header("Anthropic-Version", "2023-06-01")
-      .header(
-        "Anthropic-Beta",
-        "code-execution-2025-05-22,tools-2024-05-16",
+      .create_message(
+        None,
+        &hello(),
+        &[],
+        "be nice".into(),
+        &MessageOptions::default(),
       )
-      .body(body)
-      .send()
-      .await?;
+      .await
+      .unwrap();
 
-    if let Err(err) = resp.error_for_status_ref() {
-      if !resp.status().is_client_error() {
-        return Err(super::Error::HttpError(err.into()));
-      }
+    match resp {
+      Response::Message { content, .. } => assert_eq!(content, vec![Content::text("hi!")]),
+      other => panic!("unexpected response {:?}", other),
     }
 
-    let body = resp
-      .text()
+    let requests = server.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].method, "POST");
+    assert_eq!(requests[0].path, "/v1/messages");
+    assert_eq!(requests[0].headers["x-api-key"], "test-key");
+    assert_eq!(requests[0].headers["anthropic-version"], "2099-01-01");
+    assert_eq!(requests[0].headers["x-test"], "yes");
+    assert_eq!(requests[0].body["model"], "claude-haiku-4-5-20251001");
+    assert_eq!(requests[0].body["system"][0]["text"], "be nice");
+  }
+
+  #[tokio::test]
+  async fn test_cache_breakpoints() {
+    let server = FakeServer::start(vec![
+      Scripted::message(vec![Content::text("hi!")]),
+      Scripted::message(vec![Content::text("hi!")]),
+    ])
+    .await;
+    let tools = [Tool::code_execution(), Tool::web_search(1, None)];
+    let cached = serde_json::json!({ "type": "ephemeral" });
+
+    server
+      .client()
+      .create_message(
+        None,
+        &hello(),
+        &tools,
+        "be nice".into(),
+        &MessageOptions::default(),
+      )
+      .await
+      .unwrap();
+
+    let body = &server.requests()[0].body;
+    assert_eq!(body["system"][0]["cache_control"], cached);
+    assert!(body["tools"][0].get("cache_control").is_none());
+    assert_eq!(body["tools"][1]["cache_control"], cached);
+    assert_eq!(body["messages"][0]["content"][0]["cache_control"], cached);
+
+    Client::builder("test-key", Model::HAIKU_45)
+      .base_url(server.url())
+      .prompt_caching(false)
+      .build()
+      .create_message(
+        None,
+        &hello(),
+        &tools,
+        "be nice".into(),
+        &MessageOptions::default(),
+      )
+      .await
+      .unwrap();
+
+    let body = &server.requests()[1].body;
+    assert!(body["system"][0].get("cache_control").is_none());
+    assert!(
+      body["messages"][0]["content"][0]
+        .get("cache_control")
+        .is_none()
+    );
+  }
+
+  #[tokio::test]
+  async fn test_thinking_budget() {
+    let server = FakeServer::start(vec![Scripted::message(vec![Content::text("4")])]).await;
+    let options = MessageOptions {
+      thinking_budget: Some(2_000),
+      ..Default::default()
+    };
+
+    server
+      .client()
+      .create_message(None, &hello(), &[], "".into(), &options)
+      .await
+      .unwrap();
+
+    let body = &server.requests()[0].body;
+    assert_eq!(
+      body["thinking"],
+      serde_json::json!({ "type": "enabled", "budget_tokens": 2_000 })
+    );
+    assert_eq!(body["max_tokens"], 3_024);
+  }
+
+  #[tokio::test]
+  async fn test_thinking_budget_capped_by_model() {
+    let server = FakeServer::start(vec![Scripted::message(vec![Content::text("4")])]).await;
+    let options = MessageOptions {
+      thinking_budget: Some(100_000),
+      ..Default::default()
+    };
+
+    server
+      .client()
+      .create_message(Some(Model::HAIKU_45), &hello(), &[], "".into(), &options)
       .await
-      .map_err(|e| reqwest_middleware::Error::Reqwest(e))?;
+      .unwrap();
+
+    // the budget shrinks to leave room for the answer within the model's output limit.
+    let body = &server.requests()[0].body;
+    assert_eq!(body["thinking"]["budget_tokens"], 62_976);
+    assert_eq!(body["max_tokens"], 64_000);
+  }
+
+  #[tokio::test]
+  async fn test_max_tokens_capped_by_model() {
+    let server = FakeServer::start(vec![
+      Scripted::message(vec![Content::text("1")]),
+      Scripted::message(vec![Content::text("2")]),
+    ])
+    .await;
+    let client = server.client();
+    let options = MessageOptions {
+      max_tokens: Some(100_000),
+      ..Default::default()
+    };
 
-    match serde_json::from_str(&body)? {
-      Response::Error { error } => Err(error.into()),
-      resp => Ok(resp),
+    for model in [Model::HAIKU_45, Model::HAIKU_3] {
+      client
+        .create_message(Some(model), &hello(), &[], "".into(), &options)
+        .await
+        .unwrap();
     }
+
+    let requests = server.requests();
+    assert_eq!(requests[0].body["max_tokens"], 64_000);
+    assert_eq!(requests[1].body["max_tokens"], 4_096);
+  }
+
+  #[tokio::test]
+  async fn test_sampling_options() {
+    let server = FakeServer::start(vec![
+      Scripted::message(vec![Content::text("1")]),
+      Scripted::message(vec![Content::text("2")]),
+    ])
+    .await;
+    let client = server.client();
+    let options = MessageOptions {
+      temperature: Some(0.2),
+      top_p: Some(0.9),
+      top_k: Some(40),
+      stop_sequences: vec!["\n\nHuman:".into()],
+      user_id: Some("abc123".into()),
+      ..Default::default()
+    };
+    let thinking = MessageOptions {
+      thinking_budget: Some(2_000),
+      temperature: None,
+      top_p: Some(0.97),
+      ..options.clone()
+    };
+
+    for options in [&options, &thinking] {
+      client
+        .create_message(None, &hello(), &[], "".into(), options)
+        .await
+        .unwrap();
+    }
+
+    let requests = server.requests();
+    let body = &requests[0].body;
+    assert_eq!(body["temperature"], 0.2);
+    assert!(body["top_p"].is_null());
+    assert_eq!(body["top_k"], 40);
+    assert_eq!(body["stop_sequences"], serde_json::json!(["\n\nHuman:"]));
+    assert_eq!(body["metadata"], serde_json::json!({ "user_id": "abc123" }));
+
+    // thinking keeps a high enough top_p, but not top_k.
+    let body = &requests[1].body;
+    assert_eq!(body["top_p"], 0.97);
+    assert!(body["top_k"].is_null());
+    assert!(body["temperature"].is_null());
+  }
+
+  #[tokio::test]
+  async fn test_tool_choice() {
+    let server = FakeServer::start(vec![
+      Scripted::message(vec![Content::text("1")]),
+      Scripted::message(vec![Content::text("2")]),
+      Scripted::message(vec![Content::text("3")]),
+    ])
+    .await;
+    let client = server.client();
+    let tools = [Tool::Custom {
+      name: "fetch_url".into(),
+      description: "fetches a url".into(),
+      input_schema: Box::new(Schema::object()),
+      cache_control: None,
+    }];
+    let forced = MessageOptions {
+      tool_choice: Some(ToolChoice::parse("fetch_url")),
+      disable_parallel_tool_use: true,
+      thinking_budget: Some(2_000),
+      ..Default::default()
+    };
+    let serial = MessageOptions {
+      disable_parallel_tool_use: true,
+      ..Default::default()
+    };
+
+    for (tools, options) in [
+      (&tools[..], &forced),
+      (&tools[..], &serial),
+      (&[][..], &forced),
+    ] {
+      client
+        .create_message(None, &hello(), tools, "".into(), options)
+        .await
+        .unwrap();
+    }
+
+    let requests = server.requests();
+    assert_eq!(
+      requests[0].body["tool_choice"],
+      serde_json::json!({ "type": "tool", "name": "fetch_url", "disable_parallel_tool_use": true })
+    );
+    assert!(requests[0].body["thinking"].is_null());
+    assert_eq!(
+      requests[1].body["tool_choice"],
+      serde_json::json!({ "type": "auto", "disable_parallel_tool_use": true })
+    );
+    assert!(requests[2].body["tool_choice"].is_null());
+  }
+
+  #[tokio::test]
+  async fn test_create_message_api_error() {
+    let server = FakeServer::start(vec![Scripted::Raw {
+      status: 400,
+      headers: vec![("request-id".into(), "req_123".into())],
+      body: serde_json::json!({
+        "type": "error",
+        "error": { "type": "invalid_request_error", "message": "prompt is too long" },
+      })
+      .to_string(),
+    }])
+    .await;
+
+    let err = server
+      .client()
+      .create_message(None, &hello(), &[], "".into(), &MessageOptions::default())
+      .await
+      .unwrap_err();
+
+    assert!(matches!(
+      err,
+      crate::claude::Error::APIError(APIError::InvalidRequestError { .. }, _)
+    ));
+    assert_eq!(err.status(), Some(400));
+    assert_eq!(err.request_id(), Some("req_123"));
+    assert!(!err.is_retryable());
+    assert_eq!(server.requests().len(), 1);
+  }
+
+  #[tokio::test]
+  async fn test_create_message_retries_server_errors() {
+    let server = FakeServer::start(vec![
+      Scripted::status(500),
+      Scripted::status(503),
+      Scripted::message(vec![Content::text("made it")]),
+    ])
+    .await;
+
+    let resp = server
+      .client()
+      .create_message(None, &hello(), &[], "".into(), &MessageOptions::default())
+      .await;
+
+    assert!(matches!(resp, Ok(Response::Message { .. })));
+    assert_eq!(server.requests().len(), 3);
+  }
+
+  #[tokio::test]
+  async fn test_create_message_waits_out_rate_limits() {
+    // the server is out of requests for the next 200ms, and asks us to back off.
+    let reset = Utc::now() + TimeDelta::milliseconds(200);
+    let server = FakeServer::start(vec![
+      Scripted::Raw {
+        status: 429,
+        headers: vec![
+          ("retry-after".into(), "0".into()),
+          ("anthropic-ratelimit-requests-limit".into(), "50".into()),
+          ("anthropic-ratelimit-requests-remaining".into(), "0".into()),
+          (
+            "anthropic-ratelimit-requests-reset".into(),
+            reset.to_rfc3339(),
+          ),
+        ],
+        body: r#"{"type":"error","error":{"type":"rate_limit_error","message":"slow down"}}"#
+          .into(),
+      },
+      Scripted::message(vec![Content::text("made it")]),
+    ])
+    .await;
+    let client = server.client();
+
+    let resp = client
+      .create_message(None, &hello(), &[], "".into(), &MessageOptions::default())
+      .await;
+
+    assert!(matches!(resp, Ok(Response::Message { .. })));
+    assert!(Utc::now() >= reset);
+    assert_eq!(server.requests().len(), 2);
+
+    let limits = client.rate_limits();
+    assert_eq!(limits.throttled, 1);
+    assert_eq!(limits.requests.limit, Some(50));
+    // refilled at the reset, then one request spent.
+    assert_eq!(limits.requests.remaining, Some(49));
+  }
+
+  #[tokio::test]
+  async fn test_create_message_overloaded() {
+    let server = FakeServer::start(vec![
+      Scripted::error(529, "overloaded_error", "Overloaded"),
+      Scripted::error(529, "overloaded_error", "Overloaded"),
+    ])
+    .await;
+    let client = Client::builder("test-key", Model::HAIKU_45)
+      .base_url(server.url())
+      .max_retries(1)
+      .retry_bounds(Duration::from_millis(1), Duration::from_millis(10))
+      .build();
+
+    let resp = client
+      .create_message(None, &hello(), &[], "".into(), &MessageOptions::default())
+      .await;
+
+    assert!(matches!(
+      resp,
+      Err(crate::claude::Error::APIError(
+        APIError::OverloadedError { .. },
+        _
+      ))
+    ));
+    // retried by the rate limiter only, not again by the 5xx retry policy.
+    assert_eq!(server.requests().len(), 2);
+    assert_eq!(client.rate_limits().throttled, 2);
+  }
+
+  #[tokio::test]
+  async fn test_create_message_gives_up_after_max_retries() {
+    let server = FakeServer::start(vec![Scripted::status(500); 5]).await;
+    let client = Client::builder("test-key", Model::HAIKU_45)
+      .base_url(server.url())
+      .max_retries(1)
+      .retry_bounds(Duration::from_millis(1), Duration::from_millis(10))
+      .build();
+
+    let resp = client
+      .create_message(None, &hello(), &[], "".into(), &MessageOptions::default())
+      .await;
+
+    let err = resp.unwrap_err();
+    assert!(matches!(err, crate::claude::Error::HttpError(..)));
+    assert_eq!(err.status(), Some(500));
+    assert!(err.is_retryable());
+    assert_eq!(server.requests().len(), 2);
+  }
+
+  #[tokio::test]
+  async fn test_count_tokens() {
+    let server = FakeServer::start(vec![Scripted::body(r#"{"input_tokens": 42}"#)]).await;
+
+    let tokens = server
+      .client()
+      .count_tokens(
+        None,
+        &hello(),
+        &[],
+        "prompt".into(),
+        &MessageOptions::default(),
+      )
+      .await
+      .unwrap();
+
+    assert_eq!(tokens, 42);
+    let req = &server.requests()[0];
+    assert_eq!(req.path, "/v1/messages/count_tokens");
+    assert_eq!(req.body["system"][0]["text"], "prompt");
+    assert!(req.body["max_tokens"].is_null());
+    assert!(req.body["tools"].is_null());
+  }
+
+  #[tokio::test]
+  async fn test_create_message_stream() {
+    let server = FakeServer::start(vec![Scripted::message(vec![Content::text("streamed")])]).await;
+    let mut seen = String::new();
+
+    let resp = server
+      .client()
+      .create_message_stream(
+        None,
+        &hello(),
+        &[],
+        "".into(),
+        &MessageOptions::default(),
+        |text| seen.push_str(text),
+      )
+      .await
+      .unwrap();
+
+    assert_eq!(seen, "streamed");
+    assert!(matches!(resp, Response::Message { .. }));
+    assert_eq!(server.requests()[0].body["stream"], true);
+  }
+
+  #[tokio::test]
+  async fn test_hung_requests_time_out_or_cancel() {
+    let server = FakeServer::start(vec![Scripted::Stall, Scripted::Stall]).await;
+    let client = Client::builder("test-key", Model::HAIKU_45)
+      .base_url(server.url())
+      .max_retries(0)
+      .read_timeout(Duration::from_millis(100))
+      .build();
+
+    let err = client
+      .create_message(None, &hello(), &[], "".into(), &MessageOptions::default())
+      .await
+      .unwrap_err();
+    assert!(matches!(err, crate::claude::Error::HttpError(..)));
+    assert!(err.is_retryable());
+
+    let token = CancellationToken::new();
+    let options = MessageOptions {
+      cancel: Some(token.clone()),
+      ..Default::default()
+    };
+    let cancel = async {
+      tokio::time::sleep(Duration::from_millis(10)).await;
+      token.cancel();
+    };
+    let client = server.client();
+    let messages = hello();
+    let (resp, _) = tokio::join!(
+      client.create_message_stream(None, &messages, &[], "".into(), &options, |_| {}),
+      cancel
+    );
+    assert!(matches!(resp, Err(crate::claude::Error::Cancelled)));
   }
 }
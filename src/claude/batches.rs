@@ -0,0 +1,256 @@
+//! Client for the Message Batches API.
+//! Batched requests are processed asynchronously, usually within the hour, at half the usual price.
+
+use super::api::{APIError, Request, Response};
+use super::error::parse;
+use super::{Client, Error};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+/// A single request within a batch. `custom_id` is echoed back with its result.
+#[derive(Serialize, Debug)]
+pub struct BatchRequest {
+  pub custom_id: String,
+  pub params: Request,
+}
+
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProcessingStatus {
+  InProgress,
+  Canceling,
+  Ended,
+}
+
+/// How many requests in a batch are in each state.
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct RequestCounts {
+  pub processing: usize,
+  pub succeeded: usize,
+  pub errored: usize,
+  pub canceled: usize,
+  pub expired: usize,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Batch {
+  pub id: String,
+  pub processing_status: ProcessingStatus,
+  pub request_counts: RequestCounts,
+  pub created_at: String,
+  pub expires_at: String,
+  pub ended_at: Option<String>,
+  pub results_url: Option<String>,
+}
+
+impl Batch {
+  /// Whether every request has finished and results can be fetched.
+  pub fn is_ended(&self) -> bool {
+    self.processing_status == ProcessingStatus::Ended
+  }
+}
+
+/// The error body returned for a request that failed within a batch.
+#[derive(Deserialize, Debug)]
+pub struct BatchError {
+  pub error: APIError,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum BatchOutcome {
+  Succeeded { message: Response },
+  Errored { error: BatchError },
+  Canceled,
+  Expired,
+}
+
+/// One line of a batch's results file.
+#[derive(Deserialize, Debug)]
+pub struct BatchResult {
+  pub custom_id: String,
+  pub result: BatchOutcome,
+}
+
+impl Client {
+  /// Submits a batch of requests for asynchronous processing.
+  pub async fn create_batch(&self, requests: Vec<BatchRequest>) -> Result<Batch, Error> {
+    let body = json!({ "requests": requests }).to_string();
+    let resp = self
+      .call(reqwest::Method::POST, "/v1/messages/batches", Some(body))
+      .await?;
+
+    Self::decode(resp).await
+  }
+
+  /// Fetches the current status of a batch.
+  pub async fn batch(&self, id: &str) -> Result<Batch, Error> {
+    let path = format!("/v1/messages/batches/{}", id);
+    let resp = self.call(reqwest::Method::GET, &path, None).await?;
+
+    Self::decode(resp).await
+  }
+
+  /// Streams the results of an ended batch, invoking `on_result` once per request.
+  /// Results are not returned in the order the requests were submitted; match them up by `custom_id`.
+  pub async fn batch_results<F: FnMut(BatchResult)>(
+    &self,
+    id: &str,
+    mut on_result: F,
+  ) -> Result<(), Error> {
+    let path = format!("/v1/messages/batches/{}/results", id);
+    let mut resp = self.call(reqwest::Method::GET, &path, None).await?;
+    let mut buf = vec![];
+
+    while let Some(chunk) = resp
+      .chunk()
+      .await
+      .map_err(reqwest_middleware::Error::Reqwest)?
+    {
+      buf.extend_from_slice(&chunk);
+
+      while let Some(pos) = buf.iter().position(|&b| b == b'\n') {
+        let line = buf.drain(..=pos).collect::<Vec<_>>();
+        if !line.trim_ascii().is_empty() {
+          on_result(serde_json::from_slice(&line)?);
+        }
+      }
+    }
+
+    // the last line may not have a trailing newline.
+    if !buf.trim_ascii().is_empty() {
+      on_result(serde_json::from_slice(&buf)?);
+    }
+
+    Ok(())
+  }
+
+  async fn decode(resp: reqwest::Response) -> Result<Batch, Error> {
+    let body = resp
+      .text()
+      .await
+      .map_err(reqwest_middleware::Error::Reqwest)?;
+
+    parse(&body)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::claude::testing::{FakeServer, Scripted};
+  use crate::claude::{Content, Interaction, MessageOptions, Role};
+
+  fn batch(status: &str) -> String {
+    json!({
+      "id": "msgbatch_1",
+      "type": "message_batch",
+      "processing_status": status,
+      "request_counts": { "processing": 0, "succeeded": 1, "errored": 1, "canceled": 0, "expired": 0 },
+      "created_at": "2025-10-01T00:00:00Z",
+      "expires_at": "2025-10-02T00:00:00Z",
+      "ended_at": null,
+      "results_url": null,
+    })
+    .to_string()
+  }
+
+  #[tokio::test]
+  async fn test_create_and_poll_batch() {
+    let server = FakeServer::start(vec![
+      Scripted::body(batch("in_progress")),
+      Scripted::body(batch("ended")),
+    ])
+    .await;
+    let client = server.client();
+    let params = client.batch_request(
+      None,
+      &[Interaction {
+        role: Role::User,
+        content: vec![Content::text("Someone: hello")],
+      }],
+      &[],
+      String::new(),
+      &MessageOptions::default(),
+    );
+
+    let created = client
+      .create_batch(vec![BatchRequest {
+        custom_id: "job-0".into(),
+        params,
+      }])
+      .await
+      .unwrap();
+    assert!(!created.is_ended());
+
+    let polled = client.batch(&created.id).await.unwrap();
+    assert!(polled.is_ended());
+    assert_eq!(polled.request_counts.errored, 1);
+
+    let requests = server.requests();
+    assert_eq!(requests[0].method, "POST");
+    assert_eq!(requests[0].path, "/v1/messages/batches");
+    assert_eq!(requests[0].body["requests"][0]["custom_id"], "job-0");
+    assert_eq!(
+      requests[0].body["requests"][0]["params"]["model"],
+      "claude-haiku-4-5-20251001"
+    );
+    assert!(requests[0].body["requests"][0]["params"]["stream"].is_null());
+    assert_eq!(requests[1].method, "GET");
+    assert_eq!(requests[1].path, "/v1/messages/batches/msgbatch_1");
+  }
+
+  #[tokio::test]
+  async fn test_batch_results() {
+    let lines = [
+      json!({
+        "custom_id": "job-1",
+        "result": { "type": "succeeded", "message": {
+          "id": "msg_1", "type": "message", "role": "assistant", "model": "claude-haiku-4-5-20251001",
+          "content": [{ "type": "text", "text": "a digest" }],
+          "stop_reason": "end_turn", "stop_sequence": null,
+          "usage": { "input_tokens": 10, "output_tokens": 20 },
+        }},
+      }),
+      json!({
+        "custom_id": "job-0",
+        "result": { "type": "errored", "error": { "type": "error", "error": {
+          "type": "invalid_request_error", "message": "bad request",
+        }}},
+      }),
+      json!({ "custom_id": "job-2", "result": { "type": "expired" } }),
+    ];
+    let body = lines.iter().map(|l| l.to_string()).collect::<Vec<_>>();
+    let server = FakeServer::start(vec![Scripted::body(body.join("\n"))]).await;
+
+    let mut results = vec![];
+    server
+      .client()
+      .batch_results("msgbatch_1", |result| results.push(result))
+      .await
+      .unwrap();
+
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0].custom_id, "job-1");
+    assert!(matches!(
+      &results[0].result,
+      BatchOutcome::Succeeded {
+        message: Response::Message { content, .. }
+      } if content == &vec![Content::text("a digest")]
+    ));
+    assert!(matches!(
+      &results[1].result,
+      BatchOutcome::Errored {
+        error: BatchError {
+          error: APIError::InvalidRequestError { .. }
+        }
+      }
+    ));
+    assert!(matches!(results[2].result, BatchOutcome::Expired));
+    assert_eq!(
+      server.requests()[0].path,
+      "/v1/messages/batches/msgbatch_1/results"
+    );
+  }
+}
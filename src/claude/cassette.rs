@@ -0,0 +1,317 @@
+//! Record/replay of HTTP exchanges, so tests can run against real API responses offline.
+//! A cassette is a fixture file in `fixtures/cassettes`. When replaying, its responses are
+//! served in order and nothing leaves the process. When recording, requests go out to the
+//! API and each exchange is saved, minus request headers and any secrets it was given.
+//!
+//! Re-record a cassette by running its test with `RECORD_CASSETTES=1` and `CLAUDE_KEY` set.
+
+use super::{Client, Model};
+use http::Extensions;
+use reqwest::{Request, Response};
+use reqwest_middleware::{Error, Middleware, Next, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Stands in for anything secret in a recording.
+const REDACTED: &str = "<redacted>";
+
+/// Response headers worth keeping. Everything else is left out of recordings.
+const KEPT_HEADERS: &[&str] = &["content-type", "request-id", "retry-after"];
+const KEPT_HEADER_PREFIX: &str = "anthropic-ratelimit-";
+
+/// One request and the response it got.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Exchange {
+  pub request: RecordedRequest,
+  pub response: RecordedResponse,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RecordedRequest {
+  pub method: String,
+  /// Path and query, without the host.
+  pub path: String,
+  /// The body parsed as JSON, or `Null` if it isn't JSON.
+  pub body: Value,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RecordedResponse {
+  pub status: u16,
+  pub headers: BTreeMap<String, String>,
+  pub body: String,
+}
+
+#[derive(Default)]
+struct Tape {
+  /// Exchanges still to be replayed.
+  pending: VecDeque<Exchange>,
+  /// Exchanges that have been replayed or recorded.
+  played: Vec<Exchange>,
+}
+
+/// Middleware that replays or records a cassette. Clones share the same tape, so a test
+/// can keep one to inspect the requests after handing another to a `Client`.
+#[derive(Clone)]
+pub struct Cassette {
+  path: PathBuf,
+  recording: bool,
+  /// Replaced with `REDACTED` wherever they show up in a recording.
+  secrets: Vec<String>,
+  tape: Arc<Mutex<Tape>>,
+}
+
+impl Cassette {
+  /// Replays the exchanges saved at `path`.
+  pub fn replay<P: AsRef<Path>>(path: P) -> Self {
+    let path = path.as_ref().to_owned();
+    let json = std::fs::read_to_string(&path)
+      .unwrap_or_else(|e| panic!("failed to read cassette {}: {}", path.display(), e));
+    let exchanges: Vec<Exchange> = serde_json::from_str(&json)
+      .unwrap_or_else(|e| panic!("failed to parse cassette {}: {}", path.display(), e));
+
+    Self {
+      path,
+      recording: false,
+      secrets: vec![],
+      tape: Arc::new(Mutex::new(Tape {
+        pending: exchanges.into(),
+        played: vec![],
+      })),
+    }
+  }
+
+  /// Sends requests on to the network, saving each exchange to `path`.
+  pub fn record<P: AsRef<Path>>(path: P) -> Self {
+    Self {
+      path: path.as_ref().to_owned(),
+      recording: true,
+      secrets: vec![],
+      tape: Arc::new(Mutex::new(Tape::default())),
+    }
+  }
+
+  /// Keeps `secret`, such as an API key, out of the recording.
+  pub fn redacting<S: Into<String>>(mut self, secret: S) -> Self {
+    self.secrets.push(secret.into());
+    self
+  }
+
+  /// The requests seen so far, in order.
+  pub fn requests(&self) -> Vec<RecordedRequest> {
+    let tape = self.tape.lock().unwrap();
+    tape.played.iter().map(|e| e.request.clone()).collect()
+  }
+
+  fn next_response(&self, request: RecordedRequest) -> Result<Response> {
+    let mut tape = self.tape.lock().unwrap();
+    let Some(exchange) = tape.pending.pop_front() else {
+      return Err(Error::Middleware(anyhow::anyhow!(
+        "cassette {} has no response left for {} {}",
+        self.path.display(),
+        request.method,
+        request.path
+      )));
+    };
+
+    let expected = &exchange.request;
+    if (expected.method.as_str(), expected.path.as_str())
+      != (request.method.as_str(), request.path.as_str())
+    {
+      return Err(Error::Middleware(anyhow::anyhow!(
+        "cassette {} expected {} {}, got {} {}",
+        self.path.display(),
+        expected.method,
+        expected.path,
+        request.method,
+        request.path
+      )));
+    }
+
+    let response = to_response(&exchange.response)?;
+    tape.played.push(Exchange {
+      request,
+      response: exchange.response,
+    });
+    Ok(response)
+  }
+
+  async fn record_exchange(
+    &self,
+    req: Request,
+    extensions: &mut Extensions,
+    next: Next<'_>,
+    request: RecordedRequest,
+  ) -> Result<Response> {
+    let resp = next.run(req, extensions).await?;
+
+    let status = resp.status().as_u16();
+    let headers = resp
+      .headers()
+      .iter()
+      .filter(|(name, _)| {
+        KEPT_HEADERS.contains(&name.as_str()) || name.as_str().starts_with(KEPT_HEADER_PREFIX)
+      })
+      .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_owned())))
+      .collect();
+    let body = resp.text().await.map_err(Error::Reqwest)?;
+    let response = RecordedResponse {
+      status,
+      headers,
+      body,
+    };
+    let replayed = to_response(&response)?;
+
+    let mut tape = self.tape.lock().unwrap();
+    tape.played.push(Exchange { request, response });
+    // saved after every exchange, so a test that fails halfway still leaves a usable recording.
+    let json = scrub(
+      &serde_json::to_string_pretty(&tape.played).unwrap(),
+      &self.secrets,
+    );
+    if let Some(dir) = self.path.parent() {
+      std::fs::create_dir_all(dir).ok();
+    }
+    std::fs::write(&self.path, json + "\n").map_err(|e| Error::Middleware(e.into()))?;
+
+    Ok(replayed)
+  }
+}
+
+#[async_trait::async_trait]
+impl Middleware for Cassette {
+  async fn handle(
+    &self,
+    req: Request,
+    extensions: &mut Extensions,
+    next: Next<'_>,
+  ) -> Result<Response> {
+    let url = req.url();
+    let path = match url.query() {
+      Some(query) => format!("{}?{}", url.path(), query),
+      None => url.path().to_owned(),
+    };
+    let request = RecordedRequest {
+      method: req.method().to_string(),
+      path,
+      body: req
+        .body()
+        .and_then(|body| body.as_bytes())
+        .and_then(|bytes| serde_json::from_slice(bytes).ok())
+        .unwrap_or(Value::Null),
+    };
+
+    if self.recording {
+      self.record_exchange(req, extensions, next, request).await
+    } else {
+      self.next_response(request)
+    }
+  }
+}
+
+fn scrub(text: &str, secrets: &[String]) -> String {
+  secrets.iter().fold(text.to_owned(), |text, secret| {
+    text.replace(secret, REDACTED)
+  })
+}
+
+fn to_response(recorded: &RecordedResponse) -> Result<Response> {
+  let mut builder = http::Response::builder().status(recorded.status);
+  for (name, value) in &recorded.headers {
+    builder = builder.header(name, value);
+  }
+  let response = builder
+    .body(recorded.body.clone())
+    .map_err(|e| Error::Middleware(e.into()))?;
+
+  Ok(Response::from(response))
+}
+
+/// The fixture file for the cassette called `name`.
+fn fixture(name: &str) -> PathBuf {
+  Path::new(env!("CARGO_MANIFEST_DIR"))
+    .join("fixtures/cassettes")
+    .join(format!("{}.json", name))
+}
+
+/// A client that replays the cassette called `name`, or records it against the real API
+/// when `RECORD_CASSETTES` is set. Keep the returned cassette to inspect the requests.
+pub fn client(name: &str) -> (Client, Cassette) {
+  let path = fixture(name);
+  let (builder, cassette) = match std::env::var("RECORD_CASSETTES") {
+    Ok(_) => {
+      let key = std::env::var("CLAUDE_KEY").expect("recording a cassette needs CLAUDE_KEY");
+      let cassette = Cassette::record(path).redacting(&key);
+      (Client::builder(key, Model::HAIKU_45), cassette)
+    }
+    // replayed requests never reach the network, so the URL and key don't matter.
+    Err(_) => (
+      Client::builder("test-key", Model::HAIKU_45).base_url("http://cassette.invalid"),
+      Cassette::replay(path),
+    ),
+  };
+
+  let client = builder
+    .retry_bounds(Duration::from_millis(1), Duration::from_millis(10))
+    .middleware(cassette.clone())
+    .build();
+  (client, cassette)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::claude::{Content, MessageOptions, Response as Message};
+
+  #[test]
+  fn test_scrub() {
+    assert_eq!(
+      scrub(
+        r#"{"error": "bad key sk-ant-secret"}"#,
+        &["sk-ant-secret".into()]
+      ),
+      r#"{"error": "bad key <redacted>"}"#
+    );
+  }
+
+  #[tokio::test]
+  async fn test_replay() {
+    let (client, cassette) = client("hello");
+    let resp = client
+      .create_message(
+        None,
+        &[crate::claude::Interaction {
+          role: crate::claude::Role::User,
+          content: vec![Content::text("Say hello")],
+        }],
+        &[],
+        String::new(),
+        &MessageOptions::default(),
+      )
+      .await
+      .unwrap();
+
+    let Message::Message { content, .. } = resp else {
+      panic!("expected a message");
+    };
+    assert!(matches!(&content[0], Content::Text { text, .. } if text == "Hello!"));
+
+    let requests = cassette.requests();
+    assert_eq!(requests[0].path, "/v1/messages");
+    assert_eq!(
+      requests[0].body["messages"][0]["content"][0]["text"],
+      "Say hello"
+    );
+
+    // the cassette has run out, so another request fails rather than going to the network.
+    let err = client
+      .count_tokens(None, &[], &[], String::new(), &MessageOptions::default())
+      .await
+      .unwrap_err();
+    assert!(err.to_string().contains("no response left"));
+  }
+}
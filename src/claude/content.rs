@@ -1,13 +1,68 @@
+use base64::prelude::*;
 use serde::{Deserialize, Serialize};
 
+/// Where a piece of cited text came from: a web search result, or a span of a document
+/// from the request. Document indexes count the documents in the request, in order.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
-#[serde(rename_all = "snake_case")]
-pub struct Citation {
-  pub r#type: String,
-  pub url: String,
-  pub title: String,
-  pub encrypted_index: String,
-  pub cited_text: String,
+#[serde(tag = "type")]
+pub enum Citation {
+  #[serde(rename = "web_search_result_location")]
+  WebSearchResult {
+    url: String,
+    title: String,
+    encrypted_index: String,
+    cited_text: String,
+  },
+  /// Pages of a PDF, numbered from 1. The end page is exclusive.
+  #[serde(rename = "page_location")]
+  Page {
+    cited_text: String,
+    document_index: usize,
+    document_title: Option<String>,
+    start_page_number: usize,
+    end_page_number: usize,
+  },
+  /// Characters of a plain text document. The end index is exclusive.
+  #[serde(rename = "char_location")]
+  Char {
+    cited_text: String,
+    document_index: usize,
+    document_title: Option<String>,
+    start_char_index: usize,
+    end_char_index: usize,
+  },
+}
+
+impl Citation {
+  /// The text that was quoted from the source.
+  pub fn cited_text(&self) -> &str {
+    match self {
+      Self::WebSearchResult { cited_text, .. }
+      | Self::Page { cited_text, .. }
+      | Self::Char { cited_text, .. } => cited_text,
+    }
+  }
+}
+
+/// Turns on citations for a document.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CitationsConfig {
+  pub enabled: bool,
+}
+
+/// Marks the end of a prompt prefix that the API should cache.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CacheControl {
+  r#type: String,
+}
+
+impl CacheControl {
+  /// The default (five minute) cache lifetime.
+  pub fn ephemeral() -> Self {
+    Self {
+      r#type: "ephemeral".into(),
+    }
+  }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
@@ -17,25 +72,51 @@ pub enum Content {
   Text {
     text: String,
     citations: Option<Vec<Citation>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cache_control: Option<CacheControl>,
   },
   Image {
     source: ImageSource,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cache_control: Option<CacheControl>,
+  },
+  Document {
+    source: DocumentSource,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    citations: Option<CitationsConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cache_control: Option<CacheControl>,
   },
   ToolResult {
     tool_use_id: String,
-    content: String,
+    /// Text, image and document blocks.
+    content: Vec<Content>,
     is_error: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cache_control: Option<CacheControl>,
   },
   ToolUse {
     id: String,
     name: String,
     input: serde_json::Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cache_control: Option<CacheControl>,
   },
   ServerToolUse {
     id: String,
     name: String,
     input: serde_json::Value,
   },
+  Thinking {
+    thinking: String,
+    #[serde(default)]
+    signature: String,
+  },
+  RedactedThinking {
+    data: String,
+  },
   WebSearchToolResult {
     tool_use_id: String,
     content: serde_json::Value,
@@ -50,7 +131,47 @@ pub enum Content {
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
 pub enum ImageSource {
-  Base64 { media_type: String, data: String },
+  Base64 {
+    media_type: String,
+    data: String,
+  },
+  /// An image uploaded with the Files API.
+  File {
+    file_id: String,
+  },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum DocumentSource {
+  /// A base64 encoded PDF.
+  Base64 {
+    media_type: String,
+    data: String,
+    /// Counted once when the document is made, for token estimates. Unknown if it was deserialized.
+    #[serde(skip)]
+    pages: Option<usize>,
+  },
+  /// Plain text, which the API splits into sentences for citations.
+  Text { media_type: String, data: String },
+}
+
+impl DocumentSource {
+  pub fn pdf(bytes: &[u8]) -> Self {
+    Self::Base64 {
+      media_type: "application/pdf".into(),
+      data: BASE64_STANDARD.encode(bytes),
+      pages: Some(super::tokens::count_pdf_pages(bytes)),
+    }
+  }
+
+  pub fn text<S: Into<String>>(data: S) -> Self {
+    Self::Text {
+      media_type: "text/plain".into(),
+      data: data.into(),
+    }
+  }
 }
 
 impl Content {
@@ -61,10 +182,75 @@ impl Content {
     }
   }
 
+  pub fn is_tool_result(&self) -> bool {
+    matches!(self, Self::ToolResult { .. })
+  }
+
   pub fn text<S: Into<String>>(text: S) -> Self {
     Self::Text {
       text: text.into(),
       citations: None,
+      cache_control: None,
+    }
+  }
+
+  pub fn image(source: ImageSource) -> Self {
+    Self::Image {
+      source,
+      cache_control: None,
+    }
+  }
+
+  /// A document with citations enabled, so answers can point back into it.
+  pub fn document<S: Into<String>>(source: DocumentSource, title: S) -> Self {
+    Self::Document {
+      source,
+      title: Some(title.into()),
+      citations: Some(CitationsConfig { enabled: true }),
+      cache_control: None,
+    }
+  }
+
+  pub fn tool_use<S: Into<String>>(id: S, name: S, input: serde_json::Value) -> Self {
+    Self::ToolUse {
+      id: id.into(),
+      name: name.into(),
+      input,
+      cache_control: None,
+    }
+  }
+
+  pub fn tool_result<S: Into<String>>(
+    tool_use_id: S,
+    content: Vec<Content>,
+    is_error: bool,
+  ) -> Self {
+    Self::ToolResult {
+      tool_use_id: tool_use_id.into(),
+      content,
+      is_error,
+      cache_control: None,
+    }
+  }
+
+  /// A tool result reporting that the tool failed.
+  pub fn tool_error<S: Into<String>>(tool_use_id: S, message: S) -> Self {
+    Self::tool_result(tool_use_id, vec![Content::text(message)], true)
+  }
+
+  /// Sets a cache breakpoint on this block.
+  /// Returns false if the block type can't carry one.
+  pub fn set_cache_control(&mut self, control: CacheControl) -> bool {
+    match self {
+      Self::Text { cache_control, .. }
+      | Self::Image { cache_control, .. }
+      | Self::Document { cache_control, .. }
+      | Self::ToolResult { cache_control, .. }
+      | Self::ToolUse { cache_control, .. } => {
+        *cache_control = Some(control);
+        true
+      }
+      _ => false,
     }
   }
 }
@@ -1,17 +1,155 @@
+use super::api::APIError;
+use serde::de::DeserializeOwned;
 use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::io;
 
+/// What we know about the HTTP exchange that produced an error.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Details {
+  pub status: Option<u16>,
+  /// The `request-id` header, which Anthropic support can use to look up the request.
+  pub request_id: Option<String>,
+  /// The raw response body, kept when it couldn't be understood.
+  pub body: Option<String>,
+}
+
 #[derive(Debug)]
 pub enum Error {
   IoError(io::Error),
-  HttpError(reqwest_middleware::Error),
-  JsonError(serde_json::Error),
-  APIError(super::api::APIError),
+  HttpError(reqwest_middleware::Error, Details),
+  JsonError(serde_json::Error, Details),
+  APIError(APIError, Details),
+  StreamError(String),
+  /// The call was cancelled before it finished.
+  Cancelled,
+}
+
+/// How an error should be handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+  /// A temporary problem; the same request may well succeed later.
+  Retryable,
+  /// Something about the request itself, such as an unreadable image or a conversation that
+  /// has grown too long. Retrying won't help, but changing the conversation might.
+  UserFixable,
+  /// A problem with how the bot is set up, such as a rejected API key.
+  Fatal,
+}
+
+impl Error {
+  pub fn details(&self) -> Option<&Details> {
+    match self {
+      Self::HttpError(_, details) | Self::JsonError(_, details) | Self::APIError(_, details) => {
+        Some(details)
+      }
+      Self::IoError(_) | Self::StreamError(_) | Self::Cancelled => None,
+    }
+  }
+
+  /// The HTTP status of the failed response, if there was one.
+  pub fn status(&self) -> Option<u16> {
+    self.details().and_then(|d| d.status)
+  }
+
+  pub fn request_id(&self) -> Option<&str> {
+    self.details().and_then(|d| d.request_id.as_deref())
+  }
+
+  pub fn body(&self) -> Option<&str> {
+    self.details().and_then(|d| d.body.as_deref())
+  }
+
+  pub fn kind(&self) -> ErrorKind {
+    match self {
+      Self::APIError(error, _) => match error {
+        APIError::RateLimitError { .. }
+        | APIError::OverloadedError { .. }
+        | APIError::ApiError { .. } => ErrorKind::Retryable,
+        APIError::InvalidRequestError { .. } => ErrorKind::UserFixable,
+        APIError::AuthenticationError { .. }
+        | APIError::PermissionError { .. }
+        | APIError::NotFoundError { .. } => ErrorKind::Fatal,
+      },
+      Self::HttpError(error, _) => match self.status() {
+        Some(status) if status >= 500 || status == 408 || status == 429 => ErrorKind::Retryable,
+        Some(401 | 403) => ErrorKind::Fatal,
+        Some(_) => ErrorKind::UserFixable,
+        None if error.is_timeout() || error.is_connect() => ErrorKind::Retryable,
+        None => ErrorKind::Fatal,
+      },
+      // a response we can't parse won't get any better by asking again.
+      Self::JsonError(..) => ErrorKind::Fatal,
+      Self::IoError(_) | Self::StreamError(_) | Self::Cancelled => ErrorKind::Retryable,
+    }
+  }
+
+  pub fn is_retryable(&self) -> bool {
+    self.kind() == ErrorKind::Retryable
+  }
+
+  /// A short explanation suitable for showing in chat.
+  pub fn user_message(&self) -> String {
+    let message = match self {
+      Self::APIError(APIError::InvalidRequestError { message }, _) => {
+        let lower = message.to_lowercase();
+        if lower.contains("prompt is too long") || lower.contains("too many tokens") {
+          "This conversation has gotten too long for me. Try `forget-history` and ask again.".into()
+        } else if lower.contains("image") {
+          "I couldn't read one of the images in this conversation. Try `forget-history` and send it in another format.".into()
+        } else if lower.contains("pdf") || lower.contains("document") {
+          "I couldn't read one of the documents in this conversation. Try `forget-history` and send it as plain text.".into()
+        } else {
+          format!("Claude didn't accept that request: {}", message)
+        }
+      }
+      Self::APIError(APIError::RateLimitError { .. }, _) => {
+        "I'm getting too many requests right now. Try again in a minute.".into()
+      }
+      Self::APIError(APIError::OverloadedError { .. }, _) => {
+        "Claude is overloaded right now. Try again in a minute.".into()
+      }
+      Self::Cancelled => "I stopped before I finished that one.".into(),
+      Self::HttpError(error, _) if error.is_timeout() => {
+        "Claude took too long to answer. Try again in a bit.".into()
+      }
+      _ => match self.kind() {
+        ErrorKind::Fatal => {
+          "I can't reach Claude because of a problem with how I'm set up. Let an admin know.".into()
+        }
+        _ => "Something went wrong while talking to Claude. Try again in a bit.".into(),
+      },
+    };
+
+    match self.request_id() {
+      Some(id) => format!("{} (request `{}`)", message, id),
+      None => message,
+    }
+  }
+}
+
+/// Parses a response body, keeping the body around if it isn't what we expected.
+pub(super) fn parse<T: DeserializeOwned>(body: &str) -> Result<T, Error> {
+  serde_json::from_str(body).map_err(|e| {
+    Error::JsonError(
+      e,
+      Details {
+        body: Some(body.to_owned()),
+        ..Default::default()
+      },
+    )
+  })
 }
 
 impl From<reqwest_middleware::Error> for Error {
   fn from(value: reqwest_middleware::Error) -> Self {
-    Self::HttpError(value)
+    let status = value.status().map(|s| s.as_u16());
+    Self::HttpError(
+      value,
+      Details {
+        status,
+        ..Default::default()
+      },
+    )
   }
 }
 
@@ -23,13 +161,13 @@ impl From<io::Error> for Error {
 
 impl From<serde_json::Error> for Error {
   fn from(value: serde_json::Error) -> Self {
-    Self::JsonError(value)
+    Self::JsonError(value, Details::default())
   }
 }
 
-impl From<super::api::APIError> for Error {
-  fn from(value: super::api::APIError) -> Self {
-    Self::APIError(value)
+impl From<APIError> for Error {
+  fn from(value: APIError) -> Self {
+    Self::APIError(value, Details::default())
   }
 }
 
@@ -37,11 +175,64 @@ impl Display for Error {
   fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
     match self {
       Self::IoError(e) => write!(f, "IO Error: {}", e),
-      Self::HttpError(e) => write!(f, "HTTP Error: {}", e),
-      Self::JsonError(e) => write!(f, "JSON Error: {}", e),
-      Self::APIError(e) => write!(f, "API Error: {}", e),
+      Self::HttpError(e, _) => write!(f, "HTTP Error: {}", e),
+      Self::JsonError(e, _) => write!(f, "JSON Error: {}", e),
+      Self::APIError(e, _) => write!(f, "API Error: {}", e),
+      Self::StreamError(e) => write!(f, "Stream Error: {}", e),
+      Self::Cancelled => write!(f, "Cancelled"),
+    }?;
+
+    if let Some(status) = self.status() {
+      write!(f, " (status {})", status)?;
     }
+    if let Some(id) = self.request_id() {
+      write!(f, " (request {})", id)?;
+    }
+    Ok(())
   }
 }
 
 impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn invalid(message: &str) -> Error {
+    Error::APIError(
+      APIError::InvalidRequestError {
+        message: message.into(),
+      },
+      Details {
+        status: Some(400),
+        request_id: Some("req_1".into()),
+        body: None,
+      },
+    )
+  }
+
+  #[test]
+  fn test_classification() {
+    let too_long = invalid("prompt is too long: 210000 tokens > 200000 maximum");
+    assert_eq!(too_long.kind(), ErrorKind::UserFixable);
+    assert_eq!(
+      too_long.user_message(),
+      "This conversation has gotten too long for me. Try `forget-history` and ask again. (request `req_1`)"
+    );
+    assert!(
+      invalid("Could not process image")
+        .user_message()
+        .starts_with("I couldn't read one of the images")
+    );
+
+    let auth = Error::from(APIError::AuthenticationError {
+      message: "invalid x-api-key".into(),
+    });
+    assert_eq!(auth.kind(), ErrorKind::Fatal);
+    assert!(auth.user_message().contains("Let an admin know"));
+
+    let garbled = parse::<serde_json::Value>("<html>Bad Gateway</html>").unwrap_err();
+    assert_eq!(garbled.kind(), ErrorKind::Fatal);
+    assert_eq!(garbled.body(), Some("<html>Bad Gateway</html>"));
+  }
+}
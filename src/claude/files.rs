@@ -0,0 +1,118 @@
+//! Client for the Files API.
+//! Uploaded files are referred to by ID in later requests, so large attachments are only sent once.
+
+use super::error::parse;
+use super::{Client, Error};
+use serde::Deserialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A file stored with the Files API.
+#[derive(Deserialize, Clone, Debug)]
+pub struct FileMetadata {
+  pub id: String,
+  pub filename: String,
+  pub mime_type: String,
+  pub size_bytes: u64,
+  pub created_at: String,
+}
+
+impl Client {
+  /// Uploads a file, returning its metadata. Refer to it by `id` in later requests.
+  pub async fn upload_file(
+    &self,
+    filename: &str,
+    mime_type: &str,
+    bytes: &[u8],
+  ) -> Result<FileMetadata, Error> {
+    let boundary = format!(
+      "scrubby-{}",
+      SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+    );
+    // quotes and line breaks would end the header early.
+    let filename = filename.replace(['"', '\r', '\n'], "_");
+
+    let mut body = format!(
+      "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\nContent-Type: {}\r\n\r\n",
+      boundary, filename, mime_type
+    )
+    .into_bytes();
+    body.extend_from_slice(bytes);
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+
+    let content_type = format!("multipart/form-data; boundary={}", boundary);
+    let resp = self
+      .call_with_body(
+        reqwest::Method::POST,
+        "/v1/files",
+        Some((content_type, body)),
+      )
+      .await?;
+
+    Self::decode_file(resp).await
+  }
+
+  /// Deletes an uploaded file. Requests that still refer to it will fail.
+  pub async fn delete_file(&self, id: &str) -> Result<(), Error> {
+    let path = format!("/v1/files/{}", id);
+    self.call(reqwest::Method::DELETE, &path, None).await?;
+
+    Ok(())
+  }
+
+  async fn decode_file(resp: reqwest::Response) -> Result<FileMetadata, Error> {
+    let body = resp
+      .text()
+      .await
+      .map_err(reqwest_middleware::Error::Reqwest)?;
+
+    parse(&body)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::claude::testing::{FakeServer, Scripted};
+  use serde_json::json;
+
+  #[tokio::test]
+  async fn test_upload_and_delete_file() {
+    let metadata = json!({
+      "type": "file",
+      "id": "file_1",
+      "filename": "cat.png",
+      "mime_type": "image/png",
+      "size_bytes": 4,
+      "created_at": "2025-10-01T00:00:00Z",
+      "downloadable": false,
+    });
+    let server = FakeServer::start(vec![
+      Scripted::body(metadata.to_string()),
+      Scripted::body(json!({ "id": "file_1", "type": "file_deleted" }).to_string()),
+    ])
+    .await;
+    let client = server.client();
+
+    let file = client
+      .upload_file("cat.png", "image/png", b"\x89PNG")
+      .await
+      .unwrap();
+    assert_eq!(file.id, "file_1");
+    client.delete_file(&file.id).await.unwrap();
+
+    let requests = server.requests();
+    assert_eq!(requests[0].method, "POST");
+    assert_eq!(requests[0].path, "/v1/files");
+    assert!(
+      requests[0].headers["content-type"].starts_with("multipart/form-data; boundary=scrubby-")
+    );
+    assert!(requests[0].headers["anthropic-beta"].contains("files-api-2025-04-14"));
+    let raw = String::from_utf8_lossy(&requests[0].raw);
+    assert!(raw.contains("name=\"file\"; filename=\"cat.png\"\r\nContent-Type: image/png\r\n\r\n"));
+    assert!(raw.contains("\u{FFFD}PNG\r\n--scrubby-"));
+    assert_eq!(requests[1].method, "DELETE");
+    assert_eq!(requests[1].path, "/v1/files/file_1");
+  }
+}
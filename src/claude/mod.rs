@@ -1,13 +1,32 @@
 mod api;
+mod batches;
 mod content;
 mod error;
+mod files;
+mod models;
+mod openai;
+mod provider;
+mod ratelimit;
 mod retry;
 mod schema;
+mod stream;
 
+#[cfg(test)]
+pub mod cassette;
+#[cfg(test)]
+pub mod testing;
+pub mod tokens;
 pub mod tools;
 pub mod util;
 
-pub use api::{Client, Interaction, Model, Response, Role, Tool};
-pub use content::{Content, ImageSource};
+pub use api::{
+  Client, Interaction, MessageOptions, Request, Response, Role, Tool, ToolChoice, Usage,
+};
+pub use batches::{BatchOutcome, BatchRequest};
+pub use content::{Citation, Content, DocumentSource, ImageSource};
 pub use error::Error;
-pub use schema::Schema;
+pub use models::{Model, Registry};
+pub use openai::OpenAIClient;
+pub use provider::Provider;
+pub use ratelimit::RateLimitStatus;
+pub use schema::{JsonSchema, Schema};
@@ -0,0 +1,353 @@
+//! Model IDs, what each model can do, and the registry of models the API offers.
+//! New models show up in the `/v1/models` listing without a code change; the local
+//! capability table only needs an entry when their pricing or limits differ from the fallback.
+
+use super::error::parse;
+use super::{Client, Error, Usage};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fmt::Display;
+
+/// A model ID as sent to the API, e.g. `claude-haiku-4-5-20251001`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+#[serde(transparent)]
+pub struct Model(Cow<'static, str>);
+
+impl Model {
+  pub const HAIKU_3: Model = Model::from_static("claude-3-haiku-20240307");
+  pub const HAIKU_35: Model = Model::from_static("claude-3-5-haiku-latest");
+  pub const HAIKU_45: Model = Model::from_static("claude-haiku-4-5-20251001");
+  pub const SONNET_37: Model = Model::from_static("claude-3-7-sonnet-latest");
+  pub const SONNET_4: Model = Model::from_static("claude-sonnet-4-20250514");
+  pub const SONNET_45: Model = Model::from_static("claude-sonnet-4-5-20250929");
+
+  pub const fn from_static(id: &'static str) -> Self {
+    Self(Cow::Borrowed(id))
+  }
+
+  pub fn new<S: Into<String>>(id: S) -> Self {
+    Self(Cow::Owned(id.into()))
+  }
+
+  /// The model ID sent to the API.
+  pub fn id(&self) -> &str {
+    &self.0
+  }
+
+  /// What the model can do and what it costs, from the local capability table.
+  pub fn capabilities(&self) -> Capabilities {
+    Capabilities::for_id(self.id())
+  }
+
+  /// Whether the model supports extended thinking.
+  pub fn supports_thinking(&self) -> bool {
+    self.capabilities().thinking
+  }
+
+  /// Total tokens (input and output) the model can attend to in a single request.
+  pub fn context_window(&self) -> usize {
+    self.capabilities().context_window
+  }
+
+  /// Price in USD per million (input, output) tokens.
+  pub fn pricing(&self) -> (f64, f64) {
+    self.capabilities().pricing
+  }
+
+  /// Cost in USD of a single call with the given usage.
+  /// Cache writes cost 25% more than regular input tokens, and cache reads 90% less.
+  pub fn cost(&self, usage: &Usage) -> f64 {
+    let (input, output) = self.pricing();
+    let written = usage.cache_creation_input_tokens.unwrap_or(0) as f64;
+    let read = usage.cache_read_input_tokens.unwrap_or(0) as f64;
+
+    (usage.input_tokens as f64 * input
+      + written * input * 1.25
+      + read * input * 0.1
+      + usage.output_tokens as f64 * output)
+      / 1_000_000.0
+  }
+}
+
+impl Display for Model {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}", self.0)
+  }
+}
+
+/// What a model can do and what it costs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Capabilities {
+  pub vision: bool,
+  pub tools: bool,
+  pub thinking: bool,
+  pub context_window: usize,
+  pub max_output: usize,
+  /// Price in USD per million (input, output) tokens.
+  pub pricing: (f64, f64),
+}
+
+const fn caps(
+  vision: bool,
+  thinking: bool,
+  max_output: usize,
+  pricing: (f64, f64),
+) -> Capabilities {
+  Capabilities {
+    vision,
+    tools: true,
+    thinking,
+    context_window: 200_000,
+    max_output,
+    pricing,
+  }
+}
+
+/// Known model families, matched against IDs by prefix so that dated snapshots
+/// and `-latest` aliases share an entry. The longest matching prefix wins.
+const KNOWN: &[(&str, Capabilities)] = &[
+  ("claude-3-haiku", caps(true, false, 4_096, (0.25, 1.25))),
+  ("claude-3-5-haiku", caps(false, false, 8_192, (0.8, 4.0))),
+  ("claude-haiku-4-5", caps(true, true, 64_000, (1.0, 5.0))),
+  ("claude-3-sonnet", caps(true, false, 4_096, (3.0, 15.0))),
+  ("claude-3-5-sonnet", caps(true, false, 8_192, (3.0, 15.0))),
+  ("claude-3-7-sonnet", caps(true, true, 64_000, (3.0, 15.0))),
+  ("claude-sonnet-4", caps(true, true, 64_000, (3.0, 15.0))),
+  ("claude-sonnet-4-5", caps(true, true, 64_000, (3.0, 15.0))),
+  ("claude-opus-4", caps(true, true, 32_000, (15.0, 75.0))),
+  ("claude-opus-4-1", caps(true, true, 32_000, (15.0, 75.0))),
+];
+
+/// Assumed for models that aren't in the table yet. Priced like a Sonnet so that
+/// budgets err on the side of caution.
+const FALLBACK: Capabilities = caps(true, false, 8_192, (3.0, 15.0));
+
+impl Capabilities {
+  pub fn for_id(id: &str) -> Self {
+    KNOWN
+      .iter()
+      .filter(|(prefix, _)| id.starts_with(prefix))
+      .max_by_key(|(prefix, _)| prefix.len())
+      .map(|(_, caps)| caps.clone())
+      .unwrap_or(FALLBACK)
+  }
+}
+
+/// A model as described by the `/v1/models` endpoint.
+#[derive(Deserialize, Clone, Debug)]
+pub struct ModelDetails {
+  pub id: String,
+  pub display_name: String,
+  pub created_at: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ListModelsResponse {
+  data: Vec<ModelDetails>,
+  has_more: bool,
+  last_id: Option<String>,
+}
+
+/// A model the API offers, along with what it can do.
+#[derive(Clone, Debug)]
+pub struct ModelInfo {
+  pub model: Model,
+  pub display_name: String,
+  pub capabilities: Capabilities,
+}
+
+/// The models available to choose from, newest first.
+#[derive(Clone, Debug)]
+pub struct Registry {
+  models: Vec<ModelInfo>,
+}
+
+impl Registry {
+  /// The models the bot uses by default, for when the API listing isn't available.
+  pub fn builtin() -> Self {
+    let models = [
+      Model::SONNET_45,
+      Model::HAIKU_45,
+      Model::SONNET_4,
+      Model::SONNET_37,
+      Model::HAIKU_35,
+      Model::HAIKU_3,
+    ];
+
+    Self {
+      models: models
+        .into_iter()
+        .map(|model| ModelInfo {
+          display_name: model.id().to_owned(),
+          capabilities: model.capabilities(),
+          model,
+        })
+        .collect(),
+    }
+  }
+
+  /// Builds a registry from the API's model listing, merged with the local capability table.
+  pub fn from_listing(listing: Vec<ModelDetails>) -> Self {
+    Self {
+      models: listing
+        .into_iter()
+        .map(|details| ModelInfo {
+          capabilities: Capabilities::for_id(&details.id),
+          model: Model::new(details.id),
+          display_name: details.display_name,
+        })
+        .collect(),
+    }
+  }
+
+  pub fn models(&self) -> &[ModelInfo] {
+    &self.models
+  }
+
+  /// Finds a model by its exact ID, or by an alias such as `claude-sonnet-4-5`
+  /// or `claude-3-5-haiku-latest`, which resolves to the newest matching snapshot.
+  pub fn resolve(&self, id: &str) -> Option<&ModelInfo> {
+    let family = id.strip_suffix("-latest").unwrap_or(id);
+    // a snapshot is the family followed by a date, e.g. `claude-sonnet-4-20250514`.
+    // checking for the date keeps `claude-sonnet-4` from matching `claude-sonnet-4-5-...`.
+    let is_snapshot = |candidate: &str| {
+      candidate
+        .strip_prefix(family)
+        .and_then(|rest| rest.strip_prefix('-'))
+        .is_some_and(|date| date.len() == 8 && date.chars().all(|c| c.is_ascii_digit()))
+    };
+
+    self
+      .models
+      .iter()
+      .find(|info| info.model.id() == id)
+      .or_else(|| self.models.iter().find(|info| is_snapshot(info.model.id())))
+  }
+
+  /// Checks that `id` names a model guilds may pick, returning the model it resolves to.
+  /// Errors are phrased for showing to whoever tried to pick the model.
+  pub fn validate(&self, id: &str, needs_vision: bool) -> Result<Model, String> {
+    let info = self.resolve(id).ok_or_else(|| {
+      format!(
+        "I don't know a model called `{}`. Try `show-models` to see what's available.",
+        id
+      )
+    })?;
+
+    if needs_vision && !info.capabilities.vision {
+      return Err(format!("`{}` can't look at images.", info.model));
+    }
+
+    Ok(info.model.clone())
+  }
+}
+
+impl Client {
+  /// Lists every model available to this API key, following pagination, newest first.
+  pub async fn list_models(&self) -> Result<Vec<ModelDetails>, Error> {
+    let mut models = vec![];
+    let mut after: Option<String> = None;
+
+    loop {
+      let path = match &after {
+        Some(id) => format!("/v1/models?limit=100&after_id={}", id),
+        None => "/v1/models?limit=100".into(),
+      };
+      let body = self
+        .call(reqwest::Method::GET, &path, None)
+        .await?
+        .text()
+        .await
+        .map_err(reqwest_middleware::Error::Reqwest)?;
+
+      let page: ListModelsResponse = parse(&body)?;
+      models.extend(page.data);
+
+      match page.last_id {
+        Some(last) if page.has_more => after = Some(last),
+        _ => return Ok(models),
+      }
+    }
+  }
+
+  /// Builds a model registry from the API's listing.
+  pub async fn registry(&self) -> Result<Registry, Error> {
+    Ok(Registry::from_listing(self.list_models().await?))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::claude::testing::{FakeServer, Scripted};
+  use serde_json::json;
+
+  #[test]
+  fn test_capabilities() {
+    assert_eq!(Model::HAIKU_45.pricing(), (1.0, 5.0));
+    assert!(!Model::HAIKU_35.capabilities().vision);
+    assert!(!Model::new("claude-3-5-haiku-20241022").supports_thinking());
+    // the longest prefix wins, so 4.5 isn't mistaken for 4.
+    assert_eq!(
+      Capabilities::for_id("claude-sonnet-4-5-20250929").max_output,
+      64_000
+    );
+    assert_eq!(
+      Capabilities::for_id("claude-opus-4-1-20250805").pricing,
+      (15.0, 75.0)
+    );
+    assert_eq!(Capabilities::for_id("claude-something-new"), FALLBACK);
+  }
+
+  #[tokio::test]
+  async fn test_registry_pagination() {
+    let model = |id: &str| json!({ "type": "model", "id": id, "display_name": id, "created_at": "2025-01-01T00:00:00Z" });
+    let server = FakeServer::start(vec![
+      Scripted::body(
+        json!({
+          "data": [model("claude-sonnet-4-5-20250929"), model("claude-haiku-4-5-20251001")],
+          "first_id": "claude-sonnet-4-5-20250929",
+          "last_id": "claude-haiku-4-5-20251001",
+          "has_more": true,
+        })
+        .to_string(),
+      ),
+      Scripted::body(
+        json!({
+          "data": [model("claude-3-5-haiku-20241022")],
+          "first_id": "claude-3-5-haiku-20241022",
+          "last_id": "claude-3-5-haiku-20241022",
+          "has_more": false,
+        })
+        .to_string(),
+      ),
+    ])
+    .await;
+
+    let registry = server.client().registry().await.unwrap();
+
+    assert_eq!(registry.models().len(), 3);
+    let requests = server.requests();
+    assert_eq!(requests[0].path, "/v1/models?limit=100");
+    assert_eq!(
+      requests[1].path,
+      "/v1/models?limit=100&after_id=claude-haiku-4-5-20251001"
+    );
+
+    let haiku = registry.resolve("claude-3-5-haiku-latest").unwrap();
+    assert_eq!(haiku.model.id(), "claude-3-5-haiku-20241022");
+    assert!(!haiku.capabilities.vision);
+    assert_eq!(
+      registry.resolve("claude-sonnet-4-5").unwrap().model,
+      Model::SONNET_45
+    );
+    assert!(registry.resolve("gpt-4").is_none());
+    assert!(registry.resolve("claude-sonnet-4").is_none());
+    assert!(registry.validate("gpt-4", false).is_err());
+    assert!(registry.validate("claude-3-5-haiku-latest", true).is_err());
+    assert_eq!(
+      registry.validate("claude-haiku-4-5", true),
+      Ok(Model::HAIKU_45)
+    );
+  }
+}
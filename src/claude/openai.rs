@@ -0,0 +1,867 @@
+//! A provider for servers that speak the OpenAI-compatible chat completions protocol,
+//! such as llama.cpp's `llama-server` or Ollama, for offline guilds, development and CI.
+//! Conversations keep Anthropic's shape everywhere else: they're mapped to chat messages
+//! on the way out, and the reply is mapped back into `Content` blocks.
+
+use super::api::{APIError, DEFAULT_CONNECT_TIMEOUT, DEFAULT_READ_TIMEOUT, cancellable};
+use super::error::{Details, parse};
+use super::provider::Provider;
+use super::stream::EventParser;
+use super::{
+  Content, DocumentSource, Error, ImageSource, Interaction, MessageOptions, Model, Response, Role,
+  Schema, Tool, ToolChoice, Usage, tokens,
+};
+use async_trait::async_trait;
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE, HeaderValue};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+pub struct OpenAIClient {
+  base_url: String,
+  api_key: Option<String>,
+  model: Model,
+  http: reqwest::Client,
+}
+
+impl OpenAIClient {
+  /// A client for the server at `base_url`, including any version prefix,
+  /// e.g. `http://localhost:11434/v1` for Ollama.
+  pub fn new<S: Into<String>>(base_url: S, model: Model) -> Self {
+    let http = reqwest::Client::builder()
+      .connect_timeout(DEFAULT_CONNECT_TIMEOUT)
+      .read_timeout(DEFAULT_READ_TIMEOUT)
+      .build()
+      .expect("failed to build the HTTP client");
+
+    Self {
+      base_url: base_url.into().trim_end_matches('/').to_owned(),
+      api_key: None,
+      model,
+      http,
+    }
+  }
+
+  /// Sends the key as a bearer token. Local servers usually don't need one.
+  pub fn api_key<S: Into<String>>(mut self, key: S) -> Self {
+    self.api_key = Some(key.into());
+    self
+  }
+
+  fn request<'a>(
+    &self,
+    model_override: Option<Model>,
+    messages: &[Interaction],
+    tools: &'a [Tool],
+    prompt: &str,
+    options: &MessageOptions,
+  ) -> ChatRequest<'a> {
+    let tools = tools
+      .iter()
+      .filter_map(|tool| match tool {
+        Tool::Custom {
+          name,
+          description,
+          input_schema,
+          ..
+        } => Some(ChatTool {
+          kind: "function",
+          function: FunctionDefinition {
+            name,
+            description,
+            parameters: input_schema,
+          },
+        }),
+        // server tools only exist on the Anthropic API.
+        _ => None,
+      })
+      .collect::<Vec<_>>();
+    let has_tools = !tools.is_empty();
+
+    ChatRequest {
+      model: model_override.unwrap_or_else(|| self.model.clone()),
+      messages: to_messages(prompt, messages),
+      tools,
+      tool_choice: options
+        .tool_choice
+        .as_ref()
+        .filter(|_| has_tools)
+        .map(tool_choice),
+      parallel_tool_calls: (has_tools && options.disable_parallel_tool_use).then_some(false),
+      max_tokens: options.max_tokens(),
+      temperature: options.temperature,
+      top_p: options.top_p.filter(|_| options.temperature.is_none()),
+      top_k: options.top_k,
+      stop: options.stop_sequences.clone(),
+      user: options.user_id.clone(),
+      stream: true,
+      stream_options: StreamOptions {
+        include_usage: true,
+      },
+    }
+  }
+
+  async fn send(&self, payload: &ChatRequest<'_>) -> Result<reqwest::Response, Error> {
+    let mut req = self
+      .http
+      .post(format!("{}/chat/completions", self.base_url))
+      .header(CONTENT_TYPE, "application/json")
+      .body(serde_json::to_string(payload)?);
+    if let Some(key) = &self.api_key
+      && let Ok(mut value) = HeaderValue::from_str(&format!("Bearer {}", key))
+    {
+      value.set_sensitive(true);
+      req = req.header(AUTHORIZATION, value);
+    }
+
+    let resp = req
+      .send()
+      .await
+      .map_err(reqwest_middleware::Error::Reqwest)?;
+    let status = resp.status();
+    if status.is_success() {
+      return Ok(resp);
+    }
+
+    let body = resp.text().await.unwrap_or_default();
+    let message = parse::<ErrorBody>(&body)
+      .map(|e| e.error.message)
+      .unwrap_or_else(|_| body.clone());
+    Err(Error::APIError(
+      api_error(status.as_u16(), message),
+      Details {
+        status: Some(status.as_u16()),
+        request_id: None,
+        body: Some(body),
+      },
+    ))
+  }
+}
+
+#[async_trait]
+impl Provider for OpenAIClient {
+  fn model(&self) -> Model {
+    self.model.clone()
+  }
+
+  async fn create_message_stream(
+    &self,
+    model_override: Option<Model>,
+    messages: &[Interaction],
+    tools: &[Tool],
+    prompt: String,
+    options: &MessageOptions,
+    on_text: &mut (dyn for<'t> FnMut(&'t str) + Send),
+  ) -> Result<Response, Error> {
+    let payload = self.request(model_override, messages, tools, &prompt, options);
+
+    cancellable(options, async {
+      let mut resp = self.send(&payload).await?;
+      let mut parser = EventParser::default();
+      let mut acc = ChunkAccumulator::default();
+
+      while let Some(chunk) = resp
+        .chunk()
+        .await
+        .map_err(reqwest_middleware::Error::Reqwest)?
+      {
+        for data in parser.push_data(&chunk) {
+          if data == "[DONE]" {
+            continue;
+          }
+          let chunk: Chunk = parse(&data)?;
+          if let Some(error) = chunk.error {
+            return Err(Error::StreamError(error.message));
+          }
+          if let Some(text) = acc.apply(chunk) {
+            on_text(&text);
+          }
+        }
+      }
+
+      Ok(acc.finish(&payload.model))
+    })
+    .await
+  }
+
+  /// Chat completions servers can't count tokens, so this is a local estimate.
+  async fn count_tokens(
+    &self,
+    _model_override: Option<Model>,
+    messages: &[Interaction],
+    tools: &[Tool],
+    prompt: String,
+    _options: &MessageOptions,
+  ) -> Result<usize, Error> {
+    Ok(
+      tokens::estimate_text(&prompt)
+        + messages
+          .iter()
+          .map(tokens::estimate_interaction)
+          .sum::<usize>()
+        + tokens::estimate_tools(tools),
+    )
+  }
+}
+
+#[derive(Serialize, Debug)]
+struct ChatRequest<'a> {
+  model: Model,
+  messages: Vec<ChatMessage>,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  tools: Vec<ChatTool<'a>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  tool_choice: Option<Value>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  parallel_tool_calls: Option<bool>,
+  max_tokens: usize,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  temperature: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  top_p: Option<f64>,
+  /// Not part of the OpenAI protocol, but llama.cpp honours it and others ignore it.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  top_k: Option<usize>,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  stop: Vec<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  user: Option<String>,
+  stream: bool,
+  stream_options: StreamOptions,
+}
+
+#[derive(Serialize, Debug)]
+struct StreamOptions {
+  include_usage: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct ChatTool<'a> {
+  #[serde(rename = "type")]
+  kind: &'static str,
+  function: FunctionDefinition<'a>,
+}
+
+#[derive(Serialize, Debug)]
+struct FunctionDefinition<'a> {
+  name: &'a str,
+  description: &'a str,
+  parameters: &'a Schema,
+}
+
+/// A message in the chat completions format.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct ChatMessage {
+  role: String,
+  #[serde(default)]
+  content: Option<ChatContent>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  tool_calls: Option<Vec<ToolCall>>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  tool_call_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+enum ChatContent {
+  Text(String),
+  Parts(Vec<Part>),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Part {
+  Text { text: String },
+  ImageUrl { image_url: ImageUrl },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct ImageUrl {
+  url: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+struct ToolCall {
+  id: String,
+  #[serde(rename = "type")]
+  kind: String,
+  function: FunctionCall,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+struct FunctionCall {
+  name: String,
+  /// The input as a JSON encoded string.
+  arguments: String,
+}
+
+impl ChatMessage {
+  fn new<S: Into<String>>(role: S, content: Option<ChatContent>) -> Self {
+    Self {
+      role: role.into(),
+      content,
+      tool_calls: None,
+      tool_call_id: None,
+    }
+  }
+}
+
+/// Maps a conversation to chat messages. Tool results become `tool` messages, and blocks
+/// the protocol has no room for, like thinking, are dropped.
+fn to_messages(prompt: &str, messages: &[Interaction]) -> Vec<ChatMessage> {
+  let mut out = vec![];
+  if !prompt.is_empty() {
+    out.push(ChatMessage::new(
+      "system",
+      Some(ChatContent::Text(prompt.into())),
+    ));
+  }
+
+  for interaction in messages {
+    match interaction.role {
+      Role::Assistant => {
+        let text = interaction
+          .content
+          .iter()
+          .flat_map(to_parts)
+          .filter_map(|part| match part {
+            Part::Text { text } => Some(text),
+            Part::ImageUrl { .. } => None,
+          })
+          .collect::<Vec<_>>();
+        let calls = interaction
+          .content
+          .iter()
+          .filter_map(|block| match block {
+            Content::ToolUse {
+              id, name, input, ..
+            } => Some(ToolCall {
+              id: id.clone(),
+              kind: "function".into(),
+              function: FunctionCall {
+                name: name.clone(),
+                arguments: input.to_string(),
+              },
+            }),
+            _ => None,
+          })
+          .collect::<Vec<_>>();
+
+        let mut message = ChatMessage::new(
+          "assistant",
+          (!text.is_empty()).then(|| ChatContent::Text(text.join("\n\n"))),
+        );
+        message.tool_calls = (!calls.is_empty()).then_some(calls);
+        out.push(message);
+      }
+      Role::User => {
+        // tool messages have to directly follow the assistant message that asked for them.
+        for block in &interaction.content {
+          if let Content::ToolResult {
+            tool_use_id,
+            content,
+            is_error,
+            ..
+          } = block
+          {
+            let text = content
+              .iter()
+              .flat_map(to_parts)
+              .map(|part| match part {
+                Part::Text { text } => text,
+                Part::ImageUrl { .. } => "[image]".into(),
+              })
+              .collect::<Vec<_>>()
+              .join("\n\n");
+            let text = if *is_error {
+              format!("Error: {}", text)
+            } else {
+              text
+            };
+            let mut message = ChatMessage::new("tool", Some(ChatContent::Text(text)));
+            message.tool_call_id = Some(tool_use_id.clone());
+            out.push(message);
+          }
+        }
+
+        let parts = interaction
+          .content
+          .iter()
+          .flat_map(to_parts)
+          .collect::<Vec<_>>();
+        if parts.is_empty() {
+          continue;
+        }
+        // plain strings are understood by every server; only use parts when there are images.
+        let content = if parts.iter().all(|p| matches!(p, Part::Text { .. })) {
+          ChatContent::Text(
+            parts
+              .into_iter()
+              .filter_map(|part| match part {
+                Part::Text { text } => Some(text),
+                Part::ImageUrl { .. } => None,
+              })
+              .collect::<Vec<_>>()
+              .join("\n\n"),
+          )
+        } else {
+          ChatContent::Parts(parts)
+        };
+        out.push(ChatMessage::new("user", Some(content)));
+      }
+    }
+  }
+
+  out
+}
+
+/// The parts a content block becomes in a user or assistant message.
+fn to_parts(block: &Content) -> Vec<Part> {
+  let text = |text: String| vec![Part::Text { text }];
+  let image = |url: String| {
+    vec![Part::ImageUrl {
+      image_url: ImageUrl { url },
+    }]
+  };
+
+  match block {
+    Content::Text { text: t, .. } => text(t.clone()),
+    Content::Image { source, .. } => match source {
+      ImageSource::Base64 { media_type, data } => {
+        image(format!("data:{};base64,{}", media_type, data))
+      }
+      ImageSource::File { .. } => text("[an image that is no longer available]".into()),
+    },
+    Content::Document { source, title, .. } => {
+      let title = title.as_deref().unwrap_or("document");
+      match source {
+        DocumentSource::Text { data, .. } => text(format!("{}:\n{}", title, data)),
+        DocumentSource::Base64 { .. } => {
+          text(format!("[a PDF that can't be read here: {}]", title))
+        }
+      }
+    }
+    _ => vec![],
+  }
+}
+
+/// Maps a chat message back to an interaction. `tool` messages become tool results.
+fn to_interaction(message: ChatMessage) -> Interaction {
+  let mut content = match message.content {
+    None => vec![],
+    Some(ChatContent::Text(text)) if text.is_empty() => vec![],
+    Some(ChatContent::Text(text)) => vec![Content::text(text)],
+    Some(ChatContent::Parts(parts)) => parts
+      .into_iter()
+      .map(|part| match part {
+        Part::Text { text } => Content::text(text),
+        Part::ImageUrl { image_url } => from_image_url(image_url.url),
+      })
+      .collect(),
+  };
+
+  if let Some(id) = message.tool_call_id {
+    content = vec![Content::tool_result(id, content, false)];
+  }
+
+  for call in message.tool_calls.unwrap_or_default() {
+    // models sometimes produce arguments that aren't valid JSON; let the tool report it.
+    let input = serde_json::from_str(&call.function.arguments)
+      .unwrap_or(Value::String(call.function.arguments));
+    content.push(Content::tool_use(call.id, call.function.name, input));
+  }
+
+  let role = match message.role.as_str() {
+    "assistant" => Role::Assistant,
+    _ => Role::User,
+  };
+  Interaction { role, content }
+}
+
+/// Inline images are kept. Linked ones become text, since history only holds images we have the bytes of.
+fn from_image_url(url: String) -> Content {
+  if let Some(data_url) = url.strip_prefix("data:")
+    && let Some((media_type, data)) = data_url.split_once(";base64,")
+  {
+    return Content::image(ImageSource::Base64 {
+      media_type: media_type.into(),
+      data: data.into(),
+    });
+  }
+  Content::text(format!("[image: {}]", url))
+}
+
+fn tool_choice(choice: &ToolChoice) -> Value {
+  match choice {
+    ToolChoice::Auto { .. } => json!("auto"),
+    ToolChoice::Any { .. } => json!("required"),
+    ToolChoice::None => json!("none"),
+    ToolChoice::Tool { name, .. } => json!({ "type": "function", "function": { "name": name } }),
+  }
+}
+
+/// Maps a `finish_reason` to the equivalent Anthropic `stop_reason`.
+fn stop_reason(finish_reason: &str) -> String {
+  match finish_reason {
+    "stop" => "end_turn",
+    "length" => "max_tokens",
+    "tool_calls" | "function_call" => "tool_use",
+    other => other,
+  }
+  .into()
+}
+
+fn api_error(status: u16, message: String) -> APIError {
+  match status {
+    401 => APIError::AuthenticationError { message },
+    403 => APIError::PermissionError { message },
+    404 => APIError::NotFoundError { message },
+    429 => APIError::RateLimitError { message },
+    503 => APIError::OverloadedError { message },
+    400..=499 => APIError::InvalidRequestError { message },
+    _ => APIError::ApiError { message },
+  }
+}
+
+#[derive(Deserialize, Debug)]
+struct ErrorBody {
+  error: ErrorMessage,
+}
+
+#[derive(Deserialize, Debug)]
+struct ErrorMessage {
+  message: String,
+}
+
+/// One `chat.completion.chunk` of a streamed response.
+#[derive(Deserialize, Debug)]
+struct Chunk {
+  #[serde(default)]
+  id: String,
+  #[serde(default)]
+  choices: Vec<ChunkChoice>,
+  #[serde(default)]
+  usage: Option<ChatUsage>,
+  #[serde(default)]
+  error: Option<ErrorMessage>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChunkChoice {
+  #[serde(default)]
+  delta: ChunkDelta,
+  #[serde(default)]
+  finish_reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct ChunkDelta {
+  #[serde(default)]
+  content: Option<String>,
+  #[serde(default)]
+  tool_calls: Option<Vec<ToolCallDelta>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ToolCallDelta {
+  #[serde(default)]
+  index: usize,
+  #[serde(default)]
+  id: Option<String>,
+  #[serde(default)]
+  function: Option<FunctionCallDelta>,
+}
+
+#[derive(Deserialize, Debug)]
+struct FunctionCallDelta {
+  #[serde(default)]
+  name: Option<String>,
+  #[serde(default)]
+  arguments: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChatUsage {
+  prompt_tokens: usize,
+  completion_tokens: usize,
+}
+
+/// Assembles streamed chunks into a complete response.
+#[derive(Default, Debug)]
+struct ChunkAccumulator {
+  id: String,
+  text: String,
+  calls: Vec<ToolCall>,
+  finish_reason: Option<String>,
+  usage: Option<ChatUsage>,
+}
+
+impl ChunkAccumulator {
+  /// Applies a chunk, returning any text it added.
+  fn apply(&mut self, chunk: Chunk) -> Option<String> {
+    if self.id.is_empty() {
+      self.id = chunk.id;
+    }
+    if chunk.usage.is_some() {
+      self.usage = chunk.usage;
+    }
+
+    let mut added = String::new();
+    for choice in chunk.choices {
+      if let Some(text) = choice.delta.content {
+        added.push_str(&text);
+      }
+      for delta in choice.delta.tool_calls.unwrap_or_default() {
+        if self.calls.len() <= delta.index {
+          self.calls.resize_with(delta.index + 1, Default::default);
+        }
+        let call = &mut self.calls[delta.index];
+        call.kind = "function".into();
+        if let Some(id) = delta.id {
+          call.id = id;
+        }
+        if let Some(function) = delta.function {
+          call
+            .function
+            .name
+            .push_str(&function.name.unwrap_or_default());
+          call
+            .function
+            .arguments
+            .push_str(&function.arguments.unwrap_or_default());
+        }
+      }
+      if choice.finish_reason.is_some() {
+        self.finish_reason = choice.finish_reason;
+      }
+    }
+
+    self.text.push_str(&added);
+    (!added.is_empty()).then_some(added)
+  }
+
+  fn finish(self, model: &Model) -> Response {
+    let has_calls = !self.calls.is_empty();
+    let calls = self
+      .calls
+      .into_iter()
+      .enumerate()
+      .map(|(index, mut call)| {
+        // some servers leave out call IDs, but tool results have to refer to one.
+        if call.id.is_empty() {
+          call.id = format!("call_{}", index);
+        }
+        call
+      })
+      .collect::<Vec<_>>();
+    let mut message = ChatMessage::new("assistant", Some(ChatContent::Text(self.text)));
+    message.tool_calls = has_calls.then_some(calls);
+
+    let stop_reason = match self.finish_reason {
+      Some(reason) => stop_reason(&reason),
+      None if has_calls => "tool_use".into(),
+      None => "end_turn".into(),
+    };
+    let usage = self.usage.unwrap_or(ChatUsage {
+      prompt_tokens: 0,
+      completion_tokens: 0,
+    });
+
+    Response::Message {
+      id: self.id,
+      model: model.id().into(),
+      role: "assistant".into(),
+      stop_reason: Some(stop_reason),
+      stop_sequence: None,
+      usage: Usage {
+        input_tokens: usage.prompt_tokens,
+        output_tokens: usage.completion_tokens,
+        cache_creation_input_tokens: None,
+        cache_read_input_tokens: None,
+      },
+      content: to_interaction(message).content,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::claude::testing::{FakeServer, Scripted};
+
+  fn events(chunks: &[Value]) -> String {
+    chunks
+      .iter()
+      .map(|chunk| format!("data: {}\n\n", chunk))
+      .chain(["data: [DONE]\n\n".to_string()])
+      .collect()
+  }
+
+  #[test]
+  fn test_messages_map_both_ways() {
+    let conversation = vec![
+      Interaction {
+        role: Role::User,
+        content: vec![
+          Content::text("what's this?"),
+          Content::image(ImageSource::Base64 {
+            media_type: "image/png".into(),
+            data: "iVBOR".into(),
+          }),
+        ],
+      },
+      Interaction {
+        role: Role::Assistant,
+        content: vec![
+          Content::text("let me check"),
+          Content::tool_use("call_1", "fetch", json!({ "url": "https://example.com" })),
+        ],
+      },
+      Interaction {
+        role: Role::User,
+        content: vec![Content::tool_result(
+          "call_1",
+          vec![Content::text("a cat")],
+          false,
+        )],
+      },
+    ];
+
+    let messages = to_messages("be nice", &conversation);
+    assert_eq!(
+      serde_json::to_value(&messages).unwrap(),
+      json!([
+        { "role": "system", "content": "be nice" },
+        {
+          "role": "user",
+          "content": [
+            { "type": "text", "text": "what's this?" },
+            { "type": "image_url", "image_url": { "url": "data:image/png;base64,iVBOR" } },
+          ],
+        },
+        {
+          "role": "assistant",
+          "content": "let me check",
+          "tool_calls": [{
+            "id": "call_1",
+            "type": "function",
+            "function": { "name": "fetch", "arguments": "{\"url\":\"https://example.com\"}" },
+          }],
+        },
+        { "role": "tool", "content": "a cat", "tool_call_id": "call_1" },
+      ])
+    );
+
+    let back = messages
+      .into_iter()
+      .skip(1)
+      .map(to_interaction)
+      .collect::<Vec<_>>();
+    assert_eq!(
+      serde_json::to_value(&back).unwrap(),
+      serde_json::to_value(&conversation).unwrap()
+    );
+  }
+
+  #[tokio::test]
+  async fn test_streamed_tool_call() {
+    let body = events(&[
+      json!({ "id": "chatcmpl-1", "choices": [{ "delta": { "role": "assistant", "content": "Check" } }] }),
+      json!({ "id": "chatcmpl-1", "choices": [{ "delta": { "content": "ing." } }] }),
+      json!({ "id": "chatcmpl-1", "choices": [{ "delta": { "tool_calls": [
+        { "index": 0, "id": "call_9", "type": "function", "function": { "name": "fetch", "arguments": "{\"url\":" } },
+      ] } }] }),
+      json!({ "id": "chatcmpl-1", "choices": [{ "delta": { "tool_calls": [
+        { "index": 0, "function": { "arguments": "\"https://example.com\"}" } },
+      ] }, "finish_reason": "tool_calls" }] }),
+      json!({ "id": "chatcmpl-1", "choices": [], "usage": { "prompt_tokens": 12, "completion_tokens": 7 } }),
+    ]);
+    let server = FakeServer::start(vec![Scripted::body(body)]).await;
+    let client = OpenAIClient::new(format!("{}/v1/", server.url()), Model::new("llama3.2"))
+      .api_key("local-key");
+
+    let tools = vec![Tool::Custom {
+      name: "fetch".into(),
+      description: "fetch a page".into(),
+      input_schema: Box::new(Schema::object()),
+      cache_control: None,
+    }];
+    let options = MessageOptions {
+      tool_choice: Some(ToolChoice::parse("any")),
+      disable_parallel_tool_use: true,
+      ..Default::default()
+    };
+    let mut streamed = String::new();
+    let resp = client
+      .create_message_stream(
+        None,
+        &[Interaction {
+          role: Role::User,
+          content: vec![Content::text("read example.com")],
+        }],
+        &tools,
+        "be nice".into(),
+        &options,
+        &mut |text: &str| streamed.push_str(text),
+      )
+      .await
+      .unwrap();
+
+    assert_eq!(streamed, "Checking.");
+    let Response::Message {
+      stop_reason,
+      usage,
+      content,
+      ..
+    } = resp
+    else {
+      panic!("expected a message");
+    };
+    assert_eq!(stop_reason.as_deref(), Some("tool_use"));
+    assert_eq!((usage.input_tokens, usage.output_tokens), (12, 7));
+    assert_eq!(
+      serde_json::to_value(&content).unwrap(),
+      serde_json::to_value(vec![
+        Content::text("Checking."),
+        Content::tool_use("call_9", "fetch", json!({ "url": "https://example.com" })),
+      ])
+      .unwrap()
+    );
+
+    let request = &server.requests()[0];
+    assert_eq!(request.path, "/v1/chat/completions");
+    assert_eq!(request.headers["authorization"], "Bearer local-key");
+    assert_eq!(request.body["model"], "llama3.2");
+    assert_eq!(request.body["tool_choice"], "required");
+    assert_eq!(request.body["parallel_tool_calls"], false);
+    assert_eq!(request.body["tools"][0]["function"]["name"], "fetch");
+    assert_eq!(request.body["messages"][0]["role"], "system");
+  }
+
+  #[tokio::test]
+  async fn test_error_status() {
+    let server = FakeServer::start(vec![Scripted::Raw {
+      status: 404,
+      headers: vec![],
+      body: json!({ "error": { "message": "model 'llama9' not found" } }).to_string(),
+    }])
+    .await;
+    let client = OpenAIClient::new(server.url(), Model::new("llama9"));
+
+    let err = client
+      .create_message_stream(
+        None,
+        &[],
+        &[],
+        String::new(),
+        &MessageOptions::default(),
+        &mut |_| {},
+      )
+      .await
+      .unwrap_err();
+    assert!(matches!(
+      err,
+      Error::APIError(APIError::NotFoundError { ref message }, _) if message == "model 'llama9' not found"
+    ));
+    assert_eq!(err.status(), Some(404));
+  }
+}
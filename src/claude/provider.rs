@@ -0,0 +1,74 @@
+//! The interface the bot needs from a language model backend.
+//! `Client` talks to the Anthropic API; `OpenAIClient` talks to anything that serves
+//! OpenAI-compatible chat completions, such as a local llama.cpp or Ollama server.
+
+use super::{Client, Error, Interaction, MessageOptions, Model, Response, Tool};
+use async_trait::async_trait;
+
+#[async_trait]
+pub trait Provider: Send + Sync {
+  /// The model used when a call doesn't override it.
+  fn model(&self) -> Model;
+
+  /// Runs the model, invoking `on_text` with each text fragment as it arrives.
+  /// The returned response contains the assembled content blocks, including any
+  /// tool use requests, and the usage of the call.
+  async fn create_message_stream(
+    &self,
+    model_override: Option<Model>,
+    messages: &[Interaction],
+    tools: &[Tool],
+    prompt: String,
+    options: &MessageOptions,
+    on_text: &mut (dyn for<'t> FnMut(&'t str) + Send),
+  ) -> Result<Response, Error>;
+
+  /// Counts, or estimates, the input tokens the same call would use.
+  async fn count_tokens(
+    &self,
+    model_override: Option<Model>,
+    messages: &[Interaction],
+    tools: &[Tool],
+    prompt: String,
+    options: &MessageOptions,
+  ) -> Result<usize, Error>;
+}
+
+#[async_trait]
+impl Provider for Client {
+  fn model(&self) -> Model {
+    Client::model(self)
+  }
+
+  async fn create_message_stream(
+    &self,
+    model_override: Option<Model>,
+    messages: &[Interaction],
+    tools: &[Tool],
+    prompt: String,
+    options: &MessageOptions,
+    on_text: &mut (dyn for<'t> FnMut(&'t str) + Send),
+  ) -> Result<Response, Error> {
+    Client::create_message_stream(
+      self,
+      model_override,
+      messages,
+      tools,
+      prompt,
+      options,
+      on_text,
+    )
+    .await
+  }
+
+  async fn count_tokens(
+    &self,
+    model_override: Option<Model>,
+    messages: &[Interaction],
+    tools: &[Tool],
+    prompt: String,
+    options: &MessageOptions,
+  ) -> Result<usize, Error> {
+    Client::count_tokens(self, model_override, messages, tools, prompt, options).await
+  }
+}
@@ -0,0 +1,283 @@
+//! Client-side rate limiting.
+//! The API reports its limits in `anthropic-ratelimit-*` headers on every response. We keep
+//! track of them so requests wait for quota instead of being rejected, and retry 429 (rate
+//! limited) and 529 (overloaded) responses once the server says it's ready again.
+
+use super::retry::STATUS_OVERLOADED;
+use chrono::{DateTime, TimeDelta, Utc};
+use http::Extensions;
+use log::warn;
+use reqwest::header::HeaderMap;
+use reqwest::{Request, Response, StatusCode};
+use reqwest_middleware::{Middleware, Next, Result};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Longest a request waits for quota or a `retry-after`, however long the server asks for.
+/// Requests are made from the event loop, so waiting longer would stall the whole bot.
+const MAX_WAIT: Duration = Duration::from_secs(60);
+
+/// The known state of one of the API's rate limits.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Quota {
+  pub limit: Option<u64>,
+  pub remaining: Option<u64>,
+  /// When the quota is next fully replenished.
+  pub reset: Option<DateTime<Utc>>,
+}
+
+impl Quota {
+  fn update(&mut self, headers: &HeaderMap, prefix: &str) {
+    let header = |name: &str| {
+      headers
+        .get(format!("{}-{}", prefix, name))
+        .and_then(|v| v.to_str().ok())
+    };
+
+    if let Some(limit) = header("limit").and_then(|v| v.parse().ok()) {
+      self.limit = Some(limit);
+    }
+    if let Some(remaining) = header("remaining").and_then(|v| v.parse().ok()) {
+      self.remaining = Some(remaining);
+    }
+    if let Some(reset) = header("reset").and_then(|v| DateTime::parse_from_rfc3339(v).ok()) {
+      self.reset = Some(reset.with_timezone(&Utc));
+    }
+  }
+
+  /// Assumes the quota is full again once its reset time has passed.
+  fn refill(&mut self, now: DateTime<Utc>) {
+    if self.reset.is_some_and(|reset| reset <= now) {
+      self.remaining = self.limit;
+      self.reset = None;
+    }
+  }
+
+  /// When this quota will next allow a request, if it's used up.
+  fn wait(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    match (self.remaining, self.reset) {
+      (Some(0), Some(reset)) if reset > now => Some(reset),
+      _ => None,
+    }
+  }
+}
+
+/// A snapshot of the client's view of its rate limits.
+#[derive(Clone, Debug, Default)]
+pub struct RateLimitStatus {
+  pub requests: Quota,
+  pub input_tokens: Quota,
+  pub output_tokens: Quota,
+  /// Set by a `retry-after` header; no requests are sent until then.
+  pub paused_until: Option<DateTime<Utc>>,
+  /// Number of 429 and 529 responses received.
+  pub throttled: u64,
+}
+
+impl RateLimitStatus {
+  /// Records the limits reported in a response's headers.
+  pub fn update(&mut self, headers: &HeaderMap) {
+    self
+      .requests
+      .update(headers, "anthropic-ratelimit-requests");
+    self
+      .input_tokens
+      .update(headers, "anthropic-ratelimit-input-tokens");
+    self
+      .output_tokens
+      .update(headers, "anthropic-ratelimit-output-tokens");
+  }
+
+  /// Reserves a request if quota is available, or returns the time to wait until.
+  fn acquire(&mut self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    self.requests.refill(now);
+    self.input_tokens.refill(now);
+    self.output_tokens.refill(now);
+
+    let wait = [
+      self.paused_until.filter(|until| *until > now),
+      self.requests.wait(now),
+      self.input_tokens.wait(now),
+      self.output_tokens.wait(now),
+    ]
+    .into_iter()
+    .flatten()
+    .max();
+
+    if wait.is_none() {
+      // count the request against the bucket right away, so that concurrent
+      // requests see it before the response (and its headers) come back.
+      if let Some(remaining) = &mut self.requests.remaining {
+        *remaining = remaining.saturating_sub(1);
+      }
+    }
+
+    wait
+  }
+}
+
+/// Rate limit state shared by every request made through a `Client`.
+pub type RateLimits = Arc<Mutex<RateLimitStatus>>;
+
+/// Waits for quota before each request, tracks the limits the API reports, and
+/// retries requests that were rate limited or hit an overloaded server.
+pub struct RateLimitMiddleware {
+  pub limits: RateLimits,
+  pub max_retries: u32,
+  /// Delay bounds used when the server doesn't say how long to wait.
+  pub retry_bounds: (Duration, Duration),
+}
+
+impl RateLimitMiddleware {
+  async fn acquire(&self) {
+    let deadline = Instant::now() + MAX_WAIT;
+    loop {
+      let wait = self.limits.lock().unwrap().acquire(Utc::now());
+      let Some(delay) = wait.and_then(|until| (until - Utc::now()).to_std().ok()) else {
+        return;
+      };
+      // past the deadline the request goes out anyway, and is retried if it's throttled.
+      let left = deadline.saturating_duration_since(Instant::now());
+      if left.is_zero() {
+        warn!(
+          "Still waiting for quota after {:?}, sending anyway",
+          MAX_WAIT
+        );
+        return;
+      }
+      tokio::time::sleep(delay.min(left)).await;
+    }
+  }
+
+  /// How long to wait before retrying: `retry-after` if the server sent a usable one
+  /// (at most `MAX_WAIT`), otherwise exponential backoff within `retry_bounds`.
+  fn retry_delay(&self, headers: &HeaderMap, attempt: u32) -> Duration {
+    let retry_after = headers
+      .get("retry-after")
+      .and_then(|v| v.to_str().ok())
+      .and_then(|v| v.trim().parse::<f64>().ok())
+      .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+      .map(|delay| delay.min(MAX_WAIT));
+
+    retry_after.unwrap_or_else(|| {
+      let (min, max) = self.retry_bounds;
+      min.saturating_mul(2u32.saturating_pow(attempt)).min(max)
+    })
+  }
+}
+
+#[async_trait::async_trait]
+impl Middleware for RateLimitMiddleware {
+  async fn handle(
+    &self,
+    req: Request,
+    extensions: &mut Extensions,
+    next: Next<'_>,
+  ) -> Result<Response> {
+    let mut req = req;
+    let mut attempt = 0;
+
+    loop {
+      self.acquire().await;
+
+      // keep a copy in case it needs to be retried. streaming bodies can't be
+      // cloned, so those requests only get a single attempt.
+      let retry = if attempt < self.max_retries {
+        req.try_clone()
+      } else {
+        None
+      };
+      let resp = next.clone().run(req, extensions).await?;
+      self.limits.lock().unwrap().update(resp.headers());
+
+      let status = resp.status();
+      let throttled =
+        status == StatusCode::TOO_MANY_REQUESTS || status.as_u16() == STATUS_OVERLOADED;
+      if !throttled {
+        return Ok(resp);
+      }
+
+      let delay = self.retry_delay(resp.headers(), attempt);
+      {
+        let mut limits = self.limits.lock().unwrap();
+        limits.throttled += 1;
+        // pausing the shared state makes every request back off, not just this one.
+        let until = Utc::now() + TimeDelta::from_std(delay).unwrap_or_default();
+        limits.paused_until = limits.paused_until.max(Some(until));
+      }
+
+      let Some(retry) = retry else {
+        return Ok(resp);
+      };
+
+      warn!(
+        "Request throttled with status {}, retrying in {:?}",
+        status, delay
+      );
+      req = retry;
+      attempt += 1;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use reqwest::header::HeaderValue;
+
+  #[test]
+  fn test_acquire_waits_for_reset() {
+    let now = Utc::now();
+    let reset = now + TimeDelta::seconds(10);
+    let mut headers = HeaderMap::new();
+    headers.insert(
+      "anthropic-ratelimit-requests-limit",
+      HeaderValue::from_static("2"),
+    );
+    headers.insert(
+      "anthropic-ratelimit-requests-remaining",
+      HeaderValue::from_static("1"),
+    );
+    headers.insert(
+      "anthropic-ratelimit-requests-reset",
+      HeaderValue::from_str(&reset.to_rfc3339()).unwrap(),
+    );
+
+    let mut status = RateLimitStatus::default();
+    status.update(&headers);
+    assert_eq!(status.requests.limit, Some(2));
+
+    assert_eq!(status.acquire(now), None);
+    assert_eq!(status.requests.remaining, Some(0));
+    assert_eq!(status.acquire(now), Some(reset));
+    // once the reset has passed the bucket is full again.
+    assert_eq!(status.acquire(reset), None);
+    assert_eq!(status.requests.remaining, Some(1));
+  }
+
+  #[test]
+  fn test_retry_delay() {
+    let middleware = RateLimitMiddleware {
+      limits: RateLimits::default(),
+      max_retries: 3,
+      retry_bounds: (Duration::from_secs(1), Duration::from_secs(30)),
+    };
+    let delay = |retry_after: Option<&'static str>, attempt| {
+      let mut headers = HeaderMap::new();
+      if let Some(v) = retry_after {
+        headers.insert("retry-after", HeaderValue::from_static(v));
+      }
+      middleware.retry_delay(&headers, attempt)
+    };
+
+    assert_eq!(delay(Some("2.5"), 0), Duration::from_millis(2_500));
+    assert_eq!(delay(Some("86400"), 0), MAX_WAIT);
+    // values that aren't a usable delay fall back to backoff.
+    for bad in ["-1", "nan", "inf", "1e300", "soon"] {
+      assert_eq!(delay(Some(bad), 1), Duration::from_secs(2), "{}", bad);
+    }
+    assert_eq!(delay(None, 0), Duration::from_secs(1));
+    assert_eq!(delay(None, 10), Duration::from_secs(30));
+  }
+}
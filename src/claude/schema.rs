@@ -1,54 +1,158 @@
+//! JSON Schema, as accepted for tool `input_schema`s.
+//! Schemas can be built by hand with the constructors below, or derived from a Rust
+//! struct with `json_schema!`, which also lets tools deserialize their input directly.
+
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::default::Default;
+use serde_json::Value;
+use std::collections::BTreeMap;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-#[serde(tag = "type")]
+/// The JSON types a schema can describe.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
-pub enum Schema {
-  Object {
-    properties: HashMap<String, Schema>,
-    required: Vec<String>,
-  },
-  String {
-    description: String,
-  },
-  Integer {
-    description: String,
-  },
+pub enum Type {
+  Null,
+  Boolean,
+  Integer,
+  Number,
+  String,
+  Array,
+  Object,
 }
 
-/// the default Schema is an empty Schema::Object
-impl Default for Schema {
-  fn default() -> Self {
-    Schema::Object {
-      properties: HashMap::default(),
-      required: Vec::default(),
-    }
-  }
+/// A schema's `type`: a single type, or a list of types such as `["string", "null"]`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum Kind {
+  One(Type),
+  Many(Vec<Type>),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Schema {
+  #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+  pub kind: Option<Kind>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub description: Option<String>,
+  #[serde(rename = "enum", skip_serializing_if = "Option::is_none")]
+  pub values: Option<Vec<Value>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub default: Option<Value>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub format: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub minimum: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub maximum: Option<f64>,
+  #[serde(rename = "minLength", skip_serializing_if = "Option::is_none")]
+  pub min_length: Option<usize>,
+  #[serde(rename = "maxLength", skip_serializing_if = "Option::is_none")]
+  pub max_length: Option<usize>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub pattern: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub items: Option<Box<Schema>>,
+  #[serde(rename = "minItems", skip_serializing_if = "Option::is_none")]
+  pub min_items: Option<usize>,
+  #[serde(rename = "maxItems", skip_serializing_if = "Option::is_none")]
+  pub max_items: Option<usize>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub properties: Option<BTreeMap<String, Schema>>,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub required: Vec<String>,
+  #[serde(
+    rename = "additionalProperties",
+    skip_serializing_if = "Option::is_none"
+  )]
+  pub additional_properties: Option<bool>,
+  #[serde(rename = "anyOf", default, skip_serializing_if = "Vec::is_empty")]
+  pub any_of: Vec<Schema>,
 }
 
 impl Schema {
-  /// Create a new Schema::String
-  pub fn string<S: Into<String>>(description: S) -> Self {
-    Schema::String {
-      description: description.into(),
+  fn of_type(kind: Type) -> Self {
+    Schema {
+      kind: Some(Kind::One(kind)),
+      ..Default::default()
     }
   }
 
-  /// Create a new Schema::Integer
+  fn described<S: Into<String>>(kind: Type, description: S) -> Self {
+    Self::of_type(kind).with_description(description)
+  }
+
+  /// Create a new string Schema
+  pub fn string<S: Into<String>>(description: S) -> Self {
+    Self::described(Type::String, description)
+  }
+
+  /// Create a new integer Schema
   pub fn integer<S: Into<String>>(description: S) -> Self {
-    Schema::Integer {
-      description: description.into(),
+    Self::described(Type::Integer, description)
+  }
+
+  /// Create a new string Schema limited to the given values
+  pub fn enumeration<S: Into<String>, V: Into<String>>(
+    description: S,
+    values: impl IntoIterator<Item = V>,
+  ) -> Self {
+    Self::string(description).with_values(values.into_iter().map(|v| Value::String(v.into())))
+  }
+
+  /// Create a new array Schema whose elements match `items`
+  pub fn array(items: Schema) -> Self {
+    Schema {
+      items: Some(Box::new(items)),
+      ..Self::of_type(Type::Array)
     }
   }
 
-  /// Create a new Schema::Object
+  /// Create a new, empty object Schema
   pub fn object() -> Self {
-    Self::default()
+    Schema {
+      properties: Some(BTreeMap::new()),
+      ..Self::of_type(Type::Object)
+    }
+  }
+
+  pub fn with_description<S: Into<String>>(mut self, description: S) -> Self {
+    self.description = Some(description.into());
+    self
+  }
+
+  /// Restricts the Schema to the given values
+  pub fn with_values(mut self, values: impl IntoIterator<Item = Value>) -> Self {
+    self.values = Some(values.into_iter().collect());
+    self
+  }
+
+  pub fn with_default<V: Into<Value>>(mut self, default: V) -> Self {
+    self.default = Some(default.into());
+    self
+  }
+
+  /// Sets the inclusive bounds of a number or integer Schema
+  pub fn with_range(mut self, minimum: Option<f64>, maximum: Option<f64>) -> Self {
+    self.minimum = minimum;
+    self.maximum = maximum;
+    self
   }
 
-  /// Add a new property to the Schema::Object
+  /// Also allow `null`
+  pub fn nullable(mut self) -> Self {
+    self.kind = match self.kind {
+      Some(Kind::One(kind)) if kind != Type::Null => Some(Kind::Many(vec![kind, Type::Null])),
+      Some(Kind::Many(mut kinds)) if !kinds.contains(&Type::Null) => {
+        kinds.push(Type::Null);
+        Some(Kind::Many(kinds))
+      }
+      Some(kind) => Some(kind),
+      // a schema without a type already allows null.
+      None => None,
+    };
+    self
+  }
+
+  /// Add a new property to an object Schema
   pub fn with_property<S: Into<String>>(
     mut self,
     name: S,
@@ -56,17 +160,200 @@ impl Schema {
     is_required: bool,
   ) -> Self {
     let name = name.into();
-    if let Schema::Object {
-      properties,
-      required,
-      ..
-    } = &mut self
-    {
-      if is_required {
-        required.push(name.clone());
-      }
-      properties.insert(name, schema);
+    if is_required {
+      self.required.push(name.clone());
     }
     self
+      .properties
+      .get_or_insert_with(BTreeMap::new)
+      .insert(name, schema);
+    self
+  }
+
+  /// Disallow properties that aren't listed in the object Schema
+  pub fn closed(mut self) -> Self {
+    self.additional_properties = Some(false);
+    self
+  }
+
+  /// The Schema for a Rust type.
+  pub fn of<T: JsonSchema>() -> Self {
+    T::schema()
+  }
+}
+
+/// Types that can describe themselves with a JSON Schema.
+/// Implement it for tool input structs with `json_schema!`.
+pub trait JsonSchema {
+  fn schema() -> Schema;
+
+  /// Whether the value may be left out of an enclosing object.
+  fn optional() -> bool {
+    false
+  }
+}
+
+macro_rules! primitive_schema {
+  ($kind:expr => $($ty:ty),*) => {
+    $(
+      impl JsonSchema for $ty {
+        fn schema() -> Schema {
+          Schema::of_type($kind)
+        }
+      }
+    )*
+  };
+}
+
+primitive_schema!(Type::String => String, char);
+primitive_schema!(Type::Boolean => bool);
+primitive_schema!(Type::Integer => i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+primitive_schema!(Type::Number => f32, f64);
+
+impl<T: JsonSchema> JsonSchema for Option<T> {
+  fn schema() -> Schema {
+    T::schema().nullable()
+  }
+
+  fn optional() -> bool {
+    true
+  }
+}
+
+impl<T: JsonSchema> JsonSchema for Vec<T> {
+  fn schema() -> Schema {
+    Schema::array(T::schema())
+  }
+}
+
+/// Any JSON value at all.
+impl JsonSchema for Value {
+  fn schema() -> Schema {
+    Schema::default()
+  }
+}
+
+/// Declares a struct that can be deserialized from tool input, and implements `JsonSchema`
+/// for it. Field doc comments become property descriptions, and `Option` fields are optional.
+///
+/// ```ignore
+/// json_schema! {
+///   pub struct FetchInput {
+///     /// the full path to a website to retrieve
+///     url: String,
+///   }
+/// }
+/// ```
+#[macro_export]
+macro_rules! json_schema {
+  (
+    $(#[$meta:meta])*
+    $vis:vis struct $name:ident {
+      $(
+        $(#[doc = $doc:literal])*
+        $field_vis:vis $field:ident : $ty:ty
+      ),* $(,)?
+    }
+  ) => {
+    $(#[$meta])*
+    #[derive(serde::Deserialize)]
+    $vis struct $name {
+      $(
+        $(#[doc = $doc])*
+        $field_vis $field: $ty,
+      )*
+    }
+
+    impl $crate::claude::JsonSchema for $name {
+      fn schema() -> $crate::claude::Schema {
+        let schema = $crate::claude::Schema::object();
+        $(
+          let description = [$($doc),*]
+            .iter()
+            .map(|line: &&str| line.trim())
+            .collect::<Vec<_>>()
+            .join(" ");
+          let mut property = <$ty as $crate::claude::JsonSchema>::schema();
+          if !description.is_empty() {
+            property = property.with_description(description);
+          }
+          let schema = schema.with_property(
+            stringify!($field),
+            property,
+            !<$ty as $crate::claude::JsonSchema>::optional(),
+          );
+        )*
+        schema
+      }
+    }
+  };
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  crate::json_schema! {
+    #[allow(dead_code)]
+    struct Search {
+      /// what to look for.
+      /// may span lines.
+      query: String,
+      limit: Option<u32>,
+      tags: Vec<String>,
+    }
+  }
+
+  #[test]
+  fn test_builders() {
+    let schema = Schema::object()
+      .with_property(
+        "unit",
+        Schema::enumeration("temperature unit", ["celsius", "fahrenheit"]).with_default("celsius"),
+        false,
+      )
+      .with_property(
+        "days",
+        Schema::integer("days to forecast").with_range(Some(1.0), Some(7.0)),
+        true,
+      )
+      .with_property("note", Schema::string("a note").nullable(), false)
+      .closed();
+
+    assert_eq!(
+      serde_json::to_value(&schema).unwrap(),
+      json!({
+        "type": "object",
+        "properties": {
+          "days": { "type": "integer", "description": "days to forecast", "minimum": 1.0, "maximum": 7.0 },
+          "note": { "type": ["string", "null"], "description": "a note" },
+          "unit": {
+            "type": "string",
+            "description": "temperature unit",
+            "enum": ["celsius", "fahrenheit"],
+            "default": "celsius",
+          },
+        },
+        "required": ["days"],
+        "additionalProperties": false,
+      })
+    );
+  }
+
+  #[test]
+  fn test_derived_schema() {
+    assert_eq!(
+      serde_json::to_value(Schema::of::<Search>()).unwrap(),
+      json!({
+        "type": "object",
+        "properties": {
+          "query": { "type": "string", "description": "what to look for. may span lines." },
+          "limit": { "type": ["integer", "null"] },
+          "tags": { "type": "array", "items": { "type": "string" } },
+        },
+        "required": ["query", "tags"],
+      })
+    );
   }
 }
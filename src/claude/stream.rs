@@ -0,0 +1,340 @@
+use super::api::{APIError, Response};
+use super::content::Citation;
+use super::error::parse;
+use super::{Content, Error};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// An incremental update to a single content block of a streamed message.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Delta {
+  #[serde(rename = "text_delta")]
+  Text { text: String },
+  #[serde(rename = "input_json_delta")]
+  InputJson { partial_json: String },
+  #[serde(rename = "citations_delta")]
+  Citations { citation: Citation },
+  #[serde(rename = "thinking_delta")]
+  Thinking { thinking: String },
+  #[serde(rename = "signature_delta")]
+  Signature { signature: String },
+  #[serde(other)]
+  Unknown,
+}
+
+/// Top-level message changes sent near the end of a stream.
+#[derive(Deserialize, Debug)]
+pub struct MessageDelta {
+  pub stop_reason: Option<String>,
+  pub stop_sequence: Option<String>,
+}
+
+/// Cumulative output token count reported alongside a `message_delta` event.
+#[derive(Deserialize, Debug)]
+pub struct DeltaUsage {
+  pub output_tokens: usize,
+}
+
+/// A single server-sent event from the streaming Messages API.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+  MessageStart {
+    message: Response,
+  },
+  ContentBlockStart {
+    index: usize,
+    content_block: Content,
+  },
+  ContentBlockDelta {
+    index: usize,
+    delta: Delta,
+  },
+  ContentBlockStop {
+    index: usize,
+  },
+  MessageDelta {
+    delta: MessageDelta,
+    usage: DeltaUsage,
+  },
+  MessageStop,
+  Ping,
+  Error {
+    error: APIError,
+  },
+}
+
+/// Splits a raw byte stream into server-sent events.
+/// Bytes are buffered until a full event (terminated by a blank line) is available,
+/// so multi-byte characters split across network chunks are handled correctly.
+#[derive(Default)]
+pub struct EventParser {
+  buf: Vec<u8>,
+}
+
+impl EventParser {
+  /// Feeds a chunk of the response body and returns any events it completed.
+  pub fn push(&mut self, chunk: &[u8]) -> Result<Vec<Event>, Error> {
+    self
+      .push_data(chunk)
+      .iter()
+      .map(|data| parse(data))
+      .collect()
+  }
+
+  /// Feeds a chunk of the response body and returns the raw `data` of any events it completed.
+  pub fn push_data(&mut self, chunk: &[u8]) -> Vec<String> {
+    self.buf.extend(chunk.iter().filter(|&&b| b != b'\r'));
+
+    let mut events = vec![];
+    while let Some(pos) = self.buf.windows(2).position(|w| w == b"\n\n") {
+      let raw = self.buf.drain(..pos + 2).collect::<Vec<_>>();
+      let raw = String::from_utf8_lossy(&raw);
+      let data = raw
+        .lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .map(|data| data.trim_start())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+      if !data.is_empty() {
+        events.push(data);
+      }
+    }
+
+    events
+  }
+}
+
+/// Assembles streamed events back into the `Response::Message` that the
+/// non-streaming endpoint would have returned.
+#[derive(Default)]
+pub struct Accumulator {
+  message: Option<Response>,
+  partial_json: HashMap<usize, String>,
+  complete: bool,
+}
+
+impl Accumulator {
+  /// Applies a single event to the message being built.
+  pub fn apply(&mut self, event: Event) -> Result<(), Error> {
+    match event {
+      Event::MessageStart { message } => self.message = Some(message),
+      Event::ContentBlockStart {
+        index,
+        content_block,
+      } => {
+        let content = self.content_mut()?;
+        if index != content.len() {
+          return Err(Error::StreamError(format!(
+            "content block {} started out of order",
+            index
+          )));
+        }
+        content.push(content_block);
+      }
+      Event::ContentBlockDelta { index, delta } => match delta {
+        Delta::InputJson { partial_json } => {
+          self
+            .partial_json
+            .entry(index)
+            .or_default()
+            .push_str(&partial_json);
+        }
+        delta => {
+          let block = self
+            .content_mut()?
+            .get_mut(index)
+            .ok_or_else(|| Error::StreamError(format!("delta for unknown block {}", index)))?;
+
+          match (block, delta) {
+            (Content::Text { text, .. }, Delta::Text { text: more }) => text.push_str(&more),
+            (Content::Text { citations, .. }, Delta::Citations { citation }) => {
+              citations.get_or_insert_with(Vec::new).push(citation)
+            }
+            (Content::Thinking { thinking, .. }, Delta::Thinking { thinking: more }) => {
+              thinking.push_str(&more)
+            }
+            (Content::Thinking { signature, .. }, Delta::Signature { signature: sig }) => {
+              signature.push_str(&sig)
+            }
+            _ => {}
+          }
+        }
+      },
+      Event::ContentBlockStop { index } => {
+        if let Some(json) = self.partial_json.remove(&index) {
+          let input = if json.is_empty() {
+            serde_json::json!({})
+          } else {
+            serde_json::from_str(&json)?
+          };
+
+          match self.content_mut()?.get_mut(index) {
+            Some(Content::ToolUse { input: slot, .. })
+            | Some(Content::ServerToolUse { input: slot, .. }) => *slot = input,
+            _ => {}
+          }
+        }
+      }
+      Event::MessageDelta { delta, usage: u } => {
+        if let Some(Response::Message {
+          stop_reason,
+          stop_sequence,
+          usage,
+          ..
+        }) = &mut self.message
+        {
+          *stop_reason = delta.stop_reason;
+          *stop_sequence = delta.stop_sequence;
+          usage.output_tokens = u.output_tokens;
+        }
+      }
+      Event::MessageStop => self.complete = true,
+      Event::Ping => {}
+      Event::Error { error } => return Err(error.into()),
+    }
+
+    Ok(())
+  }
+
+  /// Returns the assembled message once the stream has finished.
+  pub fn finish(self) -> Result<Response, Error> {
+    match self.message {
+      Some(message) if self.complete => Ok(message),
+      _ => Err(Error::StreamError(
+        "stream ended before message_stop".into(),
+      )),
+    }
+  }
+
+  fn content_mut(&mut self) -> Result<&mut Vec<Content>, Error> {
+    match &mut self.message {
+      Some(Response::Message { content, .. }) => Ok(content),
+      _ => Err(Error::StreamError(
+        "content received before message_start".into(),
+      )),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const STREAM: &str = concat!(
+    "event: message_start\n",
+    "data: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_1\",\"type\":\"message\",\"role\":\"assistant\",\"content\":[],\"model\":\"claude-sonnet-4-5-20250929\",\"stop_reason\":null,\"stop_sequence\":null,\"usage\":{\"input_tokens\":25,\"output_tokens\":1}}}\n\n",
+    "event: content_block_start\n",
+    "data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n",
+    "event: ping\n",
+    "data: {\"type\":\"ping\"}\n\n",
+    "event: content_block_delta\n",
+    "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Let me \"}}\n\n",
+    "event: content_block_delta\n",
+    "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"check. ✅\"}}\n\n",
+    "event: content_block_stop\n",
+    "data: {\"type\":\"content_block_stop\",\"index\":0}\n\n",
+    "event: content_block_start\n",
+    "data: {\"type\":\"content_block_start\",\"index\":1,\"content_block\":{\"type\":\"tool_use\",\"id\":\"toolu_1\",\"name\":\"fetch_url\",\"input\":{}}}\n\n",
+    "event: content_block_delta\n",
+    "data: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"{\\\"url\\\": \\\"https://exa\"}}\n\n",
+    "event: content_block_delta\n",
+    "data: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"mple.com\\\"}\"}}\n\n",
+    "event: content_block_stop\n",
+    "data: {\"type\":\"content_block_stop\",\"index\":1}\n\n",
+    "event: message_delta\n",
+    "data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"tool_use\",\"stop_sequence\":null},\"usage\":{\"output_tokens\":42}}\n\n",
+    "event: message_stop\n",
+    "data: {\"type\":\"message_stop\"}\n\n",
+  );
+
+  #[test]
+  fn test_stream_accumulation() {
+    let mut parser = EventParser::default();
+    let mut acc = Accumulator::default();
+
+    // feed the stream in small chunks to exercise event and UTF-8 boundaries.
+    for chunk in STREAM.as_bytes().chunks(7) {
+      for event in parser.push(chunk).unwrap() {
+        acc.apply(event).unwrap();
+      }
+    }
+
+    match acc.finish().unwrap() {
+      Response::Message {
+        content,
+        stop_reason,
+        usage,
+        ..
+      } => {
+        assert_eq!(stop_reason.as_deref(), Some("tool_use"));
+        assert_eq!(usage.output_tokens, 42);
+        assert_eq!(
+          content,
+          vec![
+            Content::text("Let me check. ✅"),
+            Content::tool_use(
+              "toolu_1",
+              "fetch_url",
+              serde_json::json!({ "url": "https://example.com" })
+            ),
+          ]
+        );
+      }
+      other => panic!("unexpected response {:?}", other),
+    }
+  }
+
+  #[test]
+  fn test_thinking_stream() {
+    let events = [
+      r#"{"type":"message_start","message":{"id":"msg_2","type":"message","role":"assistant","content":[],"model":"claude-sonnet-4-5-20250929","stop_reason":null,"stop_sequence":null,"usage":{"input_tokens":5,"output_tokens":1}}}"#,
+      r#"{"type":"content_block_start","index":0,"content_block":{"type":"thinking","thinking":""}}"#,
+      r#"{"type":"content_block_delta","index":0,"delta":{"type":"thinking_delta","thinking":"2 + 2 "}}"#,
+      r#"{"type":"content_block_delta","index":0,"delta":{"type":"thinking_delta","thinking":"is 4"}}"#,
+      r#"{"type":"content_block_delta","index":0,"delta":{"type":"signature_delta","signature":"c2ln"}}"#,
+      r#"{"type":"content_block_stop","index":0}"#,
+      r#"{"type":"content_block_start","index":1,"content_block":{"type":"redacted_thinking","data":"b3BhcXVl"}}"#,
+      r#"{"type":"content_block_stop","index":1}"#,
+      r#"{"type":"message_stop"}"#,
+    ];
+    let mut acc = Accumulator::default();
+
+    for event in events {
+      acc.apply(serde_json::from_str(event).unwrap()).unwrap();
+    }
+
+    match acc.finish().unwrap() {
+      Response::Message { content, .. } => assert_eq!(
+        content,
+        vec![
+          Content::Thinking {
+            thinking: "2 + 2 is 4".into(),
+            signature: "c2ln".into(),
+          },
+          Content::RedactedThinking {
+            data: "b3BhcXVl".into(),
+          },
+        ]
+      ),
+      other => panic!("unexpected response {:?}", other),
+    }
+  }
+
+  #[test]
+  fn test_truncated_stream() {
+    let mut parser = EventParser::default();
+    let mut acc = Accumulator::default();
+    let (head, _) = STREAM.split_at(STREAM.find("event: message_delta").unwrap());
+
+    for event in parser.push(head.as_bytes()).unwrap() {
+      acc.apply(event).unwrap();
+    }
+
+    assert!(acc.finish().is_err());
+  }
+}
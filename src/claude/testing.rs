@@ -0,0 +1,331 @@
+//! An in-process stand-in for the Anthropic API.
+//! Tests script the responses the server should give, point a `Client` at it,
+//! and then inspect the requests it received.
+
+use super::{Client, Content, Model};
+use serde_json::{Value, json};
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// A single scripted reply, served in order of arrival.
+#[derive(Clone, Debug)]
+pub enum Scripted {
+  /// A `Response::Message`. Served as server-sent events when the request asks to stream.
+  Message {
+    content: Vec<Content>,
+    stop_reason: String,
+  },
+  /// A `Response::Error` with the given HTTP status.
+  Error {
+    status: u16,
+    kind: String,
+    message: String,
+  },
+  /// An arbitrary status and body, e.g. a bare 500 from a proxy.
+  Raw {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+  },
+  /// Accepts the request and never answers, like a hung connection.
+  Stall,
+}
+
+impl Scripted {
+  /// A message that ends its turn normally.
+  pub fn message(content: Vec<Content>) -> Self {
+    Self::Message {
+      content,
+      stop_reason: "end_turn".into(),
+    }
+  }
+
+  /// A message that stops in order to use a tool.
+  pub fn tool_use(content: Vec<Content>) -> Self {
+    Self::Message {
+      content,
+      stop_reason: "tool_use".into(),
+    }
+  }
+
+  pub fn error<S: Into<String>>(status: u16, kind: S, message: S) -> Self {
+    Self::Error {
+      status,
+      kind: kind.into(),
+      message: message.into(),
+    }
+  }
+
+  /// A successful response with an arbitrary body, for endpoints other than Messages.
+  pub fn body<S: Into<String>>(body: S) -> Self {
+    Self::Raw {
+      status: 200,
+      headers: vec![],
+      body: body.into(),
+    }
+  }
+
+  pub fn status(status: u16) -> Self {
+    Self::Raw {
+      status,
+      headers: vec![],
+      body: String::new(),
+    }
+  }
+}
+
+/// A request as received by the fake server.
+#[derive(Clone, Debug)]
+pub struct Recorded {
+  pub method: String,
+  pub path: String,
+  pub headers: HashMap<String, String>,
+  /// The body parsed as JSON, or `Null` if it isn't JSON.
+  pub body: Value,
+  pub raw: Vec<u8>,
+}
+
+pub struct FakeServer {
+  addr: SocketAddr,
+  requests: Arc<Mutex<Vec<Recorded>>>,
+}
+
+impl FakeServer {
+  /// Binds to an ephemeral local port and serves `script` one reply per request.
+  /// Requests beyond the end of the script receive a 500.
+  pub async fn start(script: Vec<Scripted>) -> Self {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let requests = Arc::new(Mutex::new(vec![]));
+    let script = Arc::new(Mutex::new(VecDeque::from(script)));
+
+    let recorded = requests.clone();
+    tokio::spawn(async move {
+      while let Ok((stream, _)) = listener.accept().await {
+        let recorded = recorded.clone();
+        let script = script.clone();
+        tokio::spawn(async move { serve(stream, recorded, script).await });
+      }
+    });
+
+    Self { addr, requests }
+  }
+
+  pub fn url(&self) -> String {
+    format!("http://{}", self.addr)
+  }
+
+  /// A client pointed at this server that retries quickly.
+  pub fn client(&self) -> Client {
+    Client::builder("test-key", Model::HAIKU_45)
+      .base_url(self.url())
+      .retry_bounds(Duration::from_millis(1), Duration::from_millis(10))
+      .build()
+  }
+
+  pub fn requests(&self) -> Vec<Recorded> {
+    self.requests.lock().unwrap().clone()
+  }
+}
+
+async fn serve(
+  mut stream: TcpStream,
+  recorded: Arc<Mutex<Vec<Recorded>>>,
+  script: Arc<Mutex<VecDeque<Scripted>>>,
+) {
+  let Some(req) = read_request(&mut stream).await else {
+    return;
+  };
+  let streaming = req.body.get("stream") == Some(&Value::Bool(true));
+  recorded.lock().unwrap().push(req);
+
+  let reply = script.lock().unwrap().pop_front().unwrap_or(Scripted::Raw {
+    status: 500,
+    headers: vec![],
+    body: "script exhausted".into(),
+  });
+
+  let (status, content_type, headers, body) = match reply {
+    Scripted::Stall => {
+      tokio::time::sleep(Duration::from_secs(3_600)).await;
+      return;
+    }
+    Scripted::Message {
+      content,
+      stop_reason,
+    } if streaming => (
+      200,
+      "text/event-stream",
+      vec![],
+      event_stream(content, stop_reason),
+    ),
+    Scripted::Message {
+      content,
+      stop_reason,
+    } => (
+      200,
+      "application/json",
+      vec![],
+      message(content, Some(stop_reason)).to_string(),
+    ),
+    Scripted::Error {
+      status,
+      kind,
+      message,
+    } => (
+      status,
+      "application/json",
+      vec![],
+      json!({ "type": "error", "error": { "type": kind, "message": message } }).to_string(),
+    ),
+    Scripted::Raw {
+      status,
+      headers,
+      body,
+    } => (status, "text/plain", headers, body),
+  };
+
+  let mut head = format!(
+    "HTTP/1.1 {} Scripted\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+    status,
+    content_type,
+    body.len()
+  );
+  for (name, value) in headers {
+    head.push_str(&format!("{}: {}\r\n", name, value));
+  }
+  head.push_str("\r\n");
+
+  stream.write_all(head.as_bytes()).await.ok();
+  stream.write_all(body.as_bytes()).await.ok();
+  stream.shutdown().await.ok();
+}
+
+async fn read_request(stream: &mut TcpStream) -> Option<Recorded> {
+  let mut buf = vec![];
+  let mut chunk = [0u8; 4096];
+
+  let header_end = loop {
+    if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+      break pos + 4;
+    }
+    let n = stream.read(&mut chunk).await.ok()?;
+    if n == 0 {
+      return None;
+    }
+    buf.extend_from_slice(&chunk[..n]);
+  };
+
+  let head = String::from_utf8_lossy(&buf[..header_end]).to_string();
+  let mut lines = head.lines();
+  let mut start = lines.next()?.split_whitespace();
+  let method = start.next()?.to_owned();
+  let path = start.next()?.to_owned();
+  let headers = lines
+    .filter_map(|line| line.split_once(':'))
+    .map(|(k, v)| (k.trim().to_lowercase(), v.trim().to_owned()))
+    .collect::<HashMap<_, _>>();
+
+  let len = headers
+    .get("content-length")
+    .and_then(|v| v.parse::<usize>().ok())
+    .unwrap_or(0);
+  while buf.len() < header_end + len {
+    let n = stream.read(&mut chunk).await.ok()?;
+    if n == 0 {
+      break;
+    }
+    buf.extend_from_slice(&chunk[..n]);
+  }
+
+  let raw = buf[header_end..].to_vec();
+  let body = serde_json::from_slice(&raw).unwrap_or(Value::Null);
+
+  Some(Recorded {
+    method,
+    path,
+    headers,
+    body,
+    raw,
+  })
+}
+
+fn message(content: Vec<Content>, stop_reason: Option<String>) -> Value {
+  json!({
+    "id": "msg_fake",
+    "type": "message",
+    "role": "assistant",
+    "model": "claude-haiku-4-5-20251001",
+    "content": content,
+    "stop_reason": stop_reason,
+    "stop_sequence": null,
+    "usage": { "input_tokens": 10, "output_tokens": 20 },
+  })
+}
+
+/// Renders a message as the event sequence the streaming endpoint would produce.
+fn event_stream(content: Vec<Content>, stop_reason: String) -> String {
+  let mut events = vec![json!({
+    "type": "message_start",
+    "message": message(vec![], None),
+  })];
+
+  for (index, block) in content.into_iter().enumerate() {
+    match block {
+      Content::Text { text, .. } => {
+        events.push(json!({
+          "type": "content_block_start",
+          "index": index,
+          "content_block": { "type": "text", "text": "" },
+        }));
+        events.push(json!({
+          "type": "content_block_delta",
+          "index": index,
+          "delta": { "type": "text_delta", "text": text },
+        }));
+      }
+      Content::ToolUse {
+        id, name, input, ..
+      } => {
+        events.push(json!({
+          "type": "content_block_start",
+          "index": index,
+          "content_block": { "type": "tool_use", "id": id, "name": name, "input": {} },
+        }));
+        events.push(json!({
+          "type": "content_block_delta",
+          "index": index,
+          "delta": { "type": "input_json_delta", "partial_json": input.to_string() },
+        }));
+      }
+      block => events.push(json!({
+        "type": "content_block_start",
+        "index": index,
+        "content_block": block,
+      })),
+    }
+    events.push(json!({ "type": "content_block_stop", "index": index }));
+  }
+
+  events.push(json!({
+    "type": "message_delta",
+    "delta": { "stop_reason": stop_reason, "stop_sequence": null },
+    "usage": { "output_tokens": 20 },
+  }));
+  events.push(json!({ "type": "message_stop" }));
+
+  events
+    .into_iter()
+    .map(|event| {
+      format!(
+        "event: {}\ndata: {}\n\n",
+        event["type"].as_str().unwrap(),
+        event
+      )
+    })
+    .collect()
+}
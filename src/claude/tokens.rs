@@ -0,0 +1,122 @@
+//! Rough local token counts, for when asking the `count_tokens` endpoint isn't worth
+//! a round trip or the API can't be reached. Estimates err on the high side.
+
+use super::{Content, DocumentSource, Interaction, Tool};
+
+/// Average characters per token for English text.
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Images are resized to fit in 600x600 before upload, which costs about (600 * 600) / 750 tokens.
+const IMAGE_TOKENS: usize = 480;
+
+/// Each PDF page is sent as both its extracted text and an image of the page.
+const PDF_PAGE_TOKENS: usize = 2_000;
+
+/// Base64 characters per page assumed for a PDF whose pages weren't counted, about 50 KB each.
+const PDF_BASE64_PER_PAGE: usize = 68_000;
+
+/// Role markers and other framing the API adds around each turn.
+const TURN_OVERHEAD: usize = 4;
+
+pub fn estimate_text(text: &str) -> usize {
+  text.chars().count().div_ceil(CHARS_PER_TOKEN)
+}
+
+pub fn estimate_content(content: &Content) -> usize {
+  match content {
+    Content::Text { text, .. } => estimate_text(text),
+    Content::Image { .. } => IMAGE_TOKENS,
+    Content::Thinking { thinking, .. } => estimate_text(thinking),
+    Content::ToolResult {
+      tool_use_id,
+      content,
+      ..
+    } => estimate_text(tool_use_id) + content.iter().map(estimate_content).sum::<usize>(),
+    Content::Document { source, .. } => match source {
+      DocumentSource::Text { data, .. } => estimate_text(data),
+      DocumentSource::Base64 { data, pages, .. } => {
+        PDF_PAGE_TOKENS * pages.unwrap_or_else(|| data.len().div_ceil(PDF_BASE64_PER_PAGE))
+      }
+    },
+    // everything else is structured; its JSON form is a reasonable stand-in.
+    other => estimate_text(&serde_json::to_string(other).unwrap_or_default()),
+  }
+}
+
+/// Counts the page objects in a PDF, or assumes a single page if there are none.
+pub fn count_pdf_pages(bytes: &[u8]) -> usize {
+  // page objects are tagged `/Type /Page`; the page tree nodes are `/Type /Pages`.
+  let pages = [&b"/Type /Page"[..], &b"/Type/Page"[..]]
+    .iter()
+    .map(|tag| {
+      bytes
+        .windows(tag.len() + 1)
+        .filter(|w| w.starts_with(tag) && w[tag.len()] != b's')
+        .count()
+    })
+    .sum::<usize>();
+
+  pages.max(1)
+}
+
+pub fn estimate_interaction(interaction: &Interaction) -> usize {
+  TURN_OVERHEAD
+    + interaction
+      .content
+      .iter()
+      .map(estimate_content)
+      .sum::<usize>()
+}
+
+pub fn estimate_tools(tools: &[Tool]) -> usize {
+  tools
+    .iter()
+    .map(|tool| estimate_text(&serde_json::to_string(tool).unwrap_or_default()))
+    .sum()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::claude::{ImageSource, Role};
+
+  #[test]
+  fn test_estimates() {
+    assert_eq!(estimate_text(""), 0);
+    assert_eq!(estimate_text("hello"), 2);
+    // characters, not bytes.
+    assert_eq!(estimate_text("✅✅✅✅"), 1);
+
+    let turn = Interaction {
+      role: Role::User,
+      content: vec![
+        Content::text("12345678"),
+        Content::image(ImageSource::Base64 {
+          media_type: "image/png".into(),
+          data: "A".repeat(100_000),
+        }),
+      ],
+    };
+    assert_eq!(
+      estimate_interaction(&turn),
+      TURN_OVERHEAD + 2 + IMAGE_TOKENS
+    );
+
+    let pdf = b"<< /Type /Pages /Count 2 >> << /Type /Page >> << /Type/Page >>";
+    let doc = Content::document(DocumentSource::pdf(pdf), "a.pdf");
+    assert_eq!(estimate_content(&doc), 2 * PDF_PAGE_TOKENS);
+
+    let DocumentSource::Base64 { data, .. } = DocumentSource::pdf(pdf) else {
+      unreachable!()
+    };
+    let uncounted = DocumentSource::Base64 {
+      media_type: "application/pdf".into(),
+      data,
+      pages: None,
+    };
+    assert_eq!(
+      estimate_content(&Content::document(uncounted, "a.pdf")),
+      PDF_PAGE_TOKENS
+    );
+  }
+}
@@ -0,0 +1,455 @@
+//! `fetch_url`: reads a web page and hands the model a readable, size-limited version of it.
+
+use super::guard::Guard;
+use super::{Tool, ToolMetadata, ToolOutput, parse_input, readable};
+use crate::claude::{DocumentSource, Schema};
+use async_trait::async_trait;
+use reqwest::Url;
+use reqwest::header::{CONTENT_TYPE, LOCATION};
+use reqwest::redirect::Policy;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Characters of a page handed to the model, unless the tool is configured otherwise.
+pub const DEFAULT_MAX_CHARS: usize = 8_000;
+
+/// Longest a fetch may take, including reading the body.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(20);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Redirects followed before giving up.
+const MAX_REDIRECTS: usize = 5;
+
+/// Bodies larger than this aren't read at all.
+const MAX_BODY_BYTES: usize = 10 * 1024 * 1024;
+
+/// PDFs are sent to the model whole, and stay in the conversation, so they get a tighter limit.
+const MAX_PDF_BYTES: usize = 2 * 1024 * 1024;
+
+crate::json_schema! {
+  pub struct FetchInput {
+    /// the full path to a website to retrieve, starting with http:// or https://
+    pub url: String,
+  }
+}
+
+pub struct FetchTool {
+  metadata: ToolMetadata,
+  http: reqwest::Client,
+  guard: Guard,
+  max_chars: usize,
+}
+
+impl FetchTool {
+  /// A tool that only fetches what `guard` allows, using the shared client for that guard.
+  pub fn new(clients: &mut FetchClients, guard: Guard) -> Self {
+    Self {
+      metadata: ToolMetadata::Custom {
+        name: "fetch_url".into(),
+        description: "Retrieve the main content of a given webpage as markdown, or the document itself for PDFs.  This tool should only be used when you are explicitly asked to fetch a webpage.".into(),
+        input_schema: Box::new(Schema::of::<FetchInput>()),
+        cache_control: None,
+      },
+      http: clients.get(&guard),
+      guard,
+      max_chars: DEFAULT_MAX_CHARS,
+    }
+  }
+
+  /// Caps how much of a page's text is handed to the model.
+  pub fn max_chars(mut self, max_chars: usize) -> Self {
+    self.max_chars = max_chars;
+    self
+  }
+}
+
+/// HTTP clients for `fetch_url`, one per guard, kept so that connections and TLS sessions
+/// are reused across messages. Clones of a client share its connection pool.
+#[derive(Default)]
+pub struct FetchClients(HashMap<Guard, reqwest::Client>);
+
+impl FetchClients {
+  pub fn get(&mut self, guard: &Guard) -> reqwest::Client {
+    self
+      .0
+      .entry(guard.clone())
+      .or_insert_with(|| http_client(guard))
+      .clone()
+  }
+}
+
+/// A client that refuses to connect to internal addresses and leaves redirects to the
+/// caller, so each hop can be checked against the guard.
+fn http_client(guard: &Guard) -> reqwest::Client {
+  reqwest::Client::builder()
+    .timeout(FETCH_TIMEOUT)
+    .connect_timeout(CONNECT_TIMEOUT)
+    .redirect(Policy::none())
+    .dns_resolver(Arc::new(guard.clone()))
+    .build()
+    .expect("failed to build the HTTP client")
+}
+
+#[async_trait]
+impl Tool for FetchTool {
+  fn metadata(&self) -> &ToolMetadata {
+    &self.metadata
+  }
+
+  async fn invoke(&mut self, params: serde_json::Value) -> Result<ToolOutput, String> {
+    let FetchInput { url } = parse_input(params)?;
+    let mut url = Url::parse(&url).map_err(|e| format!("`{}` isn't a valid URL: {}", url, e))?;
+
+    let mut redirects = 0;
+    let resp = loop {
+      self.guard.check(&url).await?;
+      let resp = self
+        .http
+        .get(url.clone())
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+      let location = resp
+        .headers()
+        .get(LOCATION)
+        .and_then(|value| value.to_str().ok());
+      match location {
+        Some(location) if resp.status().is_redirection() => {
+          redirects += 1;
+          if redirects > MAX_REDIRECTS {
+            return Err(format!("Gave up after {} redirects.", MAX_REDIRECTS));
+          }
+          url = url
+            .join(location)
+            .map_err(|e| format!("Bad redirect to `{}`: {}", location, e))?;
+        }
+        _ => break resp.error_for_status().map_err(|e| e.to_string())?,
+      }
+    };
+
+    let content_type = resp
+      .headers()
+      .get(CONTENT_TYPE)
+      .and_then(|value| value.to_str().ok())
+      .unwrap_or_default()
+      .to_owned();
+    let body = read_body(resp).await?;
+
+    // links are resolved against where the page ended up, after any redirects.
+    render(&content_type, &url, &body, self.max_chars)
+  }
+
+  fn timeout(&self) -> Duration {
+    FETCH_TIMEOUT
+  }
+}
+
+/// Reads the whole body, giving up once it passes `MAX_BODY_BYTES`.
+async fn read_body(mut resp: reqwest::Response) -> Result<Vec<u8>, String> {
+  let too_large = || {
+    format!(
+      "The page is larger than {} MB.",
+      MAX_BODY_BYTES / 1024 / 1024
+    )
+  };
+  if resp
+    .content_length()
+    .is_some_and(|len| len > MAX_BODY_BYTES as u64)
+  {
+    return Err(too_large());
+  }
+
+  let mut body = vec![];
+  while let Some(chunk) = resp.chunk().await.map_err(|e| e.to_string())? {
+    body.extend_from_slice(&chunk);
+    if body.len() > MAX_BODY_BYTES {
+      return Err(too_large());
+    }
+  }
+  Ok(body)
+}
+
+/// Turns a fetched body into something the model can read, according to its content type.
+fn render(
+  content_type: &str,
+  url: &Url,
+  body: &[u8],
+  max_chars: usize,
+) -> Result<ToolOutput, String> {
+  let mime = content_type
+    .split(';')
+    .next()
+    .unwrap_or_default()
+    .trim()
+    .to_ascii_lowercase();
+  let text = || String::from_utf8_lossy(body);
+
+  match mime.as_str() {
+    // the model reads PDFs itself, so they're passed along whole.
+    "application/pdf" => {
+      if body.len() > MAX_PDF_BYTES {
+        return Err(format!(
+          "The PDF is too big to read ({} MB, the limit is {} MB).",
+          body.len().div_ceil(1024 * 1024),
+          MAX_PDF_BYTES / (1024 * 1024)
+        ));
+      }
+      let title = url
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|name| !name.is_empty())
+        .unwrap_or(url.as_str());
+      Ok(ToolOutput::default().with_document(DocumentSource::pdf(body), title))
+    }
+    "application/json" | "text/json" => json(body, max_chars),
+    mime if mime.ends_with("+json") => json(body, max_chars),
+    // servers that don't say are usually serving HTML.
+    "" | "text/html" | "application/xhtml+xml" => {
+      let page = readable::extract(&text(), Some(url));
+      if page.markdown.trim().is_empty() {
+        return Err(
+          "The page has no readable text. It may need JavaScript to show anything.".into(),
+        );
+      }
+      Ok(ToolOutput::text(truncate(&page.to_markdown(), max_chars)))
+    }
+    mime if mime.starts_with("text/") || mime == "application/xml" => {
+      Ok(ToolOutput::text(truncate(&text(), max_chars)))
+    }
+    mime => Err(format!("Can't read `{}` content.", mime)),
+  }
+}
+
+/// Pretty-prints JSON in a code block, so the truncation note stays outside of it.
+fn json(body: &[u8], max_chars: usize) -> Result<ToolOutput, String> {
+  let text = serde_json::from_slice::<serde_json::Value>(body)
+    .ok()
+    .and_then(|value| serde_json::to_string_pretty(&value).ok())
+    .unwrap_or_else(|| String::from_utf8_lossy(body).into_owned());
+
+  let shown = cut(&text, max_chars);
+  let mut out = format!("```json\n{}\n```", shown.trim_end());
+  if shown.len() < text.len() {
+    out.push_str("\n\n");
+    out.push_str(&omitted(shown, &text));
+  }
+  Ok(ToolOutput::text(out))
+}
+
+/// Shortens `text` to at most `max_chars` characters, noting how much was left out.
+pub fn truncate(text: &str, max_chars: usize) -> String {
+  let shown = cut(text, max_chars);
+  if shown.len() == text.len() {
+    return text.to_owned();
+  }
+  format!("{}\n\n{}", shown.trim_end(), omitted(shown, text))
+}
+
+/// The first `max_chars` characters of `text`, ending at a paragraph, line or word break
+/// if there's one close enough to the end.
+fn cut(text: &str, max_chars: usize) -> &str {
+  let Some((end, _)) = text.char_indices().nth(max_chars) else {
+    return text;
+  };
+  let head = &text[..end];
+
+  // only back off to a break in the last fifth, so the page isn't cut short by much.
+  let floor = head
+    .char_indices()
+    .nth(max_chars * 4 / 5)
+    .map_or(0, |(i, _)| i);
+  for separator in ["\n\n", "\n", " "] {
+    if let Some(i) = head.rfind(separator)
+      && i >= floor
+    {
+      return &head[..i];
+    }
+  }
+  head
+}
+
+fn omitted(shown: &str, text: &str) -> String {
+  format!(
+    "[truncated: showing {} of {} characters]",
+    shown.chars().count(),
+    text.chars().count()
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::claude::Content;
+  use crate::claude::testing::{FakeServer, Scripted};
+  use serde_json::json;
+  use std::net::Ipv4Addr;
+
+  fn text_of(output: ToolOutput) -> String {
+    match &output.content[..] {
+      [Content::Text { text, .. }] => text.clone(),
+      other => panic!("expected one text block, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn test_truncate() {
+    assert_eq!(truncate("short", 10), "short");
+
+    // cutting by bytes would split the multi-byte characters.
+    let text = "ä".repeat(30);
+    assert_eq!(
+      truncate(&text, 10),
+      format!(
+        "{}\n\n[truncated: showing 10 of 30 characters]",
+        "ä".repeat(10)
+      )
+    );
+
+    let text = "Erster Absatz über Spätzle.\n\nZweiter Absatz, der länger ist.";
+    assert_eq!(
+      truncate(text, 32),
+      "Erster Absatz über Spätzle.\n\n[truncated: showing 27 of 60 characters]"
+    );
+    assert_eq!(
+      truncate("one two three four five six", 16),
+      "one two three\n\n[truncated: showing 13 of 27 characters]"
+    );
+  }
+
+  #[test]
+  fn test_render_by_content_type() {
+    let url = Url::parse("https://example.com/docs/report.pdf").unwrap();
+
+    let html = b"<html><head><title>Hi</title><script>evil()</script></head><body><p>Hello <a href=\"/there\">there</a></p></body></html>";
+    let output = render("text/html; charset=utf-8", &url, html, 100).unwrap();
+    assert_eq!(
+      text_of(output),
+      "# Hi\n\nHello [there](https://example.com/there)"
+    );
+
+    let output = render("application/json", &url, br#"{"a":[1,2]}"#, 100).unwrap();
+    assert_eq!(
+      text_of(output),
+      "```json\n{\n  \"a\": [\n    1,\n    2\n  ]\n}\n```"
+    );
+    let output = render("application/problem+json", &url, br#"{"title":"nope"}"#, 10).unwrap();
+    assert_eq!(
+      text_of(output),
+      "```json\n{\n  \"title\n```\n\n[truncated: showing 10 of 21 characters]"
+    );
+
+    let output = render("text/plain", &url, "naïve <b>text</b>".as_bytes(), 100).unwrap();
+    assert_eq!(text_of(output), "naïve <b>text</b>");
+
+    let output = render("application/pdf", &url, b"%PDF-1.4", 100).unwrap();
+    assert_eq!(
+      output,
+      ToolOutput::default().with_document(DocumentSource::pdf(b"%PDF-1.4"), "report.pdf")
+    );
+
+    assert_eq!(
+      render("application/pdf", &url, &vec![0; 5 * 1024 * 1024], 100),
+      Err("The PDF is too big to read (5 MB, the limit is 2 MB).".into())
+    );
+
+    assert_eq!(
+      render("image/png", &url, b"\x89PNG", 100),
+      Err("Can't read `image/png` content.".into())
+    );
+    assert!(render("text/html", &url, b"<script>app()</script>", 100).is_err());
+  }
+
+  fn redirect(location: &str) -> Scripted {
+    Scripted::Raw {
+      status: 302,
+      headers: vec![("Location".into(), location.into())],
+      body: String::new(),
+    }
+  }
+
+  /// A tool that may fetch from the fake server, and nowhere else internal.
+  fn trusting_loopback() -> FetchTool {
+    FetchTool::new(
+      &mut FetchClients::default(),
+      Guard {
+        trusted: vec![Ipv4Addr::LOCALHOST.into()],
+        ..Default::default()
+      },
+    )
+  }
+
+  #[tokio::test]
+  async fn test_fetch_checks_redirects() {
+    let server = FakeServer::start(vec![
+      redirect("/moved"),
+      Scripted::body("made it"),
+      redirect("http://169.254.169.254/latest/meta-data/"),
+    ])
+    .await;
+    let mut tool = trusting_loopback();
+
+    let output = tool
+      .invoke(json!({ "url": format!("{}/start", server.url()) }))
+      .await;
+    assert_eq!(output, Ok(ToolOutput::text("made it")));
+
+    let output = tool
+      .invoke(json!({ "url": format!("{}/again", server.url()) }))
+      .await;
+    assert_eq!(
+      output,
+      Err("`169.254.169.254` is an internal address.".into())
+    );
+
+    let paths = server
+      .requests()
+      .into_iter()
+      .map(|r| r.path)
+      .collect::<Vec<_>>();
+    assert_eq!(paths, vec!["/start", "/moved", "/again"]);
+  }
+
+  #[tokio::test]
+  async fn test_fetch_refuses_internal_addresses() {
+    let server = FakeServer::start(vec![Scripted::body("secret")]).await;
+    let mut tool = FetchTool::new(&mut FetchClients::default(), Guard::default());
+
+    assert_eq!(
+      tool.invoke(json!({ "url": server.url() })).await,
+      Err("`127.0.0.1` is an internal address.".into())
+    );
+    let port = server.url().rsplit(':').next().unwrap().to_owned();
+    assert_eq!(
+      tool
+        .invoke(json!({ "url": format!("http://localhost:{}/", port) }))
+        .await,
+      Err("`localhost` is an internal address.".into())
+    );
+    assert_eq!(
+      tool.invoke(json!({ "url": "gopher://example.com/" })).await,
+      Err("Only http and https URLs can be fetched, not `gopher`.".into())
+    );
+    assert!(server.requests().is_empty());
+  }
+
+  #[tokio::test]
+  async fn test_fetch_limits() {
+    let mut script = vec![redirect("/loop"); MAX_REDIRECTS + 1];
+    script.push(Scripted::body("x".repeat(MAX_BODY_BYTES + 1)));
+    let server = FakeServer::start(script).await;
+    let mut tool = trusting_loopback();
+
+    assert_eq!(
+      tool.invoke(json!({ "url": server.url() })).await,
+      Err("Gave up after 5 redirects.".into())
+    );
+    assert_eq!(server.requests().len(), MAX_REDIRECTS + 1);
+
+    assert_eq!(
+      tool.invoke(json!({ "url": server.url() })).await,
+      Err("The page is larger than 10 MB.".into())
+    );
+  }
+}
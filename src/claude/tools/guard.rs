@@ -0,0 +1,312 @@
+//! Keeps `fetch_url` on the public internet. The model picks the URLs, so without this a
+//! user could have the bot read internal services or the host's metadata endpoints.
+//! Every hop is checked before it's requested, and the HTTP client's resolver refuses
+//! internal addresses too, so a hostname can't pass the check and then resolve elsewhere.
+
+use reqwest::Url;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+/// Hostnames that only mean something inside the host's network, like fly.io's `.internal`.
+const INTERNAL_NAMES: &[&str] = &["localhost", "internal", "local", "localdomain"];
+
+/// Where the tool may and may not fetch from.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Guard {
+  /// If set, only these domains and their subdomains can be fetched.
+  pub allowed_domains: Option<Vec<String>>,
+  /// Domains, and their subdomains, that are never fetched.
+  pub blocked_domains: Option<Vec<String>>,
+  /// Addresses exempt from the internal address check, e.g. a test server on loopback.
+  pub trusted: Vec<IpAddr>,
+}
+
+impl Guard {
+  /// Checks that `url` may be fetched, resolving its host to make sure it's public.
+  pub async fn check(&self, url: &Url) -> Result<(), String> {
+    if !matches!(url.scheme(), "http" | "https") {
+      return Err(format!(
+        "Only http and https URLs can be fetched, not `{}`.",
+        url.scheme()
+      ));
+    }
+
+    let host = url
+      .host_str()
+      .ok_or_else(|| format!("`{}` has no host.", url))?;
+    // IPv6 hosts keep their brackets in URLs.
+    match host.trim_start_matches('[').trim_end_matches(']').parse() {
+      Ok(ip) => {
+        self.check_ip(ip)?;
+        self.check_listed_ip(ip)
+      }
+      Err(_) => {
+        self.check_domain(host)?;
+        self
+          .lookup(host, url.port_or_known_default().unwrap_or(80))
+          .await
+          .map(|_| ())
+      }
+    }
+  }
+
+  fn check_domain(&self, domain: &str) -> Result<(), String> {
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+    let matches = |list: &Option<Vec<String>>| {
+      list.iter().flatten().any(|entry| {
+        let entry = entry.trim_start_matches("*.").to_ascii_lowercase();
+        domain == entry || domain.ends_with(&format!(".{}", entry))
+      })
+    };
+
+    if INTERNAL_NAMES
+      .iter()
+      .any(|name| domain == *name || domain.ends_with(&format!(".{}", name)))
+    {
+      return Err(format!("`{}` is an internal address.", domain));
+    }
+    if matches(&self.blocked_domains) {
+      return Err(format!("`{}` is blocked in this server.", domain));
+    }
+    if self.allowed_domains.is_some() && !matches(&self.allowed_domains) {
+      return Err(format!("`{}` isn't on this server's allow list.", domain));
+    }
+    Ok(())
+  }
+
+  /// The lists name domains, so a URL with a bare address would get around them. With
+  /// either list set, an address has to be on the allow list itself to be fetched.
+  fn check_listed_ip(&self, ip: IpAddr) -> Result<(), String> {
+    let listed = |list: &Option<Vec<String>>| {
+      list
+        .iter()
+        .flatten()
+        .any(|entry| entry.trim().parse::<IpAddr>() == Ok(ip))
+    };
+
+    if listed(&self.blocked_domains) {
+      return Err(format!("`{}` is blocked in this server.", ip));
+    }
+    match (&self.allowed_domains, &self.blocked_domains) {
+      (None, None) => Ok(()),
+      (Some(_), _) if listed(&self.allowed_domains) => Ok(()),
+      _ => Err(format!(
+        "`{}` is an IP address, and this server only fetches pages by domain name.",
+        ip
+      )),
+    }
+  }
+
+  fn check_ip(&self, ip: IpAddr) -> Result<(), String> {
+    match is_public(ip) || self.trusted.contains(&ip) {
+      true => Ok(()),
+      false => Err(format!("`{}` is an internal address.", ip)),
+    }
+  }
+
+  /// Resolves `host`, failing if any of its addresses are internal.
+  async fn lookup(&self, host: &str, port: u16) -> Result<Vec<SocketAddr>, String> {
+    let addrs = tokio::net::lookup_host((host, port))
+      .await
+      .map_err(|e| format!("Couldn't resolve `{}`: {}", host, e))?
+      .collect::<Vec<_>>();
+
+    for addr in &addrs {
+      self
+        .check_ip(addr.ip())
+        .map_err(|_| format!("`{}` resolves to an internal address.", host))?;
+    }
+    Ok(addrs)
+  }
+}
+
+impl Resolve for Guard {
+  fn resolve(&self, name: Name) -> Resolving {
+    let guard = self.clone();
+    Box::pin(async move {
+      // reqwest fills in the port from the URL.
+      let addrs = guard.lookup(name.as_str(), 0).await?;
+      Ok(Box::new(addrs.into_iter()) as Addrs)
+    })
+  }
+}
+
+/// Whether `ip` is on the public internet, rather than loopback, a private network,
+/// link-local (where cloud metadata services live) or otherwise reserved.
+pub fn is_public(ip: IpAddr) -> bool {
+  match ip {
+    IpAddr::V4(ip) => is_public_v4(ip),
+    IpAddr::V6(ip) => is_public_v6(ip),
+  }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+  let [a, b, c, _] = ip.octets();
+  !(ip.is_private()
+    || ip.is_loopback()
+    || ip.is_link_local()
+    || ip.is_unspecified()
+    || ip.is_broadcast()
+    || ip.is_multicast()
+    || ip.is_documentation()
+    || a == 0
+    // shared address space used by carrier-grade NAT.
+    || (a == 100 && (64..128).contains(&b))
+    || (a, b, c) == (192, 0, 0)
+    // benchmarking.
+    || (a == 198 && (18..20).contains(&b))
+    // reserved for future use.
+    || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+  // addresses that embed an IPv4 address are as public as the address they embed.
+  if let Some(v4) = ip.to_ipv4_mapped() {
+    return is_public_v4(v4);
+  }
+  let segments = ip.segments();
+  let embedded = |hi: u16, lo: u16| Ipv4Addr::from(((hi as u32) << 16) | lo as u32);
+  match segments {
+    // NAT64.
+    [0x64, 0xff9b, 0, 0, 0, 0, hi, lo] => return is_public_v4(embedded(hi, lo)),
+    // 6to4.
+    [0x2002, hi, lo, ..] => return is_public_v4(embedded(hi, lo)),
+    _ => {}
+  }
+
+  !(ip.is_loopback()
+    || ip.is_unspecified()
+    || ip.is_multicast()
+    // unique local, which includes fly.io's private network.
+    || (segments[0] & 0xfe00) == 0xfc00
+    // link-local, and the deprecated site-local.
+    || (segments[0] & 0xffc0) == 0xfe80
+    || (segments[0] & 0xffc0) == 0xfec0
+    // documentation.
+    || segments[..2] == [0x2001, 0xdb8]
+    // IPv4-compatible, long deprecated.
+    || segments[..6] == [0, 0, 0, 0, 0, 0])
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_is_public() {
+    for ip in [
+      "127.0.0.1",
+      "10.1.2.3",
+      "172.16.0.1",
+      "192.168.1.1",
+      "169.254.169.254",
+      "100.64.0.1",
+      "0.0.0.0",
+      "0.1.2.3",
+      "255.255.255.255",
+      "224.0.0.1",
+      "::1",
+      "::",
+      "fdaa:0:1::3",
+      "fe80::1",
+      "::ffff:127.0.0.1",
+      "::ffff:169.254.169.254",
+      "64:ff9b::a00:1",
+      "2002:c0a8:101::1",
+      "2001:db8::1",
+      "::7f00:1",
+    ] {
+      assert!(!is_public(ip.parse().unwrap()), "{} should be internal", ip);
+    }
+
+    for ip in [
+      "1.1.1.1",
+      "93.184.215.14",
+      "100.128.0.1",
+      "2606:4700:4700::1111",
+      "::ffff:1.1.1.1",
+      "2002:101:101::1",
+    ] {
+      assert!(is_public(ip.parse().unwrap()), "{} should be public", ip);
+    }
+  }
+
+  #[tokio::test]
+  async fn test_check() {
+    let check =
+      |guard: Guard, url: &'static str| async move { guard.check(&Url::parse(url).unwrap()).await };
+
+    assert_eq!(
+      check(Guard::default(), "file:///etc/passwd").await,
+      Err("Only http and https URLs can be fetched, not `file`.".into())
+    );
+    assert_eq!(
+      check(Guard::default(), "http://169.254.169.254/latest/meta-data/").await,
+      Err("`169.254.169.254` is an internal address.".into())
+    );
+    // other spellings of loopback parse to the same address.
+    assert!(check(Guard::default(), "http://2130706433/").await.is_err());
+    assert!(
+      check(Guard::default(), "http://[::ffff:7f00:1]/")
+        .await
+        .is_err()
+    );
+    assert_eq!(
+      check(Guard::default(), "http://LOCALHOST./").await,
+      Err("`localhost` is an internal address.".into())
+    );
+    assert!(
+      check(Guard::default(), "http://_api.internal:4280/")
+        .await
+        .is_err()
+    );
+    assert!(check(Guard::default(), "https://1.1.1.1/").await.is_ok());
+
+    let trusted = Guard {
+      trusted: vec!["127.0.0.1".parse().unwrap()],
+      ..Default::default()
+    };
+    assert!(check(trusted, "http://127.0.0.1:8080/").await.is_ok());
+
+    let lists = Guard {
+      allowed_domains: Some(vec!["example.com".into(), "*.example.org".into()]),
+      blocked_domains: Some(vec!["private.example.com".into()]),
+      ..Default::default()
+    };
+    assert_eq!(
+      lists.check_domain("evil.com"),
+      Err("`evil.com` isn't on this server's allow list.".into())
+    );
+    assert_eq!(
+      lists.check_domain("api.private.example.com"),
+      Err("`api.private.example.com` is blocked in this server.".into())
+    );
+    assert!(lists.check_domain("docs.example.com").is_ok());
+    assert!(lists.check_domain("example.org").is_ok());
+    assert!(lists.check_domain("notexample.com").is_err());
+
+    // addresses can't be matched against domains, so either list keeps them out.
+    let not_listed =
+      Err("`1.1.1.1` is an IP address, and this server only fetches pages by domain name.".into());
+    assert_eq!(check(lists.clone(), "https://1.1.1.1/").await, not_listed);
+    let blocked = Guard {
+      blocked_domains: Some(vec!["example.com".into(), "8.8.8.8".into()]),
+      ..Default::default()
+    };
+    assert_eq!(check(blocked.clone(), "https://1.1.1.1/").await, not_listed);
+    assert_eq!(
+      check(blocked, "https://8.8.8.8/").await,
+      Err("`8.8.8.8` is blocked in this server.".into())
+    );
+    let allowed = Guard {
+      allowed_domains: Some(vec!["example.com".into(), "1.1.1.1".into()]),
+      ..Default::default()
+    };
+    assert!(check(allowed.clone(), "https://1.1.1.1/").await.is_ok());
+    assert!(
+      check(allowed, "http://[2606:4700:4700::1111]/")
+        .await
+        .is_err()
+    );
+  }
+}
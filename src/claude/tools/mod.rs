@@ -1,66 +1,169 @@
-use super::Schema;
 use super::Tool as ToolMetadata;
+use super::{Content, DocumentSource};
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+mod fetch;
+pub mod guard;
+pub mod readable;
+pub mod registry;
+mod reminders;
+
+pub use fetch::{FetchClients, FetchTool};
+pub use reminders::{ReminderOrigin, ReminderTool, discord_time};
 
 pub type ToolCollection = Vec<Box<dyn Tool>>;
 
+/// How long a tool may run, unless it says otherwise.
+pub const DEFAULT_TOOL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Tools run on the same runtime as every guild's conversations, so `invoke` must not block.
+/// Wrap blocking work in `tokio::task::spawn_blocking`.
+#[async_trait]
 pub trait Tool
 where
   Self: Send + Sync,
 {
   fn metadata(&self) -> &ToolMetadata;
-  fn invoke(&mut self, params: serde_json::Value) -> Result<Option<String>, String>;
+  async fn invoke(&mut self, params: serde_json::Value) -> Result<ToolOutput, String>;
+
+  /// How long a single call may run before it's abandoned.
+  fn timeout(&self) -> Duration {
+    DEFAULT_TOOL_TIMEOUT
+  }
+}
+
+/// What a tool hands back to the model: any mix of text, images and documents.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ToolOutput {
+  pub content: Vec<Content>,
+}
+
+impl ToolOutput {
+  pub fn text<S: Into<String>>(text: S) -> Self {
+    Self::default().with_text(text)
+  }
+
+  pub fn with_text<S: Into<String>>(mut self, text: S) -> Self {
+    self.content.push(Content::text(text));
+    self
+  }
+
+  pub fn with_document<S: Into<String>>(mut self, source: DocumentSource, title: S) -> Self {
+    self.content.push(Content::document(source, title));
+    self
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.content.is_empty()
+  }
+}
+
+/// A tool that runs on Anthropic's side, like web search. The API calls it and puts
+/// the results straight into the response, so it's only ever offered, never invoked.
+pub struct ServerTool(pub ToolMetadata);
+
+#[async_trait]
+impl Tool for ServerTool {
+  fn metadata(&self) -> &ToolMetadata {
+    &self.0
+  }
+
+  async fn invoke(&mut self, _params: serde_json::Value) -> Result<ToolOutput, String> {
+    Err(format!("`{}` runs on the server.", self.0.name()))
+  }
 }
 
-pub fn invoke_tool(
+/// Deserializes a tool's input into its typed form, describing what was wrong if it doesn't fit.
+pub fn parse_input<T: DeserializeOwned>(params: serde_json::Value) -> Result<T, String> {
+  serde_json::from_value(params).map_err(|e| format!("Invalid input: {}", e))
+}
+
+/// Runs the named tool, abandoning it once it runs past its timeout or `cancel` is cancelled.
+pub async fn invoke_tool(
   collection: &mut ToolCollection,
   name: &str,
   input: serde_json::Value,
-) -> Result<Option<String>, String> {
+  cancel: Option<&CancellationToken>,
+) -> Result<ToolOutput, String> {
   let tool = collection
     .iter_mut()
     .find(|tool| tool.metadata().name() == name)
     .ok_or_else(|| "No tool found!".to_string())?;
+  let timeout = tool.timeout();
+
+  let call = tokio::time::timeout(timeout, tool.invoke(input));
+  let result = match cancel {
+    Some(token) => tokio::select! {
+      result = call => result,
+      _ = token.cancelled() => return Err(format!("`{}` was cancelled.", name)),
+    },
+    None => call.await,
+  };
 
-  tool.invoke(input)
+  result.unwrap_or_else(|_| {
+    Err(format!(
+      "`{}` took longer than {:?} and was stopped.",
+      name, timeout
+    ))
+  })
 }
 
-pub struct FetchTool(ToolMetadata);
-
-impl FetchTool {
-  pub fn new() -> Self {
-    Self(ToolMetadata::Custom {
-      name: "fetch_url".into(),
-      description: "Retrieve the textual representation of a given webpage.  This tool should only be used when you are explicitly asked to fetch a webpage.".into(),
-      input_schema: Schema::object().with_property(
-        "url",
-        Schema::string("the full path to a website to retrieve, starting with http:// or https://"),
-        true,
-      ),
-    })
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::claude::Schema;
+  use serde_json::json;
+
+  struct SleepTool(ToolMetadata);
+
+  #[async_trait]
+  impl Tool for SleepTool {
+    fn metadata(&self) -> &ToolMetadata {
+      &self.0
+    }
+
+    async fn invoke(&mut self, params: serde_json::Value) -> Result<ToolOutput, String> {
+      let millis = params["millis"].as_u64().unwrap_or_default();
+      tokio::time::sleep(Duration::from_millis(millis)).await;
+      Ok(ToolOutput::text("awake"))
+    }
+
+    fn timeout(&self) -> Duration {
+      Duration::from_millis(50)
+    }
   }
-}
 
-impl Tool for FetchTool {
-  fn metadata(&self) -> &ToolMetadata {
-    &self.0
+  fn tools() -> ToolCollection {
+    vec![Box::new(SleepTool(ToolMetadata::Custom {
+      name: "sleep".into(),
+      description: "sleeps".into(),
+      input_schema: Box::new(Schema::object()),
+      cache_control: None,
+    }))]
   }
 
-  fn invoke(&mut self, params: serde_json::Value) -> Result<Option<String>, String> {
-    let url = params
-      .as_object()
-      .and_then(|obj| obj.get("url"))
-      .and_then(|s| s.as_str())
-      .ok_or("No URL provided!".to_string())?;
-
-    let resp = ureq::get(url).call().map_err(|e| e.to_string())?;
-    let body = resp.into_string().map_err(|e| e.to_string())?;
-
-    let doc = scraper::Html::parse_document(&body);
-    let mut text = doc.root_element().text().collect::<Vec<_>>().join("");
-    if text.len() > 1200 {
-      text = text[..1024].to_string();
-      text += " <truncated>";
-    }
-    Ok(Some(text))
+  #[tokio::test]
+  async fn test_invoke_tool_timeout_and_cancel() {
+    let mut tools = tools();
+
+    let quick = invoke_tool(&mut tools, "sleep", json!({ "millis": 10 }), None).await;
+    assert_eq!(quick, Ok(ToolOutput::text("awake")));
+
+    let slow = invoke_tool(&mut tools, "sleep", json!({ "millis": 5_000 }), None).await;
+    assert_eq!(
+      slow,
+      Err("`sleep` took longer than 50ms and was stopped.".into())
+    );
+
+    let token = CancellationToken::new();
+    token.cancel();
+    let cancelled = invoke_tool(&mut tools, "sleep", json!({ "millis": 10 }), Some(&token)).await;
+    assert_eq!(cancelled, Err("`sleep` was cancelled.".into()));
+
+    let missing = invoke_tool(&mut tools, "nope", json!({}), None).await;
+    assert_eq!(missing, Err("No tool found!".into()));
   }
 }
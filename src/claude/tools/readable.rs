@@ -0,0 +1,462 @@
+//! Turns an HTML page into markdown holding just its main content, roughly what a
+//! browser's reader mode shows: no scripts, styles, navigation or other page furniture.
+
+use reqwest::Url;
+use scraper::{ElementRef, Html, Node, Selector};
+use std::collections::HashMap;
+
+/// Never part of the readable content.
+const SKIPPED: &[&str] = &[
+  "script", "style", "noscript", "template", "svg", "canvas", "iframe", "form", "button", "input",
+  "select", "textarea", "nav", "header", "footer", "aside", "head", "img", "video", "audio",
+];
+
+/// Rendered inline, as part of the surrounding paragraph.
+const INLINE: &[&str] = &[
+  "a", "abbr", "b", "br", "cite", "code", "em", "i", "kbd", "mark", "q", "s", "small", "span",
+  "strong", "sub", "sup", "time", "u", "var", "label", "font",
+];
+
+/// Class or id fragments that mark boilerplate, like cookie banners and share buttons.
+const UNLIKELY: &[&str] = &[
+  "advert",
+  "banner",
+  "breadcrumb",
+  "comment",
+  "cookie",
+  "footer",
+  "menu",
+  "navbar",
+  "newsletter",
+  "popup",
+  "promo",
+  "related",
+  "share",
+  "sidebar",
+  "social",
+  "sponsor",
+];
+
+/// Class or id fragments that outweigh `UNLIKELY`, e.g. `article-comments-count`.
+const LIKELY: &[&str] = &["article", "content", "main", "post", "story", "body"];
+
+/// An `article` or `main` with less text than this is probably a teaser, not the page.
+const MIN_CONTENT_CHARS: usize = 200;
+
+/// A readable version of a page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page {
+  pub title: Option<String>,
+  /// The main content as markdown.
+  pub markdown: String,
+}
+
+impl Page {
+  /// The page as one markdown document, title first.
+  pub fn to_markdown(&self) -> String {
+    match &self.title {
+      Some(title) if !self.markdown.starts_with(&format!("# {}\n", title)) => {
+        format!("# {}\n\n{}", title, self.markdown)
+      }
+      _ => self.markdown.clone(),
+    }
+  }
+}
+
+/// Extracts the main content of `html`. Relative links are resolved against `base`.
+pub fn extract(html: &str, base: Option<&Url>) -> Page {
+  let doc = Html::parse_document(html);
+
+  let title = select(&doc, "title")
+    .next()
+    .or_else(|| select(&doc, "h1").next())
+    .map(|el| collapse(&el.text().collect::<String>()))
+    .filter(|title| !title.is_empty());
+
+  let mut renderer = Renderer {
+    base,
+    blocks: vec![],
+    line: String::new(),
+  };
+  renderer.block(main_content(&doc));
+  renderer.flush();
+
+  Page {
+    title,
+    markdown: renderer.blocks.join("\n\n"),
+  }
+}
+
+fn select<'a>(doc: &'a Html, selector: &str) -> impl Iterator<Item = ElementRef<'a>> {
+  let selector = Selector::parse(selector).expect("invalid selector");
+  doc.select(&selector).collect::<Vec<_>>().into_iter()
+}
+
+/// The element that holds the page's content: an explicit `article` or `main` if there
+/// is a substantial one, otherwise whichever element the most paragraph text sits in.
+fn main_content(doc: &Html) -> ElementRef<'_> {
+  for selector in ["article", "main", "[role=main]"] {
+    if let Some(el) = select(doc, selector).find(|el| text_len(*el) >= MIN_CONTENT_CHARS) {
+      return el;
+    }
+  }
+
+  // score paragraphs by length and commas, crediting their parent in full and their
+  // grandparent by half, in the manner of readability.js.
+  let mut scores: HashMap<_, f64> = HashMap::new();
+  for p in select(doc, "p") {
+    let len = text_len(p);
+    if len < 25 || is_unlikely(p) {
+      continue;
+    }
+    let text = p.text().collect::<String>();
+    let score = 1.0 + text.matches(',').count() as f64 + (len / 100).min(3) as f64;
+
+    let mut ancestors = p.ancestors().filter_map(ElementRef::wrap);
+    if let Some(parent) = ancestors.next() {
+      *scores.entry(parent.id()).or_default() += score;
+    }
+    if let Some(grandparent) = ancestors.next() {
+      *scores.entry(grandparent.id()).or_default() += score / 2.0;
+    }
+  }
+
+  scores
+    .into_iter()
+    .max_by(|a, b| a.1.total_cmp(&b.1))
+    .and_then(|(id, _)| doc.tree.get(id).and_then(ElementRef::wrap))
+    .or_else(|| select(doc, "body").next())
+    .unwrap_or_else(|| doc.root_element())
+}
+
+fn text_len(el: ElementRef) -> usize {
+  el.text().map(|t| t.trim().chars().count()).sum()
+}
+
+fn is_unlikely(el: ElementRef) -> bool {
+  let value = el.value();
+  let names = format!(
+    "{} {}",
+    value.attr("class").unwrap_or_default(),
+    value.id().unwrap_or_default()
+  )
+  .to_lowercase();
+
+  value.attr("hidden").is_some()
+    || value.attr("aria-hidden") == Some("true")
+    || (UNLIKELY.iter().any(|u| names.contains(u)) && !LIKELY.iter().any(|l| names.contains(l)))
+}
+
+/// Squeezes runs of whitespace, including newlines, into single spaces.
+fn collapse(text: &str) -> String {
+  text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+struct Renderer<'a> {
+  base: Option<&'a Url>,
+  /// Finished paragraphs, headings, list items and so on.
+  blocks: Vec<String>,
+  /// Inline text not yet part of a block.
+  line: String,
+}
+
+impl Renderer<'_> {
+  /// Ends the current paragraph, if there is one.
+  fn flush(&mut self) {
+    let line = collapse(&std::mem::take(&mut self.line));
+    if !line.is_empty() {
+      self.blocks.push(line);
+    }
+  }
+
+  fn push(&mut self, block: String) {
+    self.flush();
+    if !block.trim().is_empty() {
+      self.blocks.push(block);
+    }
+  }
+
+  /// Renders the children of `el` as a sequence of blocks.
+  fn block(&mut self, el: ElementRef) {
+    for child in el.children() {
+      match child.value() {
+        Node::Text(text) => self.line.push_str(text),
+        Node::Element(_) => {
+          let child = ElementRef::wrap(child).unwrap();
+          self.element(child);
+        }
+        _ => {}
+      }
+    }
+  }
+
+  fn element(&mut self, el: ElementRef) {
+    let name = el.value().name();
+    if SKIPPED.contains(&name) || is_unlikely(el) {
+      return;
+    }
+    if INLINE.contains(&name) {
+      let text = self.inline(el);
+      self.line.push_str(&text);
+      return;
+    }
+
+    match name {
+      "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+        let level = name[1..].parse().unwrap_or(1);
+        let text = collapse(&self.inline(el));
+        if !text.is_empty() {
+          self.push(format!("{} {}", "#".repeat(level), text));
+        }
+      }
+      "p" => {
+        self.flush();
+        self.line = self.inline(el);
+        self.flush();
+      }
+      "pre" => {
+        let code = el.text().collect::<String>();
+        self.push(format!("```\n{}\n```", code.trim_end()));
+      }
+      "ul" | "ol" => {
+        self.flush();
+        let items = el
+          .children()
+          .filter_map(ElementRef::wrap)
+          .filter(|li| li.value().name() == "li");
+        for (i, li) in items.enumerate() {
+          let marker = match name {
+            "ol" => format!("{}. ", i + 1),
+            _ => "- ".into(),
+          };
+          let item = self.nested(li);
+          if !item.is_empty() {
+            // continuation lines line up under the text of the item.
+            let indent = " ".repeat(marker.len());
+            self.blocks.push(format!(
+              "{}{}",
+              marker,
+              item
+                .replace("\n\n", "\n")
+                .replace('\n', &format!("\n{}", indent))
+            ));
+          }
+        }
+        self.join_list();
+      }
+      "blockquote" => {
+        let quote = self.nested(el);
+        if !quote.is_empty() {
+          self.push(
+            quote
+              .lines()
+              .map(|line| format!("> {}", line).trim_end().to_owned())
+              .collect::<Vec<_>>()
+              .join("\n"),
+          );
+        }
+      }
+      "table" => {
+        let rows = el
+          .select(&Selector::parse("tr").unwrap())
+          .map(|tr| {
+            let cells = tr
+              .children()
+              .filter_map(ElementRef::wrap)
+              .filter(|cell| matches!(cell.value().name(), "td" | "th"))
+              .map(|cell| collapse(&self.inline(cell)).replace('|', "\\|"))
+              .collect::<Vec<_>>();
+            format!("| {} |", cells.join(" | "))
+          })
+          .collect::<Vec<_>>();
+        if let Some((head, rest)) = rows.split_first() {
+          let columns = head.matches(" | ").count() + 1;
+          let rule = format!("|{}", " --- |".repeat(columns));
+          self.push(
+            [vec![head.clone(), rule], rest.to_vec()]
+              .concat()
+              .join("\n"),
+          );
+        }
+      }
+      "hr" => self.push("---".into()),
+      _ => {
+        // divs, sections and the like only separate blocks.
+        self.flush();
+        self.block(el);
+        self.flush();
+      }
+    }
+  }
+
+  /// Renders `el` on its own and returns the markdown, leaving the output untouched.
+  fn nested(&mut self, el: ElementRef) -> String {
+    self.flush();
+    let mut inner = Renderer {
+      base: self.base,
+      blocks: vec![],
+      line: String::new(),
+    };
+    inner.block(el);
+    inner.flush();
+    inner.blocks.join("\n\n")
+  }
+
+  /// List items are separate blocks while rendering; runs of them print without gaps.
+  fn join_list(&mut self) {
+    let is_item = |block: &String| {
+      block.starts_with("- ")
+        || block
+          .split_once(". ")
+          .is_some_and(|(n, _)| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+    };
+
+    let mut joined: Vec<String> = vec![];
+    for block in self.blocks.drain(..) {
+      match joined.last_mut() {
+        Some(last) if is_item(&block) && is_item(last) => {
+          last.push('\n');
+          last.push_str(&block);
+        }
+        _ => joined.push(block),
+      }
+    }
+    self.blocks = joined;
+  }
+
+  /// Renders `el` as inline markdown: text with links, emphasis and code.
+  fn inline(&self, el: ElementRef) -> String {
+    let mut out = String::new();
+    for child in el.children() {
+      match child.value() {
+        Node::Text(text) => out.push_str(text),
+        Node::Element(_) => {
+          let child = ElementRef::wrap(child).unwrap();
+          out.push_str(&self.inline_element(child));
+        }
+        _ => {}
+      }
+    }
+    out
+  }
+
+  fn inline_element(&self, el: ElementRef) -> String {
+    let value = el.value();
+    if SKIPPED.contains(&value.name()) || is_unlikely(el) {
+      return String::new();
+    }
+
+    let text = || collapse(&self.inline(el));
+    let wrap = |mark: &str| {
+      let text = text();
+      match text.is_empty() {
+        true => String::new(),
+        false => format!("{}{}{}", mark, text, mark),
+      }
+    };
+
+    match value.name() {
+      "br" => "\n".into(),
+      "a" => {
+        let text = text();
+        match value.attr("href").and_then(|href| self.link(href)) {
+          Some(href) if !text.is_empty() => format!("[{}]({})", text, href),
+          _ => text,
+        }
+      }
+      "strong" | "b" => wrap("**"),
+      "em" | "i" => wrap("*"),
+      "code" | "kbd" => {
+        let code = el.text().collect::<String>();
+        match code.trim().is_empty() {
+          true => String::new(),
+          false => format!("`{}`", code.trim()),
+        }
+      }
+      _ => self.inline(el),
+    }
+  }
+
+  /// An absolute URL for `href`, or `None` if it doesn't lead anywhere useful.
+  fn link(&self, href: &str) -> Option<String> {
+    let href = href.trim();
+    if href.is_empty() || href.starts_with('#') || href.starts_with("javascript:") {
+      return None;
+    }
+
+    match Url::parse(href) {
+      Ok(url) => Some(url.to_string()),
+      Err(_) => self.base?.join(href).ok().map(String::from),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn fixture(name: &str) -> String {
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+      .join("fixtures/pages")
+      .join(name);
+    std::fs::read_to_string(&path)
+      .unwrap_or_else(|e| panic!("failed to read {}: {}", path.display(), e))
+  }
+
+  #[test]
+  fn test_extract_article() {
+    let base = Url::parse("https://blog.example.com/posts/otters").unwrap();
+    let page = extract(&fixture("article.html"), Some(&base));
+
+    assert_eq!(page.title.as_deref(), Some("Why otters hold hands"));
+    let markdown = page.to_markdown();
+    assert!(markdown.starts_with("# Why otters hold hands\n\n"));
+
+    assert!(markdown.contains("## Sleeping in rafts"));
+    assert!(
+      markdown
+        .contains("according to [the aquarium](https://blog.example.com/aquarium/otters) they")
+    );
+    assert!(markdown.contains("[field notes](https://example.org/notes.pdf)"));
+    assert!(
+      markdown.contains("- Kelp anchors them in place\n- Holding paws keeps the group together")
+    );
+    assert!(markdown.contains("> An otter on its own drifts off."));
+    assert!(markdown.contains("| Species | Raft size |\n| --- | --- |\n| Sea otter | 1000 |"));
+    assert!(markdown.contains("**never**"));
+
+    for boilerplate in [
+      "trackPageview",
+      "font-family",
+      "Home",
+      "Subscribe to our newsletter",
+      "Accept cookies",
+      "Copyright",
+      "Share on",
+    ] {
+      assert!(
+        !markdown.contains(boilerplate),
+        "{:?} leaked into:\n{}",
+        boilerplate,
+        markdown
+      );
+    }
+  }
+
+  #[test]
+  fn test_extract_without_article() {
+    let page = extract(&fixture("divs.html"), None);
+
+    assert_eq!(page.title.as_deref(), Some("Rezept: Käsespätzle"));
+    assert!(
+      page
+        .markdown
+        .contains("Die Spätzle werden in Salzwasser gekocht")
+    );
+    assert!(page.markdown.contains("```\n500 g Mehl\n5 Eier\n```"));
+    // the sidebar has plenty of text, but no paragraphs to speak of.
+    assert!(!page.markdown.contains("Beliebte Rezepte"));
+    // relative links can't be resolved without a base, so only the text is kept.
+    assert!(page.markdown.contains("siehe Grundrezept."));
+  }
+}
@@ -0,0 +1,197 @@
+//! The tools a guild can turn on, and how to build them from its settings.
+//! A fresh `ToolCollection` is built for every request, so settings take effect right away;
+//! the HTTP clients behind it are kept in a `FetchClients` and reused.
+
+use super::guard::Guard;
+use super::{
+  FetchClients, FetchTool, ReminderOrigin, ReminderTool, ServerTool, ToolCollection, fetch,
+};
+use crate::claude::Tool as ToolMetadata;
+use crate::storage::Storage;
+use std::sync::Arc;
+
+pub const FETCH_URL: &str = "fetch_url";
+pub const WEB_SEARCH: &str = "web_search";
+pub const CODE_EXECUTION: &str = "code_execution";
+pub const REMINDERS: &str = "reminders";
+
+/// Every tool a guild can turn on, with a short description for `show-tools`.
+pub const AVAILABLE: &[(&str, &str)] = &[
+  (FETCH_URL, "reads a web page"),
+  (
+    REMINDERS,
+    "sets reminders and posts them in the channel later",
+  ),
+  (WEB_SEARCH, "searches the web, billed per search"),
+  (
+    CODE_EXECUTION,
+    "runs Python in a sandbox, billed per hour of use",
+  ),
+];
+
+/// Turned on for guilds that haven't picked their own tools. None are, so each guild's
+/// admins opt in to anything that reaches outside the conversation.
+pub const DEFAULT_ENABLED: &[&str] = &[];
+
+/// Searches allowed per response, unless the guild sets `web_search_max_uses`.
+pub const DEFAULT_WEB_SEARCH_MAX_USES: usize = 5;
+
+/// Which tools a guild has turned on, and how they're configured.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ToolSettings {
+  pub enabled: Vec<String>,
+  /// Characters of a fetched page handed to the model.
+  pub fetch_max_chars: usize,
+  /// If set, the only sites pages can be fetched from.
+  pub fetch_allowed_domains: Option<Vec<String>>,
+  /// Sites pages are never fetched from.
+  pub fetch_blocked_domains: Option<Vec<String>>,
+  pub web_search_max_uses: usize,
+  /// Sites web search must never return results from.
+  pub web_search_blocked_domains: Option<Vec<String>>,
+}
+
+impl Default for ToolSettings {
+  fn default() -> Self {
+    Self {
+      enabled: DEFAULT_ENABLED.iter().map(|&name| name.into()).collect(),
+      fetch_max_chars: fetch::DEFAULT_MAX_CHARS,
+      fetch_allowed_domains: None,
+      fetch_blocked_domains: None,
+      web_search_max_uses: DEFAULT_WEB_SEARCH_MAX_USES,
+      web_search_blocked_domains: None,
+    }
+  }
+}
+
+impl ToolSettings {
+  pub fn is_enabled(&self, name: &str) -> bool {
+    self.enabled.iter().any(|enabled| enabled == name)
+  }
+
+  /// Turns a tool on or off. Returns false if there's no such tool.
+  pub fn set_enabled(&mut self, name: &str, enabled: bool) -> bool {
+    if !is_available(name) {
+      return false;
+    }
+
+    self.enabled.retain(|n| n != name);
+    if enabled {
+      self.enabled.push(name.into());
+    }
+    // keep the stored list in a stable order.
+    self
+      .enabled
+      .sort_by_key(|n| AVAILABLE.iter().position(|(name, _)| name == n));
+    true
+  }
+
+  /// The enabled tools, ready to be offered to the model.
+  /// `reminders` acts for whoever asked, so it's only built when that's known.
+  pub fn build(
+    &self,
+    clients: &mut FetchClients,
+    reminders: Option<(Arc<Storage>, ReminderOrigin)>,
+  ) -> ToolCollection {
+    let mut tools: ToolCollection = vec![];
+
+    if self.is_enabled(FETCH_URL) {
+      tools.push(Box::new(
+        FetchTool::new(
+          clients,
+          Guard {
+            allowed_domains: self.fetch_allowed_domains.clone(),
+            blocked_domains: self.fetch_blocked_domains.clone(),
+            ..Default::default()
+          },
+        )
+        .max_chars(self.fetch_max_chars),
+      ));
+    }
+    if self.is_enabled(REMINDERS)
+      && let Some((storage, origin)) = reminders
+    {
+      tools.push(Box::new(ReminderTool::new(storage, origin)));
+    }
+    if self.is_enabled(WEB_SEARCH) {
+      tools.push(Box::new(ServerTool(ToolMetadata::web_search(
+        self.web_search_max_uses,
+        self.web_search_blocked_domains.clone(),
+      ))));
+    }
+    if self.is_enabled(CODE_EXECUTION) {
+      tools.push(Box::new(ServerTool(ToolMetadata::code_execution())));
+    }
+
+    tools
+  }
+}
+
+pub fn is_available(name: &str) -> bool {
+  AVAILABLE.iter().any(|(available, _)| *available == name)
+}
+
+/// Whether a `set-var` key configures tools, which only server admins may change.
+pub fn is_tool_setting(key: &str) -> bool {
+  key == "tools" || key.starts_with("fetch_") || key.starts_with("web_search_")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_build_from_settings() {
+    let names = |settings: &ToolSettings| {
+      settings
+        .build(&mut FetchClients::default(), None)
+        .iter()
+        .map(|t| t.metadata().name().to_owned())
+        .collect::<Vec<_>>()
+    };
+
+    let mut settings = ToolSettings::default();
+    assert!(names(&settings).is_empty());
+
+    assert!(settings.set_enabled(FETCH_URL, true));
+    assert_eq!(names(&settings), vec![FETCH_URL]);
+    assert!(settings.set_enabled(CODE_EXECUTION, true));
+    assert!(settings.set_enabled(WEB_SEARCH, true));
+    assert!(settings.set_enabled(FETCH_URL, false));
+    assert!(!settings.set_enabled("rm_rf", true));
+    assert_eq!(settings.enabled, vec![WEB_SEARCH, CODE_EXECUTION]);
+
+    settings.web_search_max_uses = 2;
+    settings.web_search_blocked_domains = Some(vec!["example.com".into()]);
+    let tools = settings.build(&mut FetchClients::default(), None);
+    assert_eq!(
+      serde_json::to_value(tools[0].metadata()).unwrap(),
+      serde_json::json!({
+        "type": "web_search_20250305",
+        "name": "web_search",
+        "max_uses": 2,
+        "blocked_domains": ["example.com"],
+      })
+    );
+    assert_eq!(names(&settings), vec![WEB_SEARCH, CODE_EXECUTION]);
+
+    // reminders need to know who asked, so they're left out without a requester.
+    assert!(settings.set_enabled(REMINDERS, true));
+    assert_eq!(names(&settings), vec![WEB_SEARCH, CODE_EXECUTION]);
+  }
+
+  #[test]
+  fn test_is_tool_setting() {
+    for key in [
+      "tools",
+      "fetch_max_chars",
+      "fetch_allowed_domains",
+      "web_search_max_uses",
+    ] {
+      assert!(is_tool_setting(key), "{} should need an admin", key);
+    }
+    for key in ["tool_choice", "temperature", "system"] {
+      assert!(!is_tool_setting(key), "{} shouldn't need an admin", key);
+    }
+  }
+}
@@ -0,0 +1,390 @@
+//! `reminders`: lets the model set, list and cancel reminders for whoever it's talking to.
+//! Reminders are stored in `Storage` and posted by the handler once they come due.
+
+use super::{Tool, ToolMetadata, ToolOutput, parse_input};
+use crate::claude::Schema;
+use crate::storage::{Reminder, Storage};
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDateTime, SecondsFormat, TimeDelta, Utc};
+use std::sync::Arc;
+
+/// Reminders can't be set further out than this.
+const MAX_DELAY: TimeDelta = TimeDelta::days(366);
+
+/// Pending reminders a user may have in one guild.
+const MAX_PENDING: usize = 25;
+
+/// Longest reminder text that's stored.
+const MAX_TEXT_CHARS: usize = 500;
+
+crate::json_schema! {
+  pub struct ReminderInput {
+    /// `create`, `list` or `cancel`
+    pub action: String,
+    /// for `create`: when to post it, either a delay like "in 2 hours", "in 1 day and 30 minutes" or "tomorrow", or an ISO 8601 time with a UTC offset like "2025-06-01T09:00:00-04:00"
+    pub when: Option<String>,
+    /// for `create`: what to remind them about
+    pub text: Option<String>,
+    /// for `cancel`: the id of the reminder, as shown by `list`
+    pub id: Option<i64>,
+  }
+}
+
+/// Where a reminder was asked for. It's posted as a reply to the same message.
+#[derive(Clone, Copy, Debug)]
+pub struct ReminderOrigin {
+  pub guild_id: u64,
+  pub channel_id: u64,
+  pub user_id: u64,
+  pub message_id: u64,
+}
+
+pub struct ReminderTool {
+  metadata: ToolMetadata,
+  storage: Arc<Storage>,
+  origin: ReminderOrigin,
+}
+
+impl ReminderTool {
+  pub fn new(storage: Arc<Storage>, origin: ReminderOrigin) -> Self {
+    Self {
+      metadata: ToolMetadata::Custom {
+        name: "reminders".into(),
+        description: "Set, list or cancel reminders for the person you're replying to. Reminders are posted as a reply in this channel when they come due, to within about a minute.".into(),
+        input_schema: Box::new(Schema::of::<ReminderInput>()),
+        cache_control: None,
+      },
+      storage,
+      origin,
+    }
+  }
+
+  fn create(&self, input: ReminderInput, now: DateTime<Utc>) -> Result<String, String> {
+    let when = input.when.ok_or("`create` needs `when`.")?;
+    let text = input
+      .text
+      .map(|text| text.trim().chars().take(MAX_TEXT_CHARS).collect::<String>())
+      .filter(|text| !text.is_empty())
+      .ok_or("`create` needs `text`.")?;
+
+    let due = parse_when(&when, now)?;
+    if due <= now {
+      return Err(format!(
+        "{} is in the past. It's now {}.",
+        format_time(due),
+        format_time(now)
+      ));
+    }
+    if due - now > MAX_DELAY {
+      return Err("Reminders can't be set more than a year ahead.".into());
+    }
+
+    let origin = self.origin;
+    let pending = self
+      .storage
+      .pending_reminders(origin.guild_id, origin.user_id)
+      .map_err(|e| e.to_string())?;
+    if pending.len() >= MAX_PENDING {
+      return Err(format!(
+        "They already have {} reminders pending. Cancel one first.",
+        MAX_PENDING
+      ));
+    }
+
+    let id = self
+      .storage
+      .add_reminder(&Reminder {
+        id: 0,
+        guild_id: origin.guild_id,
+        channel_id: origin.channel_id,
+        user_id: origin.user_id,
+        message_id: origin.message_id,
+        text,
+        due_at: due.timestamp(),
+      })
+      .map_err(|e| e.to_string())?;
+
+    Ok(format!(
+      "Reminder {} set for {} ({}).",
+      id,
+      discord_time(due.timestamp()),
+      format_time(due)
+    ))
+  }
+
+  fn list(&self, now: DateTime<Utc>) -> Result<String, String> {
+    let pending = self
+      .storage
+      .pending_reminders(self.origin.guild_id, self.origin.user_id)
+      .map_err(|e| e.to_string())?;
+    if pending.is_empty() {
+      return Ok("They have no reminders pending.".into());
+    }
+
+    let lines = pending
+      .iter()
+      .map(|r| format!("{}: {} — {}", r.id, discord_time(r.due_at), r.text))
+      .collect::<Vec<_>>()
+      .join("\n");
+    Ok(format!("It's now {}.\n{}", format_time(now), lines))
+  }
+
+  fn cancel(&self, input: ReminderInput) -> Result<String, String> {
+    let id = input.id.ok_or("`cancel` needs `id`.")?;
+    match self.storage.cancel_reminder(id, self.origin.user_id) {
+      Ok(true) => Ok(format!("Cancelled reminder {}.", id)),
+      Ok(false) => Err(format!("They have no pending reminder {}.", id)),
+      Err(e) => Err(e.to_string()),
+    }
+  }
+}
+
+#[async_trait]
+impl Tool for ReminderTool {
+  fn metadata(&self) -> &ToolMetadata {
+    &self.metadata
+  }
+
+  async fn invoke(&mut self, params: serde_json::Value) -> Result<ToolOutput, String> {
+    let input: ReminderInput = parse_input(params)?;
+    let tool = Self {
+      metadata: self.metadata.clone(),
+      storage: self.storage.clone(),
+      origin: self.origin,
+    };
+
+    // storage is synchronous, so it's kept off the runtime's worker threads.
+    tokio::task::spawn_blocking(move || {
+      let now = Utc::now();
+      match input.action.as_str() {
+        "create" => tool.create(input, now),
+        "list" => tool.list(now),
+        "cancel" => tool.cancel(input),
+        action => Err(format!(
+          "Unknown action `{}`. Use `create`, `list` or `cancel`.",
+          action
+        )),
+      }
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map(ToolOutput::text)
+  }
+}
+
+/// Works out when `when` is, relative to `now`. Understands delays like "in 2 hours",
+/// "90 minutes" or "1 day and 30 minutes", "tomorrow", and ISO 8601 times. Times without
+/// a UTC offset are taken to be UTC.
+pub fn parse_when(when: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>, String> {
+  let when = when.trim();
+  if let Ok(time) = DateTime::parse_from_rfc3339(when) {
+    return Ok(time.with_timezone(&Utc));
+  }
+  for format in [
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%d %H:%M",
+  ] {
+    if let Ok(time) = NaiveDateTime::parse_from_str(when, format) {
+      return Ok(time.and_utc());
+    }
+  }
+
+  let lower = when.to_lowercase();
+  let seconds = match lower.trim_start_matches("in ").trim() {
+    "tomorrow" => Some(86_400.0),
+    "next week" => Some(604_800.0),
+    delay => parse_delay(delay),
+  }
+  .ok_or_else(|| {
+    format!(
+      "Couldn't understand `{}` as a time. Use a delay like \"in 2 hours\", or an ISO 8601 time like \"{}\".",
+      when,
+      format_time(now)
+    )
+  })?;
+
+  if seconds <= 0.0 {
+    return Err(format!("`{}` isn't a delay into the future.", when));
+  }
+  // the model picks the amounts, so they can be anything a float can hold.
+  Some(seconds)
+    .filter(|seconds| seconds.is_finite())
+    .and_then(|seconds| TimeDelta::try_seconds(seconds.round() as i64))
+    .and_then(|delay| now.checked_add_signed(delay))
+    .ok_or_else(|| format!("`{}` is too far away.", when))
+}
+
+/// Parses a delay made of amounts and units, like "2h30m" or "an hour and 15 minutes",
+/// into seconds.
+fn parse_delay(delay: &str) -> Option<f64> {
+  // split into runs of digits and runs of letters, dropping spaces and punctuation.
+  let mut tokens: Vec<String> = vec![];
+  let mut last_kind = None;
+  for c in delay.chars() {
+    let kind = match c {
+      '0'..='9' | '.' => Some(true),
+      c if c.is_alphabetic() => Some(false),
+      _ => None,
+    };
+    match (kind, tokens.last_mut()) {
+      (Some(kind), Some(token)) if last_kind == Some(kind) => token.push(c),
+      (Some(_), _) => tokens.push(c.into()),
+      (None, _) => {}
+    }
+    last_kind = kind;
+  }
+
+  let mut seconds = 0.0;
+  let mut tokens = tokens.iter().map(String::as_str).filter(|&t| t != "and");
+  let mut any = false;
+  while let Some(amount) = tokens.next() {
+    let amount: f64 = match amount {
+      "a" | "an" | "one" => 1.0,
+      "half" => 0.5,
+      amount => amount.parse().ok()?,
+    };
+    // "half an hour"
+    let unit = match tokens.next()? {
+      "a" | "an" => tokens.next()?,
+      unit => unit,
+    };
+    let scale = match unit {
+      "s" | "sec" | "secs" | "second" | "seconds" => 1.0,
+      "m" | "min" | "mins" | "minute" | "minutes" => 60.0,
+      "h" | "hr" | "hrs" | "hour" | "hours" => 3_600.0,
+      "d" | "day" | "days" => 86_400.0,
+      "w" | "wk" | "wks" | "week" | "weeks" => 604_800.0,
+      _ => return None,
+    };
+    seconds += amount * scale;
+    any = true;
+  }
+
+  any.then_some(seconds)
+}
+
+fn format_time(time: DateTime<Utc>) -> String {
+  time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// A timestamp Discord shows in the reader's own time zone.
+pub fn discord_time(timestamp: i64) -> String {
+  format!("<t:{}:F>", timestamp)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  fn now() -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339("2025-03-01T12:00:00Z")
+      .unwrap()
+      .with_timezone(&Utc)
+  }
+
+  #[test]
+  fn test_parse_when() {
+    let after = |when: &str| parse_when(when, now()).map(|time| (time - now()).num_seconds());
+
+    assert_eq!(after("in 2 hours"), Ok(7_200));
+    assert_eq!(after("In 90 minutes"), Ok(5_400));
+    assert_eq!(after("2h30m"), Ok(9_000));
+    assert_eq!(after("in an hour and 15 mins"), Ok(4_500));
+    assert_eq!(after("half an hour"), Ok(1_800));
+    assert_eq!(after("in 1.5 days"), Ok(129_600));
+    assert_eq!(after("tomorrow"), Ok(86_400));
+    assert_eq!(after("2025-03-01T09:00:00-05:00"), Ok(7_200));
+    assert_eq!(after("2025-03-02 12:00"), Ok(86_400));
+
+    assert_eq!(
+      after("next tuesday"),
+      Err("Couldn't understand `next tuesday` as a time. Use a delay like \"in 2 hours\", or an ISO 8601 time like \"2025-03-01T12:00:00Z\".".into())
+    );
+    assert!(after("in 3 fortnights").is_err());
+    assert!(after("in hours").is_err());
+    assert_eq!(
+      after("in 0 minutes"),
+      Err("`in 0 minutes` isn't a delay into the future.".into())
+    );
+    let forever = format!("{} weeks", "9".repeat(400));
+    assert_eq!(
+      after(&forever),
+      Err(format!("`{}` is too far away.", forever))
+    );
+    assert_eq!(
+      after("in 9999999999999 days"),
+      Err("`in 9999999999999 days` is too far away.".into())
+    );
+    assert_eq!(
+      after("in 100000000 weeks"),
+      Err("`in 100000000 weeks` is too far away.".into())
+    );
+    assert!(after("").is_err());
+  }
+
+  #[tokio::test]
+  async fn test_reminder_tool() {
+    let storage = Arc::new(crate::storage::tests::storage("reminder-tool"));
+    let origin = ReminderOrigin {
+      guild_id: 1,
+      channel_id: 2,
+      user_id: 3,
+      message_id: 4,
+    };
+    let mut tool = ReminderTool::new(storage.clone(), origin);
+
+    let created = tool
+      .invoke(json!({ "action": "create", "when": "in 2 hours", "text": "stretch" }))
+      .await
+      .unwrap();
+    let pending = storage.pending_reminders(1, 3).unwrap();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].text, "stretch");
+    assert_eq!(pending[0].message_id, 4);
+    assert!((pending[0].due_at - Utc::now().timestamp() - 7_200).abs() <= 5);
+    assert_eq!(
+      created,
+      ToolOutput::text(format!(
+        "Reminder {} set for {} ({}).",
+        pending[0].id,
+        discord_time(pending[0].due_at),
+        format_time(DateTime::from_timestamp(pending[0].due_at, 0).unwrap())
+      ))
+    );
+
+    let past = tool
+      .invoke(json!({ "action": "create", "when": "2020-01-01T00:00:00Z", "text": "late" }))
+      .await;
+    assert!(past.is_err_and(|e| e.starts_with("2020-01-01T00:00:00Z is in the past. It's now")));
+    assert_eq!(
+      tool
+        .invoke(json!({ "action": "create", "when": "in 1 hour" }))
+        .await,
+      Err("`create` needs `text`.".into())
+    );
+
+    let listed = tool.invoke(json!({ "action": "list" })).await.unwrap();
+    let ToolOutput { content } = listed;
+    assert!(matches!(
+      &content[0],
+      crate::claude::Content::Text { text, .. } if text.ends_with(&format!("{}: {} — stretch", pending[0].id, discord_time(pending[0].due_at)))
+    ));
+
+    let id = pending[0].id;
+    assert_eq!(
+      tool.invoke(json!({ "action": "cancel", "id": id })).await,
+      Ok(ToolOutput::text(format!("Cancelled reminder {}.", id)))
+    );
+    assert_eq!(
+      tool.invoke(json!({ "action": "cancel", "id": id })).await,
+      Err(format!("They have no pending reminder {}.", id))
+    );
+    assert_eq!(
+      tool.invoke(json!({ "action": "list" })).await,
+      Ok(ToolOutput::text("They have no reminders pending."))
+    );
+  }
+}
@@ -5,8 +5,13 @@ use serenity::{
   model::{channel::Message, gateway::Ready},
   prelude::*,
 };
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 use tokio::sync::mpsc::UnboundedSender;
 
+/// How often the handler is woken up to run scheduled work.
+const TICK_INTERVAL: Duration = Duration::from_secs(60);
+
 /// Wrapper for Discord message events with associated context.
 /// Contains the message data and Discord API context needed for bot responses.
 #[derive(Debug)]
@@ -32,6 +37,13 @@ pub struct ThreadUpdateEvent {
   pub new: GuildChannel,
 }
 
+/// Fired periodically once the bot is connected.
+/// Drives scheduled work that isn't triggered by a Discord event, such as message batches.
+#[derive(Debug)]
+pub struct TickEvent {
+  pub ctx: Context,
+}
+
 /// All Discord events that the bot processes.
 /// These events are forwarded from the Discord gateway to the main event handler.
 #[derive(Debug)]
@@ -39,19 +51,47 @@ pub enum BotEvent {
   Message(MsgEvent),
   Ready(ReadyEvent),
   ThreadUpdate(ThreadUpdateEvent),
+  Tick(TickEvent),
 }
 
 /// Bridges Discord's event system with the bot's internal event processing.
 /// Receives Discord events and forwards them via channels to the main handler.
 pub struct EventDispatcher {
   tx: UnboundedSender<BotEvent>,
+  ticking: AtomicBool,
 }
 
 impl EventDispatcher {
   /// Creates a new event dispatcher that forwards events to the given channel.
   /// The channel sender is used to decouple Discord event handling from bot logic.
   pub fn new(tx: UnboundedSender<BotEvent>) -> Self {
-    Self { tx }
+    Self {
+      tx,
+      ticking: AtomicBool::new(false),
+    }
+  }
+
+  /// Starts sending `BotEvent::Tick` on a fixed interval.
+  /// Ready fires again on every reconnect, so only the first call starts the timer.
+  fn start_ticking(&self, ctx: &Context) {
+    if self.ticking.swap(true, Ordering::SeqCst) {
+      return;
+    }
+
+    let tx = self.tx.clone();
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+      let mut interval = tokio::time::interval(TICK_INTERVAL);
+      loop {
+        interval.tick().await;
+        if tx
+          .send(BotEvent::Tick(TickEvent { ctx: ctx.clone() }))
+          .is_err()
+        {
+          break;
+        }
+      }
+    });
   }
 }
 
@@ -61,6 +101,7 @@ impl EventHandler for EventDispatcher {
   /// Forwards guild information to the main handler for configuration setup.
   async fn ready(&self, ctx: Context, ready: Ready) {
     info!("connected as {}", ready.user.name);
+    self.start_ticking(&ctx);
     let event = BotEvent::Ready(ReadyEvent {
       guilds: ready.guilds.iter().map(|g| g.id).collect(),
       ctx,
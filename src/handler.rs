@@ -1,22 +1,104 @@
 use crate::audio::AudioHandler;
 use crate::channel::Channel;
 use crate::claude::{
-  self, Client, Content, ImageSource, Interaction, Model, Response, Role, Tool, tools::*,
+  BatchOutcome, BatchRequest, Citation, Client, Content, DocumentSource, ImageSource, Interaction,
+  MessageOptions, Model, OpenAIClient, Provider, RateLimitStatus, Registry, Request, Response,
+  Role, Tool, ToolChoice, Usage, tokens,
+  tools::{registry::ToolSettings, *},
+};
+use crate::dispatcher::{BotEvent, MsgEvent, ReadyEvent, ThreadUpdateEvent, TickEvent};
+use crate::storage::{
+  BatchJob, BudgetAction, GuildConfig, Reminder, Storage, UsageRecord, UsageSummary,
 };
-use crate::dispatcher::{BotEvent, MsgEvent, ReadyEvent, ThreadUpdateEvent};
-use crate::storage::Storage;
 use base64::prelude::*;
+use chrono::Utc;
 use itertools::Itertools;
 use log::{debug, error, info, trace};
 use regex::{Captures, Regex};
 use serenity::all::{
-  Channel as DChannel, ChannelId, ChannelType, CreateAttachment, CreateMessage, GuildChannel,
+  Channel as DChannel, ChannelId, ChannelType, CreateAllowedMentions, CreateAttachment,
+  CreateMessage, EditMessage, GuildChannel, Message, MessageId, UserId,
 };
-use serenity::prelude::CacheHttp;
-use std::collections::HashMap;
+use serenity::prelude::{CacheHttp, Context};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::join;
-use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+/// Shown in the reply while Claude is still working on a response.
+const PLACEHOLDER: &str = ":thought_balloon:";
+
+/// Sent instead of a response when a guild has used up its budget.
+const BUDGET_REFUSAL: &str =
+  "I've spent all the money I'm allowed to for now. Try again once the budget resets.";
+
+/// Model for text-only turns, unless the guild picks one with `set-var text_model = ...`.
+const DEFAULT_TEXT_MODEL: Model = Model::HAIKU_35;
+
+/// Model for turns with images, unless the guild picks one with `set-var vision_model = ...`.
+const DEFAULT_VISION_MODEL: Model = Model::SONNET_45;
+
+/// Model used once a guild set to `budget_action = downgrade` is over budget,
+/// unless the guild's own choice is already cheaper.
+const BUDGET_MODEL: Model = Model::HAIKU_45;
+
+/// Thinking budget used when a message asks Scrubby to "think hard".
+const THINK_HARD_BUDGET: usize = 8_000;
+
+/// Cheapest model that supports extended thinking.
+const THINKING_MODEL: Model = Model::HAIKU_45;
+
+/// Longest reasoning summary shown by the `show-thinking` command.
+const THINKING_SUMMARY_CHARS: usize = 1_500;
+
+/// Model used for digests, which go out as part of a message batch.
+const DIGEST_MODEL: Model = Model::HAIKU_45;
+
+/// Appended to the conversation when asking for a digest.
+const DIGEST_REQUEST: &str = "Please write a short digest of the conversation so far: the main topics, anything that was decided, and any open questions.";
+
+/// Times a finished batch's results are asked for before giving up on them.
+const MAX_BATCH_RESULT_ATTEMPTS: usize = 5;
+
+/// Posted where a batched job was asked for when its results couldn't be downloaded.
+const BATCH_LOST: &str = "I lost the answer to that one, sorry. Try asking again.";
+
+/// Batched requests are billed at half the usual price.
+const BATCH_PRICE_FACTOR: f64 = 0.5;
+
+/// Local models cost nothing as far as guild budgets are concerned.
+const LOCAL_PRICE_FACTOR: f64 = 0.0;
+
+/// Model requested from the local server unless `LOCAL_LLM_MODEL` says otherwise.
+const DEFAULT_LOCAL_MODEL: &str = "llama3.2";
+
+/// History limit for regular channels, in tokens. Threads may use the whole context window.
+const CHANNEL_TOKEN_LIMIT: usize = 10_000;
+
+/// Ask the API for an exact token count once the estimated request uses this much of its budget.
+const CALIBRATION_THRESHOLD: f64 = 0.8;
+
+/// How many times a response that was cut short is picked up again before giving up.
+const MAX_CONTINUATIONS: usize = 3;
+
+/// Added to a reply that ran out of tokens and couldn't be continued automatically.
+const TRUNCATED_NOTICE: &str =
+  "*(I ran out of room there. Say \"continue\" if you want the rest.)*";
+
+/// Quoted text from a document citation is shortened to this many characters in replies.
+const CITED_TEXT_CHARS: usize = 80;
+
+/// Reminders posted later than this, e.g. after a restart, say when they were due.
+const REMINDER_GRACE: Duration = Duration::from_secs(5 * 60);
+
+/// Reminders that still can't be posted this long after they were due are given up on.
+const REMINDER_EXPIRY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Minimum time between edits of a streaming reply.
+const EDIT_INTERVAL: Duration = Duration::from_millis(1_500);
 
 /// Represents the bot's response to a user message.
 /// Can be either successful text output or an error that occurred during processing.
@@ -31,7 +113,11 @@ impl From<BotResponse> for String {
   fn from(val: BotResponse) -> Self {
     match val {
       BotResponse::Text(s) => s.trim().into(),
-      BotResponse::Error(e) => format!(":skull: \n```\n{}\n```", e.to_string()).into(),
+      // API failures get a specific explanation; anything else is unexpected.
+      BotResponse::Error(e) => match e.downcast_ref::<crate::claude::Error>() {
+        Some(e) => format!(":skull: {}", e.user_message()),
+        None => format!(":skull: Something went wrong on my end: {}", e),
+      },
     }
   }
 }
@@ -40,11 +126,61 @@ impl From<BotResponse> for String {
 /// Manages conversation state, bot commands, and Claude AI integration.
 pub struct EventHandler<'a> {
   claude: Client,
+  /// An OpenAI-compatible server such as llama.cpp or Ollama, for guilds with `set-var provider = local`.
+  local: Option<OpenAIClient>,
   channels: HashMap<ChannelId, Channel>,
-  storage: Storage,
+  storage: Arc<Storage>,
   commands: Vec<Command>,
-  tools: ToolCollection,
+  think_hard: Regex,
+  tool_directive: Regex,
   audio: Option<crate::audio::AudioHandler<'a>>,
+  models: Registry,
+  /// Mixed into hashed user IDs, so they can't be reversed by hashing every possible ID.
+  user_salt: String,
+  deferred: Vec<DeferredJob>,
+  batches: Vec<PendingBatch>,
+  /// Uploaded files that dropped out of every conversation, deleted on the next tick.
+  stale_files: Vec<String>,
+  /// Guarded HTTP clients for `fetch_url`, shared by every message with the same guard.
+  fetch_clients: FetchClients,
+}
+
+/// Where a request came from, for usage accounting and delivering replies.
+#[derive(Clone, Copy, Debug)]
+struct Origin {
+  guild_id: u64,
+  channel_id: ChannelId,
+  user_id: u64,
+}
+
+/// A response that isn't needed right away, queued to go out in the next message batch.
+struct DeferredJob {
+  origin: Origin,
+  request: Request,
+  /// Uploaded files the request refers to, which have to outlive the batch.
+  files: Vec<String>,
+}
+
+/// A submitted message batch, and where each of its results should be posted.
+struct PendingBatch {
+  id: String,
+  origins: HashMap<String, Origin>,
+  /// Ticks on which the batch had ended but its results couldn't be downloaded.
+  failures: usize,
+  /// Uploaded files the batch's requests refer to. Not stored, since after a restart
+  /// there's no history left that could let them go stale.
+  files: Vec<String>,
+}
+
+/// Settings for a single response that come from configuration rather than the conversation.
+struct Turn {
+  /// Rendered system prompt for the guild.
+  prompt: String,
+  /// Model for turns without images.
+  text_model: Model,
+  /// Model for turns that include images.
+  vision_model: Model,
+  options: MessageOptions,
 }
 
 /// Represents a bot command that can be invoked via regex pattern matching.
@@ -62,10 +198,53 @@ impl<'a> EventHandler<'a> {
       regex: Regex::new(r#"(?ms)set-var\s+([A-Za-z_]+)\s*=\s*(.+)"#).unwrap(),
       invoke: |handler, cap, event| {
         let key = cap.get(1).unwrap().as_str().to_lowercase();
-        let val = cap.get(2).unwrap().as_str().trim();
+        let mut val = cap.get(2).unwrap().as_str().trim().to_owned();
+
+        if let Err(e) = GuildConfig::check_var(&key, &val) {
+          return Some(e);
+        }
+
+        if GuildConfig::is_admin_only(&key) && !Self::is_admin(event) {
+          return Some(format!("Only server admins can change `{}`.", key));
+        }
+
+        // model choices are checked against the registry, and stored as the ID they resolve to.
+        if key == "text_model" || key == "vision_model" {
+          match handler.models.validate(&val, key == "vision_model") {
+            Ok(model) => val = model.id().to_owned(),
+            Err(e) => return Some(e),
+          }
+        }
+
+        if key == "provider" && val == "local" && handler.local.is_none() {
+          return Some("There's no local model server set up. Set `LOCAL_LLM_URL` first.".into());
+        }
+
+        if key == "tool_choice"
+          && let ToolChoice::Tool { name, .. } = ToolChoice::parse(&val)
+          && !Self::guild_tools(handler, event).is_enabled(&name)
+        {
+          return Some(format!(
+            "I don't have a tool called `{}` turned on. Use `auto`, `any`, `none`, or the name of a tool.",
+            name
+          ));
+        }
+
+        if key == "tools"
+          && let Some(name) = val
+            .split(',')
+            .map(str::trim)
+            .find(|name| !name.is_empty() && *name != "none" && !registry::is_available(name))
+        {
+          return Some(format!(
+            "I don't have a tool called `{}`. Try `show-tools`.",
+            name
+          ));
+        }
+
         if let Some(id) = event.msg.guild_id {
           info!("Setting {:?} {} = {}", id, key, val);
-          handler.storage.update_config(id.into(), &key, val).ok();
+          handler.storage.update_config(id.into(), &key, &val).ok();
         }
         None
       },
@@ -85,32 +264,252 @@ impl<'a> EventHandler<'a> {
       },
     };
 
+    let toggle_tool = Command {
+      regex: Regex::new(r#"(?ms)(enable|disable)-tool\s+([A-Za-z_]+)"#).unwrap(),
+      invoke: |handler, cap, event| {
+        let enable = cap.get(1).unwrap().as_str() == "enable";
+        let name = cap.get(2).unwrap().as_str().to_lowercase();
+        let Some(guild_id) = event.msg.guild_id else {
+          return Some("Tools can only be changed in a server.".into());
+        };
+        if !Self::is_admin(event) {
+          return Some("Only server admins can change which tools I use.".into());
+        }
+
+        let mut settings = Self::guild_tools(handler, event);
+        if !settings.set_enabled(&name, enable) {
+          return Some(format!(
+            "I don't have a tool called `{}`. Try `show-tools`.",
+            name
+          ));
+        }
+
+        let val = match settings.enabled.is_empty() {
+          true => "none".to_owned(),
+          false => settings.enabled.join(","),
+        };
+        info!("Setting {:?} tools = {}", guild_id, val);
+        handler
+          .storage
+          .update_config(guild_id.into(), "tools", &val)
+          .ok();
+        None
+      },
+    };
+
+    let show_tools = Command {
+      regex: Regex::new(r#"(?ms)show-tools"#).unwrap(),
+      invoke: |handler, _cap, event| Some(Self::format_tools(&Self::guild_tools(handler, event))),
+    };
+
     let forget = Command {
       regex: Regex::new(r#"(?ms)forget-history"#).unwrap(),
       invoke: |handler, _cap, event| {
         let id = event.msg.channel_id;
         info!("Forgetting history for {:?}", id);
-        handler.channels.remove(&id);
+        if let Some(channel) = handler.channels.remove(&id) {
+          handler.stale_files.extend(channel.into_files());
+        }
         return Some("I didn't see nothin'".into());
       },
     };
 
+    let usage = Command {
+      regex: Regex::new(r#"(?ms)show-usage(?:\s+(day|week|month))?"#).unwrap(),
+      invoke: |handler, cap, event| {
+        let window = cap.get(1).map(|m| m.as_str()).unwrap_or("day");
+        let seconds = match window {
+          "week" => 7 * 86_400,
+          "month" => 30 * 86_400,
+          _ => 86_400,
+        };
+        let guild_id = event.msg.guild_id.map(|id| id.into()).unwrap_or(0u64);
+        let summary = handler
+          .storage
+          .usage_since(guild_id, Utc::now().timestamp() - seconds)
+          .ok()?;
+
+        Some(Self::format_usage(window, &summary))
+      },
+    };
+
+    let thinking = Command {
+      regex: Regex::new(r#"(?ms)show-thinking"#).unwrap(),
+      invoke: |handler, _cap, event| {
+        let channel = handler.channels.get(&event.msg.channel_id);
+        match channel.and_then(|c| c.last_thinking()) {
+          None => Some("I didn't think about my last answer at all.".into()),
+          Some((thinking, redacted)) => Some(Self::format_thinking(&thinking, redacted)),
+        }
+      },
+    };
+
+    let limits = Command {
+      regex: Regex::new(r#"(?ms)show-rate-limits"#).unwrap(),
+      invoke: |handler, _cap, _event| Some(Self::format_rate_limits(&handler.claude.rate_limits())),
+    };
+
+    let models = Command {
+      regex: Regex::new(r#"(?ms)show-models"#).unwrap(),
+      invoke: |handler, _cap, _event| Some(Self::format_models(&handler.models)),
+    };
+
+    let digest = Command {
+      regex: Regex::new(r#"(?ms)digest-later"#).unwrap(),
+      invoke: |handler, _cap, event| {
+        let guild_id = event.msg.guild_id.map(|id| id.into()).unwrap_or(0u64);
+        let config = handler.storage.guild_config(guild_id);
+
+        // batches are billed like any other response, so the budget applies to them too.
+        let mut model = DIGEST_MODEL;
+        if let Ok(cfg) = &config
+          && let Some(action) = Self::over_budget(&handler.storage, guild_id, cfg)
+        {
+          match action {
+            BudgetAction::Refuse => return Some(BUDGET_REFUSAL.into()),
+            BudgetAction::Downgrade => Self::downgrade(&mut model),
+          }
+        }
+
+        let (mut messages, files) = handler
+          .channels
+          .get_mut(&event.msg.channel_id)
+          .map(|channel| (channel.history().to_vec(), channel.files()))
+          .unwrap_or_default();
+
+        if messages.is_empty() {
+          return Some("There's nothing here to digest yet.".into());
+        }
+
+        // keep turns alternating if the last message never got a reply.
+        match messages.last_mut() {
+          Some(Interaction {
+            role: Role::User,
+            content,
+          }) => content.push(Content::text(DIGEST_REQUEST)),
+          _ => messages.push(Interaction {
+            role: Role::User,
+            content: vec![Content::text(DIGEST_REQUEST)],
+          }),
+        }
+
+        let prompt = config.map(|cfg| cfg.system()).unwrap_or_default();
+        let tool_meta = Self::guild_tools(handler, event)
+          .build(&mut handler.fetch_clients, None)
+          .iter()
+          .map(|t| t.metadata())
+          .cloned()
+          .collect::<Vec<_>>();
+        // batched responses can't run tools. they stay defined, since the history
+        // may already use them, but the model isn't allowed to call one.
+        let request = handler.claude.batch_request(
+          Some(model),
+          &messages,
+          &tool_meta,
+          prompt,
+          &MessageOptions {
+            tool_choice: Some(ToolChoice::None),
+            ..Default::default()
+          },
+        );
+
+        handler.deferred.push(DeferredJob {
+          origin: Origin {
+            guild_id,
+            channel_id: event.msg.channel_id,
+            user_id: event.msg.author.id.into(),
+          },
+          request,
+          files,
+        });
+        Some("I'll post a digest here once it's ready. It's cheaper to do it this way, but it can take a while.".into())
+      },
+    };
+
     let audio = if std::env::var("AUDIO_ENABLED").is_ok() {
       Some(crate::audio::AudioHandler::new("./storage/base.bin").unwrap())
     } else {
       None
     };
 
+    let mut claude = Client::builder(claude_key, DEFAULT_VISION_MODEL);
+    if let Ok(url) = std::env::var("CLAUDE_API_URL") {
+      claude = claude.base_url(url);
+    }
+
+    let local = std::env::var("LOCAL_LLM_URL").ok().map(|url| {
+      let model = std::env::var("LOCAL_LLM_MODEL").unwrap_or_else(|_| DEFAULT_LOCAL_MODEL.into());
+      let local = OpenAIClient::new(url, Model::new(model));
+      match std::env::var("LOCAL_LLM_KEY") {
+        Ok(key) => local.api_key(key),
+        Err(_) => local,
+      }
+    });
+
+    let storage = Arc::new(Storage::new(Path::new(storage_dir)).unwrap());
+    let batches = Self::load_batches(&storage);
+
     Self {
-      claude: Client::new(claude_key, claude::Model::Sonnet45),
+      claude: claude.build(),
+      local,
       channels: HashMap::new(),
-      storage: Storage::new(Path::new(storage_dir)).unwrap(),
-      commands: vec![set, get, forget],
-      tools: vec![],
+      storage,
+      commands: vec![
+        set,
+        get,
+        toggle_tool,
+        show_tools,
+        forget,
+        usage,
+        thinking,
+        limits,
+        models,
+        digest,
+      ],
+      think_hard: Regex::new(r#"(?i)\bthink\s+(hard|harder|deeply|carefully)\b"#).unwrap(),
+      tool_directive: Regex::new(r#"(?i)\b(?:use-tool\s+([A-Za-z0-9_-]+)|no-tools)\b"#).unwrap(),
       audio,
+      models: Registry::builtin(),
+      user_salt: std::env::var("USER_ID_SALT").unwrap_or_else(|_| claude_key.to_owned()),
+      deferred: vec![],
+      batches,
+      stale_files: vec![],
+      fetch_clients: FetchClients::default(),
     }
   }
 
+  /// Picks up batches that were submitted before the bot last stopped.
+  fn load_batches(storage: &Storage) -> Vec<PendingBatch> {
+    let jobs = match storage.pending_batch_jobs() {
+      Ok(jobs) => jobs,
+      Err(e) => {
+        error!("Failed to load pending batches: {}", e);
+        return vec![];
+      }
+    };
+
+    let mut batches: Vec<PendingBatch> = vec![];
+    for job in jobs {
+      let origin = Origin {
+        guild_id: job.guild_id,
+        channel_id: ChannelId::new(job.channel_id),
+        user_id: job.user_id,
+      };
+      match batches.last_mut() {
+        Some(batch) if batch.id == job.batch_id => {
+          batch.origins.insert(job.custom_id, origin);
+        }
+        _ => batches.push(PendingBatch {
+          id: job.batch_id,
+          origins: HashMap::from([(job.custom_id, origin)]),
+          failures: 0,
+          files: vec![],
+        }),
+      }
+    }
+    batches
+  }
+
   /// Main event processing loop that handles all incoming Discord events.
   /// Runs continuously, processing events from the dispatcher until shutdown.
   pub async fn start(storage_dir: &str, claude_key: &str, mut rx: UnboundedReceiver<BotEvent>) {
@@ -140,40 +539,287 @@ impl<'a> EventHandler<'a> {
   async fn on_event(&mut self, event: &BotEvent) {
     match event {
       BotEvent::Message(m) => self.on_message(m).await,
-      BotEvent::Ready(r) => self.on_ready(r),
+      BotEvent::Ready(r) => self.on_ready(r).await,
       BotEvent::ThreadUpdate(t) => self.on_thread_update(t),
+      BotEvent::Tick(t) => self.on_tick(t).await,
+    }
+  }
+
+  /// Runs scheduled work: posts reminders that are due, deletes uploaded files that are
+  /// no longer needed, submits queued jobs as a single message batch, and posts the
+  /// results of any batches that have finished.
+  async fn on_tick(&mut self, event: &TickEvent) {
+    self.deliver_reminders(event).await;
+
+    // files a queued or running batch still refers to are kept until it has ended.
+    let in_use = self
+      .deferred
+      .iter()
+      .flat_map(|job| &job.files)
+      .chain(self.batches.iter().flat_map(|batch| &batch.files))
+      .cloned()
+      .collect::<HashSet<_>>();
+    let (kept, stale) = std::mem::take(&mut self.stale_files)
+      .into_iter()
+      .partition::<Vec<_>, _>(|id| in_use.contains(id));
+    self.stale_files = kept;
+    for id in stale {
+      if let Err(e) = self.claude.delete_file(&id).await {
+        error!("Failed to delete file {}: {}", id, e);
+      }
+    }
+
+    if !self.deferred.is_empty() {
+      self.submit_deferred(event).await;
+    }
+
+    let mut pending = vec![];
+    for mut batch in std::mem::take(&mut self.batches) {
+      match self.claude.batch(&batch.id).await {
+        Ok(status) if status.is_ended() => {
+          if !self.finish_batch(event, &batch).await && Self::retry_batch(event, &mut batch).await {
+            pending.push(batch);
+          } else if let Err(e) = self.storage.remove_batch(&batch.id) {
+            error!("Failed to forget batch {}: {}", batch.id, e);
+          }
+        }
+        Ok(_) => pending.push(batch),
+        Err(e) => {
+          error!("Failed to check on batch {}: {}", batch.id, e);
+          pending.push(batch);
+        }
+      }
+    }
+    self.batches = pending;
+  }
+
+  /// Posts every reminder that has come due, including any missed while the bot was offline.
+  async fn deliver_reminders(&mut self, event: &TickEvent) {
+    let now = Utc::now().timestamp();
+    let due = match self.storage.due_reminders(now) {
+      Ok(due) => due,
+      Err(e) => {
+        error!("Failed to load reminders: {}", e);
+        return;
+      }
+    };
+
+    for reminder in due {
+      let channel_id = ChannelId::new(reminder.channel_id);
+      let msg = Self::reminder_message(&reminder, now);
+      let reply = msg
+        .clone()
+        .reference_message((channel_id, MessageId::new(reminder.message_id)));
+
+      // the message that asked for it may have been deleted since.
+      let mut sent = channel_id.send_message(event.ctx.http(), reply).await;
+      if let Err(e) = &sent {
+        debug!("Couldn't reply with reminder {}: {}", reminder.id, e);
+        sent = channel_id.send_message(event.ctx.http(), msg).await;
+      }
+
+      // a failure that might not happen again is retried on the next tick, for a while.
+      match sent {
+        Ok(_) => {}
+        Err(e) if Self::is_permanent(&e) => {
+          error!("Giving up on reminder {}: {}", reminder.id, e);
+        }
+        Err(e) if now - reminder.due_at > REMINDER_EXPIRY.as_secs() as i64 => {
+          error!(
+            "Giving up on reminder {}, it's a day late: {}",
+            reminder.id, e
+          );
+        }
+        Err(e) => {
+          error!("Failed to post reminder {}, will retry: {}", reminder.id, e);
+          continue;
+        }
+      }
+
+      if let Err(e) = self.storage.mark_reminder_delivered(reminder.id) {
+        error!("Failed to mark reminder {} delivered: {}", reminder.id, e);
+      }
     }
   }
 
+  /// Sends every queued job to the Message Batches API.
+  async fn submit_deferred(&mut self, event: &TickEvent) {
+    let mut origins = HashMap::new();
+    let mut files = vec![];
+    let requests = std::mem::take(&mut self.deferred)
+      .into_iter()
+      .enumerate()
+      .map(|(i, job)| {
+        let custom_id = format!("job-{}", i);
+        origins.insert(custom_id.clone(), job.origin);
+        files.extend(job.files);
+        BatchRequest {
+          custom_id,
+          params: job.request,
+        }
+      })
+      .collect();
+
+    match self.claude.create_batch(requests).await {
+      Ok(batch) => {
+        info!("Submitted batch {} with {} jobs", batch.id, origins.len());
+        let jobs = origins
+          .iter()
+          .map(|(custom_id, origin)| BatchJob {
+            batch_id: batch.id.clone(),
+            custom_id: custom_id.clone(),
+            guild_id: origin.guild_id,
+            channel_id: origin.channel_id.get(),
+            user_id: origin.user_id,
+          })
+          .collect_vec();
+        if let Err(e) = self.storage.add_batch_jobs(&jobs) {
+          error!("Failed to store batch {}: {}", batch.id, e);
+        }
+        self.batches.push(PendingBatch {
+          id: batch.id,
+          origins,
+          failures: 0,
+          files,
+        });
+      }
+      Err(e) => {
+        error!("Failed to submit batch: {}", e);
+        let reply: String = BotResponse::Error(e.into()).into();
+        let channels = origins
+          .values()
+          .map(|o| o.channel_id)
+          .unique()
+          .collect_vec();
+        for channel_id in channels {
+          Self::post(&event.ctx, channel_id, reply.clone()).await;
+        }
+      }
+    }
+  }
+
+  /// Downloads the results of an ended batch and posts each one where it was asked for.
+  /// Returns false if the results couldn't be downloaded.
+  async fn finish_batch(&mut self, event: &TickEvent, batch: &PendingBatch) -> bool {
+    let mut results = vec![];
+    if let Err(e) = self
+      .claude
+      .batch_results(&batch.id, |result| results.push(result))
+      .await
+    {
+      error!("Failed to fetch results for batch {}: {}", batch.id, e);
+      return false;
+    }
+
+    for result in results {
+      let Some(origin) = batch.origins.get(&result.custom_id) else {
+        continue;
+      };
+
+      let reply = match result.result {
+        BatchOutcome::Succeeded {
+          message:
+            Response::Message {
+              content,
+              model,
+              usage,
+              ..
+            },
+        } => {
+          Self::record_usage(&self.storage, origin, model, &usage, BATCH_PRICE_FACTOR);
+          content
+            .into_iter()
+            .filter_map(|c| match c {
+              Content::Text { text, .. } => Some(text),
+              _ => None,
+            })
+            .join("\n")
+        }
+        BatchOutcome::Succeeded {
+          message: Response::Error { error },
+        } => BotResponse::Error(crate::claude::Error::from(error).into()).into(),
+        BatchOutcome::Errored { error } => {
+          BotResponse::Error(crate::claude::Error::from(error.error).into()).into()
+        }
+        BatchOutcome::Canceled | BatchOutcome::Expired => {
+          "I never got around to that one, sorry.".into()
+        }
+      };
+
+      Self::post(&event.ctx, origin.channel_id, reply).await;
+    }
+    true
+  }
+
+  /// Counts a failed download of a batch's results. Returns whether to try again on
+  /// the next tick; once it has failed too many times, gives up and says so.
+  async fn retry_batch(event: &TickEvent, batch: &mut PendingBatch) -> bool {
+    batch.failures += 1;
+    if batch.failures < MAX_BATCH_RESULT_ATTEMPTS {
+      return true;
+    }
+
+    error!(
+      "Giving up on batch {} after {} failed downloads",
+      batch.id, batch.failures
+    );
+    let channels = batch
+      .origins
+      .values()
+      .map(|o| o.channel_id)
+      .unique()
+      .collect_vec();
+    for channel_id in channels {
+      Self::post(&event.ctx, channel_id, BATCH_LOST.into()).await;
+    }
+    false
+  }
+
   /// Handles Discord thread lifecycle events for conversation cleanup.
   /// Removes conversation history when threads are archived to prevent memory leaks.
   fn on_thread_update(&mut self, event: &ThreadUpdateEvent) {
     if let Some(metadata) = event.new.thread_metadata {
       if metadata.archived {
         debug!("Cleaning up channel {:?}", event.new.id);
-        self.channels.remove(&event.new.id);
+        if let Some(channel) = self.channels.remove(&event.new.id) {
+          self.stale_files.extend(channel.into_files());
+        }
       }
     }
   }
 
   /// Initializes bot state when Discord connection is established.
-  /// Ensures database configuration exists for all guilds the bot has access to.
-  fn on_ready(&self, event: &ReadyEvent) {
+  /// Ensures database configuration exists for all guilds the bot has access to,
+  /// and refreshes the list of models guilds can choose from.
+  async fn on_ready(&mut self, event: &ReadyEvent) {
     event
       .guilds
       .iter()
       .for_each(|&guild_id| self.storage.ensure_config(guild_id.into()));
+
+    match self.claude.registry().await {
+      Ok(models) => {
+        info!("Found {} models", models.models().len());
+        self.models = models;
+      }
+      Err(e) => error!("Failed to list models, using the built-in list: {}", e),
+    }
   }
 
   /// Core message processing logic that handles user interactions.
   /// Determines response eligibility, processes commands, manages conversation history,
   /// and coordinates with Claude AI to generate responses.
   async fn on_message(&mut self, event: &MsgEvent) {
-    let (is_respondable, msg_content) = join!(
-      Self::event_is_respondable(event),
-      Self::msg_to_content(event, &self.audio)
-    );
+    // events in a channel or thread will have a GuildId. direct messages will not.
+    // in that case, fall back to guild ID = 0 which is the global fallback configuration.
+    let guild_id = event.msg.guild_id.map(|id| id.into()).unwrap_or(0u64);
 
+    // we should always get a config back here, unless an SQL error occurs.
+    let config = self.storage.guild_config(guild_id);
+    let uses_local =
+      self.local.is_some() && config.as_ref().is_ok_and(|cfg| cfg.uses_local_model());
+
+    let is_respondable = Self::event_is_respondable(event).await;
     if is_respondable {
       match self.on_command(&event).await {
         Some(None) => {
@@ -188,6 +834,11 @@ impl<'a> EventHandler<'a> {
       }
     }
 
+    // images are only uploaded for messages that will be answered; the rest are just context.
+    // a local server can't read files uploaded to the Anthropic API.
+    let uploads = (is_respondable && !uses_local).then_some(&self.claude);
+    let msg_content = Self::msg_to_content(event, &self.audio, uploads).await;
+
     let id = event.msg.channel_id;
     let limit = match event
       .msg
@@ -198,7 +849,7 @@ impl<'a> EventHandler<'a> {
       .map(|gc| gc.kind)
     {
       Some(ChannelType::PublicThread) => None,
-      _ => Some(10),
+      _ => Some(CHANNEL_TOKEN_LIMIT),
     };
     let mut channel = self
       .channels
@@ -207,51 +858,179 @@ impl<'a> EventHandler<'a> {
 
     channel.ensure_valid_history();
     channel.user_message(msg_content);
+    self.stale_files.extend(channel.take_dropped_files());
 
     if !is_respondable {
       return;
     }
 
-    // send a typing indicator to the channel.
-    if let Ok(c) = event.msg.channel(&event.ctx).await {
-      match c {
-        DChannel::Guild(ch) => {
-          let _ = ch.broadcast_typing(&event.ctx.http).await;
+    let mut turn = Turn {
+      prompt: config
+        .as_ref()
+        .map(|cfg| cfg.system())
+        .unwrap_or_else(|_| "".into()),
+      text_model: config
+        .as_ref()
+        .ok()
+        .and_then(|cfg| cfg.text_model())
+        .map(Model::new)
+        .unwrap_or(DEFAULT_TEXT_MODEL),
+      vision_model: config
+        .as_ref()
+        .ok()
+        .and_then(|cfg| cfg.vision_model())
+        .map(Model::new)
+        .unwrap_or(DEFAULT_VISION_MODEL),
+      options: MessageOptions::default(),
+    };
+
+    // "think hard" in a message asks for extended thinking on that turn,
+    // on top of any budget the guild has configured.
+    let guild_budget = config.as_ref().ok().and_then(|cfg| cfg.thinking_budget());
+    let keyword_budget = self
+      .think_hard
+      .is_match(&event.msg.content)
+      .then_some(THINK_HARD_BUDGET);
+    turn.options.thinking_budget = guild_budget.max(keyword_budget);
+    turn.options.max_tokens = config.as_ref().ok().and_then(|cfg| cfg.max_tokens());
+
+    // "use-tool <name>" or "no-tools" in a message overrides the guild's tool choice for that turn.
+    turn.options.tool_choice = match self.tool_directive.captures(&event.msg.content) {
+      Some(cap) => Some(
+        cap
+          .get(1)
+          .map(|name| ToolChoice::parse(name.as_str()))
+          .unwrap_or(ToolChoice::None),
+      ),
+      None => config
+        .as_ref()
+        .ok()
+        .and_then(|cfg| cfg.tool_choice())
+        .map(ToolChoice::parse),
+    };
+    turn.options.disable_parallel_tool_use =
+      config.as_ref().is_ok_and(|cfg| !cfg.parallel_tool_use());
+
+    if let Ok(cfg) = &config {
+      turn.options.temperature = cfg.temperature();
+      turn.options.top_p = cfg.top_p();
+      turn.options.top_k = cfg.top_k();
+      turn.options.stop_sequences = cfg.stop_sequences();
+    }
+    turn.options.user_id = Some(Self::hash_user(&self.user_salt, event.msg.author.id.get()));
+
+    // the local server runs a single model, and has no extended thinking.
+    let (provider, price_factor): (&dyn Provider, f64) = match &self.local {
+      Some(local) if uses_local => {
+        turn.text_model = local.model();
+        turn.vision_model = local.model();
+        turn.options.thinking_budget = None;
+        (local, LOCAL_PRICE_FACTOR)
+      }
+      _ => (&self.claude, 1.0),
+    };
+
+    // local responses cost nothing, so they don't count against the budget.
+    if !uses_local
+      && let Ok(cfg) = &config
+      && let Some(action) = Self::over_budget(&self.storage, guild_id, cfg)
+    {
+      match action {
+        BudgetAction::Refuse => {
+          Self::send_reply(event, None, BUDGET_REFUSAL.into()).await;
+          return;
         }
-        DChannel::Private(ch) => {
-          let _ = ch.broadcast_typing(&event.ctx.http).await;
+        BudgetAction::Downgrade => {
+          Self::downgrade(&mut turn.text_model);
+          Self::downgrade(&mut turn.vision_model);
         }
-        _ => {}
-      };
+      }
     }
 
-    // events in a channel or thread will have a GuildId. direct messages will not.
-    // in that case, fall back to guild ID = 0 which is the global fallback configuration.
-    let guild_id = event.msg.guild_id.map(|id| id.into()).unwrap_or(0u64);
+    let mut tools = config
+      .as_ref()
+      .map(|cfg| cfg.tool_settings())
+      .unwrap_or_default()
+      .build(
+        &mut self.fetch_clients,
+        Some((
+          self.storage.clone(),
+          ReminderOrigin {
+            guild_id,
+            channel_id: id.get(),
+            user_id: event.msg.author.id.get(),
+            message_id: event.msg.id.get(),
+          },
+        )),
+      );
+    let tool_meta = tools
+      .iter()
+      .map(|t| t.metadata())
+      .cloned()
+      .collect::<Vec<_>>();
 
-    // we should always get a config back here, unless an SQL error occurs.
-    let prompt = self
-      .storage
-      .guild_config(guild_id)
-      .map(|cfg| cfg.system())
-      .unwrap_or_else(|_| "".into());
+    if let Some(ToolChoice::Tool { name, .. }) = &turn.options.tool_choice
+      && !tool_meta.iter().any(|t| t.name() == name)
+    {
+      Self::send_reply(
+        event,
+        None,
+        format!("I don't have a tool called `{}` turned on.", name),
+      )
+      .await;
+      return;
+    }
 
-    let replies =
-      match Self::dispatch_llm(&mut channel, prompt, &mut self.tools, &self.claude).await {
-        Ok(replies) => replies,
-        Err(e) => {
-          error!("{}", e);
+    Self::trim_history(&mut channel, &turn, &tool_meta, provider).await;
 
-          channel
-            .history()
-            .iter()
-            .for_each(|item| trace!("{:?}", item));
+    // post a placeholder reply right away; it is edited in place as the response streams in.
+    let placeholder = event
+      .msg
+      .reply(&event.ctx.http(), PLACEHOLDER)
+      .await
+      .map_err(|err| error!("Failed to send placeholder: {}", err))
+      .ok();
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    let mut usage = vec![];
 
-          vec![BotResponse::Error(e)]
+    let (result, _) = join!(
+      Self::dispatch_llm(&mut channel, &turn, &mut tools, provider, &mut usage, tx),
+      Self::stream_edits(event, placeholder.clone(), rx)
+    );
+    // trimming to fit the context window, or a failed request, may have dropped images.
+    self.stale_files.extend(channel.take_dropped_files());
+
+    let origin = Origin {
+      guild_id,
+      channel_id: id,
+      user_id: event.msg.author.id.into(),
+    };
+    for (model, usage) in usage {
+      Self::record_usage(&self.storage, &origin, model, &usage, price_factor);
+    }
+
+    let replies = match result {
+      Ok(replies) => replies,
+      Err(e) => {
+        error!("{}", e);
+        if let Some(api) = e.downcast_ref::<crate::claude::Error>() {
+          error!(
+            "request {} failed, retryable: {}, body: {}",
+            api.request_id().unwrap_or("unknown"),
+            api.is_retryable(),
+            api.body().unwrap_or("no body")
+          );
         }
-      };
 
-    channel.shrink();
+        channel
+          .history()
+          .iter()
+          .for_each(|item| trace!("{:?}", item));
+
+        vec![BotResponse::Error(e)]
+      }
+    };
 
     // bundle replies into single message.
     let reply = replies
@@ -260,44 +1039,441 @@ impl<'a> EventHandler<'a> {
       .filter(|s| !s.is_empty())
       .join(" ");
 
-    // check if the message is too long (2_000 characters).
-    // if so, convert it to a file attachment.
-    if reply.len() > 2_000 {
-      let attachment = CreateAttachment::bytes(reply.as_bytes(), "scrubby.txt");
-      event
-        .msg
-        .channel_id
-        .send_message(
-          &event.ctx.http(),
-          CreateMessage::new()
-            .add_file(attachment)
-            .content(":eyes:")
-            .reference_message(&event.msg),
+    Self::send_reply(event, placeholder, reply).await;
+  }
+
+  /// What to do about a guild that has spent its budget, or `None` if it hasn't.
+  fn over_budget(storage: &Storage, guild_id: u64, cfg: &GuildConfig) -> Option<BudgetAction> {
+    if !storage.over_budget(cfg).unwrap_or(false) {
+      return None;
+    }
+    info!("Guild {} is over budget", guild_id);
+    Some(cfg.budget_action())
+  }
+
+  /// Swaps `model` for the budget model, if it's the pricier of the two.
+  fn downgrade(model: &mut Model) {
+    if model.pricing() > BUDGET_MODEL.pricing() {
+      *model = BUDGET_MODEL;
+    }
+  }
+
+  /// An opaque ID for a Discord user, for the API's abuse detection.
+  /// The same user always gets the same ID, but it can't be traced back to them.
+  fn hash_user(salt: &str, user_id: u64) -> String {
+    let digest = Sha256::new()
+      .chain_update(salt.as_bytes())
+      .chain_update(user_id.to_be_bytes())
+      .finalize();
+
+    digest[..16].iter().map(|b| format!("{:02x}", b)).collect()
+  }
+
+  /// Stores the token usage and cost of a single API call.
+  /// `price_factor` scales the list price, e.g. for discounted batch requests.
+  fn record_usage(
+    storage: &Storage,
+    origin: &Origin,
+    model: String,
+    usage: &Usage,
+    price_factor: f64,
+  ) {
+    let record = UsageRecord {
+      guild_id: origin.guild_id,
+      channel_id: origin.channel_id.into(),
+      user_id: origin.user_id,
+      cost: Model::new(model.as_str()).cost(usage) * price_factor,
+      model,
+      input_tokens: usage.input_tokens,
+      output_tokens: usage.output_tokens,
+      cache_creation_tokens: usage.cache_creation_input_tokens.unwrap_or(0),
+      cache_read_tokens: usage.cache_read_input_tokens.unwrap_or(0),
+    };
+    storage
+      .record_usage(&record)
+      .map_err(|err| error!("Failed to record usage: {}", err))
+      .ok();
+  }
+
+  /// Posts a message to a channel outside of any conversation, e.g. the result of a batch job.
+  /// Messages longer than Discord's 2_000 character limit are sent as a file attachment.
+  async fn post(ctx: &Context, channel_id: ChannelId, text: String) {
+    let msg = if text.len() > 2_000 {
+      let attachment = CreateAttachment::bytes(text.as_bytes(), "scrubby.txt");
+      CreateMessage::new().add_file(attachment).content(":eyes:")
+    } else {
+      CreateMessage::new().content(text)
+    };
+
+    channel_id
+      .send_message(ctx.http(), msg)
+      .await
+      .map_err(|err| error!("Failed to send message: {}", err))
+      .ok();
+  }
+
+  /// Whether Discord refused a request in a way retrying won't fix, like a channel
+  /// that's gone or that the bot can no longer post in.
+  fn is_permanent(e: &serenity::Error) -> bool {
+    match e {
+      serenity::Error::Http(e) => e
+        .status_code()
+        .is_some_and(|status| matches!(status.as_u16(), 403 | 404)),
+      _ => false,
+    }
+  }
+
+  /// The message a reminder is posted as. The text came from the model, so only the
+  /// user who asked for it can be pinged, never `@everyone` or a role.
+  fn reminder_message(reminder: &Reminder, now: i64) -> CreateMessage {
+    CreateMessage::new()
+      .content(Self::format_reminder(reminder, now))
+      .allowed_mentions(
+        CreateAllowedMentions::new()
+          .everyone(false)
+          .all_roles(false)
+          .users([UserId::new(reminder.user_id)]),
+      )
+  }
+
+  /// Renders a reminder as it's posted, owning up to it if it's late.
+  fn format_reminder(reminder: &Reminder, now: i64) -> String {
+    let mut text = format!("<@{}> ⏰ {}", reminder.user_id, reminder.text);
+    if now - reminder.due_at > REMINDER_GRACE.as_secs() as i64 {
+      text.push_str(&format!(
+        "\n*(Sorry, this was due {}.)*",
+        discord_time(reminder.due_at)
+      ));
+    }
+    text
+  }
+
+  /// Renders a usage summary for the `show-usage` command.
+  fn format_usage(window: &str, summary: &[UsageSummary]) -> String {
+    if summary.is_empty() {
+      return format!("I haven't used any tokens in the last {}.", window);
+    }
+
+    let lines = summary
+      .iter()
+      .map(|s| {
+        format!(
+          "`{}`: {} calls, {} in ({} cached, {} written to cache) / {} out tokens, ${:.4}",
+          s.model,
+          s.calls,
+          s.input_tokens,
+          s.cache_read_tokens,
+          s.cache_creation_tokens,
+          s.output_tokens,
+          s.cost
         )
+      })
+      .join("\n");
+    let total = summary.iter().map(|s| s.cost).sum::<f64>();
+
+    // cache reads are billed at 10% of the normal input price.
+    let saved = summary
+      .iter()
+      .map(|s| s.cache_read_tokens as f64 * Model::new(s.model.as_str()).pricing().0 * 0.9)
+      .sum::<f64>()
+      / 1_000_000.0;
+
+    format!(
+      "**Usage for the last {}**\n{}\nTotal: ${:.4} (prompt caching saved ${:.4})",
+      window, lines, total, saved
+    )
+  }
+
+  /// Renders the models guilds can pick from for the `show-models` command.
+  fn format_models(registry: &Registry) -> String {
+    let lines = registry
+      .models()
+      .iter()
+      .map(|info| {
+        let caps = &info.capabilities;
+        let mut features = vec![];
+        if caps.vision {
+          features.push("vision");
+        }
+        if caps.thinking {
+          features.push("thinking");
+        }
+
+        format!(
+          "`{}` ({}): ${}/${} per MTok, {}k context{}",
+          info.model,
+          info.display_name,
+          caps.pricing.0,
+          caps.pricing.1,
+          caps.context_window / 1_000,
+          features
+            .iter()
+            .map(|f| format!(", {}", f))
+            .collect::<String>()
+        )
+      })
+      .join("\n");
+
+    format!(
+      "**Models**\n{}\nPick one with `set-var text_model = <id>` or `set-var vision_model = <id>`.",
+      lines
+    )
+  }
+
+  /// The tool settings for the guild a command was sent from.
+  fn guild_tools(handler: &EventHandler, event: &MsgEvent) -> ToolSettings {
+    let guild_id = event.msg.guild_id.map(|id| id.into()).unwrap_or(0u64);
+    handler
+      .storage
+      .guild_config(guild_id)
+      .map(|cfg| cfg.tool_settings())
+      .unwrap_or_default()
+  }
+
+  /// Whether the author of a message may manage the server it was sent in.
+  fn is_admin(event: &MsgEvent) -> bool {
+    event
+      .msg
+      .author_permissions(&event.ctx.cache)
+      .is_some_and(|p| p.administrator() || p.manage_guild())
+  }
+
+  /// Renders the tools a guild can use for the `show-tools` command.
+  fn format_tools(settings: &ToolSettings) -> String {
+    let lines = registry::AVAILABLE
+      .iter()
+      .map(|&(name, description)| {
+        let mut line = format!(
+          "{} `{}`: {}",
+          if settings.is_enabled(name) {
+            "✅"
+          } else {
+            "❌"
+          },
+          name,
+          description
+        );
+        if name == registry::FETCH_URL {
+          line.push_str(&format!(
+            " (pages cut to {} characters",
+            settings.fetch_max_chars
+          ));
+          if let Some(allowed) = &settings.fetch_allowed_domains {
+            line.push_str(&format!(", only from {}", allowed.join(", ")));
+          }
+          if let Some(blocked) = &settings.fetch_blocked_domains {
+            line.push_str(&format!(", never from {}", blocked.join(", ")));
+          }
+          line.push(')');
+        }
+        if name == registry::WEB_SEARCH {
+          line.push_str(&format!(
+            " (up to {} searches per response",
+            settings.web_search_max_uses
+          ));
+          if let Some(blocked) = &settings.web_search_blocked_domains {
+            line.push_str(&format!(", never from {}", blocked.join(", ")));
+          }
+          line.push(')');
+        }
+        line
+      })
+      .join("\n");
+
+    format!(
+      "**Tools**\n{}\nAdmins can turn them on and off with `enable-tool <name>` and `disable-tool <name>`, limit fetched pages with `set-var fetch_max_chars = <n>`, `set-var fetch_allowed_domains = <domain>, <domain>` or `set-var fetch_blocked_domains = <domain>, <domain>`, and configure web search with `set-var web_search_max_uses = <n>` or `set-var web_search_blocked_domains = <domain>, <domain>`.",
+      lines
+    )
+  }
+
+  /// Renders the API quota for the `show-rate-limits` command.
+  /// Reset times use Discord timestamps so they show up in the reader's own time zone.
+  fn format_rate_limits(status: &RateLimitStatus) -> String {
+    let quotas = [
+      ("requests", &status.requests),
+      ("input tokens", &status.input_tokens),
+      ("output tokens", &status.output_tokens),
+    ];
+
+    let mut lines = quotas
+      .iter()
+      .filter_map(|(name, quota)| {
+        let (limit, remaining) = quota.limit.zip(quota.remaining)?;
+        let reset = quota
+          .reset
+          .map(|t| format!(", resets <t:{}:R>", t.timestamp()))
+          .unwrap_or_default();
+        Some(format!(
+          "{}: {} of {} left{}",
+          name, remaining, limit, reset
+        ))
+      })
+      .collect_vec();
+
+    if lines.is_empty() {
+      lines.push("I haven't heard about any rate limits yet.".into());
+    }
+    if let Some(until) = status.paused_until.filter(|t| *t > Utc::now()) {
+      lines.push(format!("backing off until <t:{}:T>", until.timestamp()));
+    }
+    lines.push(format!("throttled {} times so far", status.throttled));
+
+    format!("**Rate limits**\n{}", lines.join("\n"))
+  }
+
+  /// Formats citations for the end of a reply: web pages by URL, and documents by
+  /// where the quote came from, with a shortened copy of the quoted text.
+  fn format_citations(citations: &[Citation]) -> String {
+    citations
+      .iter()
+      .map(|citation| {
+        let (title, location) = match citation {
+          Citation::WebSearchResult { url, .. } => return format!("`{}`", url),
+          Citation::Page {
+            document_title,
+            start_page_number,
+            end_page_number,
+            ..
+          } => {
+            let location = match end_page_number.saturating_sub(1) {
+              end if end > *start_page_number => format!("pp. {}-{}", start_page_number, end),
+              _ => format!("p. {}", start_page_number),
+            };
+            (document_title, location)
+          }
+          Citation::Char { document_title, .. } => (document_title, String::new()),
+        };
+
+        let quote = citation.cited_text().trim();
+        let quote = match quote.char_indices().nth(CITED_TEXT_CHARS) {
+          Some((end, _)) => format!("{}…", &quote[..end]),
+          None => quote.to_owned(),
+        };
+        let source = [title.as_deref().unwrap_or("document"), &location]
+          .into_iter()
+          .filter(|s| !s.is_empty())
+          .join(", ");
+
+        format!("\"{}\" ({})", quote, source)
+      })
+      .unique()
+      .join(" ")
+  }
+
+  /// Renders Claude's reasoning for the `show-thinking` command as a collapsed spoiler,
+  /// trimmed down so it fits comfortably in a single Discord message.
+  fn format_thinking(thinking: &str, redacted: bool) -> String {
+    let mut summary = thinking.trim().replace("||", "|");
+    if summary.chars().count() > THINKING_SUMMARY_CHARS {
+      summary = summary.chars().take(THINKING_SUMMARY_CHARS).collect();
+      summary.push('…');
+    }
+
+    let mut reply = String::from(":brain: here's what I was thinking:");
+    if !summary.is_empty() {
+      reply.push_str(&format!("\n||{}||", summary));
+    }
+    if redacted {
+      reply.push_str("\n*(some of it was redacted for safety reasons)*");
+    }
+    reply
+  }
+
+  /// Mirrors streamed text into the placeholder reply while Claude is still generating.
+  /// Edits are throttled so a fast stream doesn't run into Discord's rate limits.
+  async fn stream_edits(
+    event: &MsgEvent,
+    placeholder: Option<Message>,
+    mut rx: UnboundedReceiver<String>,
+  ) {
+    let Some(mut msg) = placeholder else {
+      return;
+    };
+    let mut text = String::new();
+    let mut last_edit = Instant::now();
+
+    while let Some(chunk) = rx.recv().await {
+      text.push_str(&chunk);
+
+      if last_edit.elapsed() < EDIT_INTERVAL || text.trim().is_empty() {
+        continue;
+      }
+      last_edit = Instant::now();
+
+      // discord caps messages at 2_000 characters; the final reply handles overflow.
+      let preview = if text.chars().count() > 1_990 {
+        text.chars().take(1_990).chain("…".chars()).collect()
+      } else {
+        text.clone()
+      };
+
+      msg
+        .edit(&event.ctx.http(), EditMessage::new().content(preview))
         .await
-        .map_err(|err| error!("Failed to send message: {}", err))
-        .ok();
-    } else if !reply.is_empty() {
-      event
-        .msg
-        .reply(&event.ctx.http(), reply)
-        .await
-        .map_err(|err| error!("Failed to reply: {}", err))
+        .map_err(|err| error!("Failed to update reply: {}", err))
         .ok();
     }
-    // each reply sent individually
-    //
-    // for r in replies.into_iter() {
-    //   let s: String = r.into();
-    //   if !s.is_empty() {
-    //     event
-    //       .msg
-    //       .reply(&event.ctx.http(), s)
-    //       .await
-    //       .map_err(|err| error!("Failed to reply: {}", err))
-    //       .ok();
-    //   }
-    // }
+  }
+
+  /// Delivers the final reply, replacing the placeholder message if one was posted.
+  /// Replies longer than Discord's 2_000 character limit are sent as a file attachment.
+  async fn send_reply(event: &MsgEvent, placeholder: Option<Message>, reply: String) {
+    let http = event.ctx.http();
+
+    match placeholder {
+      Some(mut msg) if reply.len() > 2_000 => {
+        let attachment = CreateAttachment::bytes(reply.as_bytes(), "scrubby.txt");
+        msg
+          .edit(
+            http,
+            EditMessage::new()
+              .content(":eyes:")
+              .new_attachment(attachment),
+          )
+          .await
+          .map_err(|err| error!("Failed to update reply: {}", err))
+          .ok();
+      }
+      Some(msg) if reply.is_empty() => {
+        msg
+          .delete(http)
+          .await
+          .map_err(|err| error!("Failed to remove placeholder: {}", err))
+          .ok();
+      }
+      Some(mut msg) => {
+        msg
+          .edit(http, EditMessage::new().content(reply))
+          .await
+          .map_err(|err| error!("Failed to update reply: {}", err))
+          .ok();
+      }
+      None if reply.len() > 2_000 => {
+        let attachment = CreateAttachment::bytes(reply.as_bytes(), "scrubby.txt");
+        event
+          .msg
+          .channel_id
+          .send_message(
+            http,
+            CreateMessage::new()
+              .add_file(attachment)
+              .content(":eyes:")
+              .reference_message(&event.msg),
+          )
+          .await
+          .map_err(|err| error!("Failed to send message: {}", err))
+          .ok();
+      }
+      None if !reply.is_empty() => {
+        event
+          .msg
+          .reply(http, reply)
+          .await
+          .map_err(|err| error!("Failed to reply: {}", err))
+          .ok();
+      }
+      None => {}
+    }
   }
 
   /// Determines if the bot should respond to a particular message.
@@ -339,7 +1515,11 @@ impl<'a> EventHandler<'a> {
 
   /// Converts Discord message data into Claude-compatible content format.
   /// Processes text, images, audio transcriptions, and document attachments for AI consumption.
-  async fn msg_to_content(event: &MsgEvent, audio: &'_ Option<AudioHandler<'_>>) -> Vec<Content> {
+  async fn msg_to_content(
+    event: &MsgEvent,
+    audio: &'_ Option<AudioHandler<'_>>,
+    uploads: Option<&Client>,
+  ) -> Vec<Content> {
     let mut items = vec![];
     let text = event
       .msg
@@ -365,16 +1545,19 @@ impl<'a> EventHandler<'a> {
       match content_type {
         Some("image/jpeg") | Some("image/png") | Some("image/gif") | Some("image/webp") => {
           if let Ok(bytes) = attachment.download().await {
-            let res = crate::claude::util::resize_image(bytes, 600, 600);
-            if let Ok(bytes) = res {
-              let data = BASE64_STANDARD.encode(&bytes);
-
-              items.push(Content::Image {
-                source: ImageSource::Base64 {
-                  media_type: "image/png".into(),
-                  data,
-                },
-              })
+            match crate::claude::util::resize_image(bytes, 600, 600) {
+              Ok(png) => {
+                let source = Self::upload_image(uploads, &attachment.filename, png).await;
+                items.push(Content::image(source));
+              }
+              // Discord's attachment URLs expire, so there's nothing to point the API at instead.
+              Err(e) => {
+                error!("Failed to resize {}: {}", attachment.filename, e);
+                items.push(Content::text(format!(
+                  "[{} couldn't be read]",
+                  attachment.filename
+                )));
+              }
             }
           }
         }
@@ -393,14 +1576,22 @@ impl<'a> EventHandler<'a> {
             }
           }
         }
+        Some("application/pdf") => {
+          if let Ok(bytes) = attachment.download().await {
+            items.push(Content::document(
+              DocumentSource::pdf(&bytes),
+              &attachment.filename,
+            ));
+          }
+        }
         Some("text/plain") => {
           if let Ok(bytes) = attachment.download().await {
             match String::from_utf8(bytes) {
               Err(e) => error!("Failed to decode text attachment: {}", e),
-              Ok(s) => items.push(Content::text(format!(
-                "<document name=\"{}\">\n{}</document>",
-                attachment.filename, s
-              ))),
+              Ok(s) => items.push(Content::document(
+                DocumentSource::text(s),
+                &attachment.filename,
+              )),
             }
           }
         }
@@ -408,10 +1599,10 @@ impl<'a> EventHandler<'a> {
           if let Ok(bytes) = attachment.download().await {
             match String::from_utf8(bytes) {
               Err(e) => error!("Failed to decode text attachment: {}", e),
-              Ok(s) => items.push(Content::text(format!(
-                "<document name=\"{}\">\n{}</document>",
-                attachment.filename, s
-              ))),
+              Ok(s) => items.push(Content::document(
+                DocumentSource::text(s),
+                &attachment.filename,
+              )),
             }
           }
         }
@@ -423,25 +1614,96 @@ impl<'a> EventHandler<'a> {
     items
   }
 
+  /// Uploads a resized image so later requests can refer to it by ID instead of
+  /// carrying it inline. Falls back to inlining it if there's nowhere to upload it, or the upload fails.
+  async fn upload_image(uploads: Option<&Client>, filename: &str, png: Vec<u8>) -> ImageSource {
+    if let Some(claude) = uploads {
+      match claude.upload_file(filename, "image/png", &png).await {
+        Ok(file) => return ImageSource::File { file_id: file.id },
+        Err(e) => error!("Failed to upload {}, sending it inline: {}", filename, e),
+      }
+    }
+
+    ImageSource::Base64 {
+      media_type: "image/png".into(),
+      data: BASE64_STANDARD.encode(&png),
+    }
+  }
+
+  /// Picks the model for the next request.
+  /// Conversations with images need a vision-capable model, and thinking needs a model that supports it.
+  fn choose_model(channel: &Channel, turn: &Turn) -> Model {
+    let model = match channel.history_has_images() {
+      true => &turn.vision_model,
+      false => &turn.text_model,
+    };
+
+    // not every model can think; step up to the cheapest one that can.
+    match model {
+      m if turn.options.thinking_budget.is_some() && !m.supports_thinking() => THINKING_MODEL,
+      m => m.clone(),
+    }
+  }
+
+  /// Trims the channel history so the next request fits in the model's context window,
+  /// leaving room for the system prompt, tool definitions and the response.
+  /// Once the history gets close to its budget, the local estimate is checked
+  /// against the `count_tokens` endpoint so trimming isn't thrown off by a bad guess.
+  async fn trim_history(
+    channel: &mut Channel,
+    turn: &Turn,
+    tools: &[Tool],
+    provider: &dyn Provider,
+  ) {
+    let model = Self::choose_model(channel, turn);
+    let overhead = tokens::estimate_text(&turn.prompt) + tokens::estimate_tools(tools);
+    let available = model
+      .context_window()
+      .saturating_sub(turn.options.max_tokens() + overhead);
+    let estimate = channel.estimated_tokens();
+
+    let mut scale = 1.0;
+    if estimate as f64 > channel.budget(available) as f64 * CALIBRATION_THRESHOLD {
+      let counted = provider
+        .count_tokens(
+          Some(model),
+          channel.history(),
+          tools,
+          turn.prompt.clone(),
+          &turn.options,
+        )
+        .await;
+
+      match counted {
+        Ok(counted) => scale = counted as f64 / (estimate + overhead).max(1) as f64,
+        Err(e) => error!("Failed to count tokens, using the estimate: {}", e),
+      }
+    }
+
+    channel.shrink(available, scale);
+  }
+
   /// Manages the conversation flow with Claude AI, including tool usage.
   /// Handles the request-response cycle, processes tool calls, and manages model selection
   /// based on conversation content (images require vision-capable models).
   async fn dispatch_llm(
     channel: &mut Channel,
-    prompt: String,
+    turn: &Turn,
     mut tools: &mut ToolCollection,
-    claude: &Client,
+    provider: &dyn Provider,
+    usage: &mut Vec<(String, Usage)>,
+    progress: UnboundedSender<String>,
   ) -> anyhow::Result<Vec<BotResponse>> {
-    let mut output = vec![];
+    let mut output: Vec<String> = vec![];
+    let mut options = turn.options.clone();
 
     let mut done = false;
+    // set when the next response picks up where the last one was cut off.
+    let mut continuing = false;
+    let mut continuations = 0;
 
     while !done {
-      let model = if channel.history_has_images() {
-        None
-      } else {
-        Some(Model::Haiku35)
-      };
+      let model = Self::choose_model(channel, turn);
       let history = channel.history();
       done = true;
       debug!(
@@ -456,64 +1718,96 @@ impl<'a> EventHandler<'a> {
         .cloned()
         .collect::<Vec<_>>();
 
-      let resp = claude
-        .create_message(model, &history, &tool_meta, prompt.clone())
+      let resp = provider
+        .create_message_stream(
+          Some(model),
+          &history,
+          &tool_meta,
+          turn.prompt.clone(),
+          &options,
+          &mut |text: &str| {
+            progress.send(text.to_owned()).ok();
+          },
+        )
         .await;
       debug!("Claude Returned: {:?}", resp);
 
       match resp {
-        Ok(Response::Message { content, .. }) => {
-          channel.bot_message(Interaction {
-            role: Role::Assistant,
-            content: content.clone(),
-          });
+        Ok(Response::Message {
+          content,
+          model,
+          usage: u,
+          stop_reason,
+          ..
+        }) => {
+          usage.push((model, u));
+
+          // a truncated response can only be picked up again if it ended mid-text,
+          // and the API doesn't allow prefilling a response while thinking is enabled.
+          let truncated = stop_reason.as_deref() == Some("max_tokens");
+          let resumable = truncated
+            && options.thinking_budget.is_none()
+            && matches!(content.last(), Some(Content::Text { .. }));
+          // long-running server tools pause the turn; sending it back as-is resumes it.
+          let paused = stop_reason.as_deref() == Some("pause_turn");
+
+          if continuing {
+            channel.continue_assistant(content.clone());
+          } else {
+            channel.bot_message(Interaction {
+              role: Role::Assistant,
+              content: content.clone(),
+            });
+          }
 
           let mut tool_output = vec![];
+          let mut merge_text = std::mem::take(&mut continuing);
 
           for content in content.into_iter() {
+            let merge = std::mem::take(&mut merge_text);
             match content {
               Content::Text {
                 text,
                 citations: Some(citations),
+                ..
               } if !citations.is_empty() => {
-                let citations = citations
-                  .iter()
-                  .map(|c| format!("`{}`", c.url))
-                  .unique()
-                  .join(" ");
-                output.push(format!("{} [{}]", text, citations));
+                output.push(format!("{} [{}]", text, Self::format_citations(&citations)));
               }
-              Content::Text { text, .. } => {
-                output.push(text.clone());
-              }
-              Content::ToolUse { id, name, input } => {
+              Content::Text { text, .. } => match output.last_mut() {
+                Some(last) if merge => last.push_str(&text),
+                _ => output.push(text),
+              },
+              Content::ToolUse {
+                id, name, input, ..
+              } => {
                 done = false;
 
-                let tool_content = match crate::claude::tools::invoke_tool(&mut tools, &name, input)
+                let tool_content = match crate::claude::tools::invoke_tool(
+                  &mut tools,
+                  &name,
+                  input,
+                  options.cancel.as_ref(),
+                )
+                .await
                 {
-                  Err(e) => Content::ToolResult {
-                    tool_use_id: id,
-                    content: e.to_string(),
-                    is_error: true,
-                  },
-                  Ok(None) => Content::ToolResult {
-                    tool_use_id: id,
-                    content: "<no output>".into(),
-                    is_error: false,
-                  },
-                  Ok(Some(s)) => Content::ToolResult {
-                    tool_use_id: id,
-                    content: s.into(),
-                    is_error: false,
-                  },
+                  Err(e) => Content::tool_error(id, e),
+                  Ok(output) if output.is_empty() => {
+                    Content::tool_result(id, vec![Content::text("<no output>")], false)
+                  }
+                  Ok(output) => Content::tool_result(id, output.content, false),
                 };
                 tool_output.push(tool_content);
               }
+              // thinking stays in the history (signatures included) but isn't shown;
+              // the show-thinking command surfaces it on request.
+              Content::Thinking { .. } | Content::RedactedThinking { .. } => {}
               Content::ServerToolUse { .. }
               | Content::WebSearchToolResult { .. }
               | Content::CodeExecutionToolResult { .. } => { /* nothing to do here */ }
               // the LLM should never respond with an image or tool result.
-              Content::Image { .. } | Content::ToolResult { .. } => unreachable!(),
+              Content::Image { .. } | Content::Document { .. } | Content::ToolResult { .. } => {
+                unreachable!()
+              }
             }
           }
           if !tool_output.is_empty() {
@@ -521,6 +1815,32 @@ impl<'a> EventHandler<'a> {
               role: Role::User,
               content: tool_output,
             });
+
+            // a forced tool has been used; let the model answer with its result.
+            // thinking stays off for the rest of the turn, as the API wants every
+            // assistant turn to start with a thinking block once it's enabled.
+            if options
+              .tool_choice
+              .as_ref()
+              .is_some_and(|c| c.forces_tool_use())
+            {
+              options.tool_choice = None;
+              options.thinking_budget = None;
+            }
+          }
+
+          if (resumable || paused) && continuations < MAX_CONTINUATIONS {
+            debug!("Continuing a response that stopped with {:?}", stop_reason);
+            continuations += 1;
+            continuing = true;
+            done = false;
+            channel.prepare_continuation();
+            // mirror the whitespace trimmed from the prefill, so the next part lines up.
+            if let Some(last) = output.last_mut() {
+              last.truncate(last.trim_end().len());
+            }
+          } else if truncated {
+            output.push(TRUNCATED_NOTICE.into());
           }
         }
         Ok(Response::Error { .. }) => unreachable!(),
@@ -540,3 +1860,515 @@ impl<'a> EventHandler<'a> {
     )
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::claude::cassette::Cassette;
+  use crate::claude::testing::{FakeServer, Scripted};
+  use crate::claude::{Schema, Tool as ToolMetadata};
+
+  struct EchoTool(ToolMetadata);
+
+  #[async_trait::async_trait]
+  impl crate::claude::tools::Tool for EchoTool {
+    fn metadata(&self) -> &ToolMetadata {
+      &self.0
+    }
+
+    async fn invoke(&mut self, params: serde_json::Value) -> Result<ToolOutput, String> {
+      let text = params["text"].as_str().unwrap_or_default();
+      let mut output = ToolOutput::text(text.to_uppercase());
+      output.content.push(Content::image(ImageSource::Base64 {
+        media_type: "image/png".into(),
+        data: "iVBORw==".into(),
+      }));
+      Ok(output)
+    }
+  }
+
+  fn echo() -> Box<dyn crate::claude::tools::Tool> {
+    Box::new(EchoTool(ToolMetadata::Custom {
+      name: "echo".into(),
+      description: "shouts the input back".into(),
+      input_schema: Box::new(Schema::object().with_property("text", Schema::string("text"), true)),
+      cache_control: None,
+    }))
+  }
+
+  #[test]
+  fn test_hash_user() {
+    let hashed = EventHandler::hash_user("salt", 1234);
+    assert_eq!(hashed.len(), 32);
+    assert_eq!(hashed, EventHandler::hash_user("salt", 1234));
+    assert_ne!(hashed, EventHandler::hash_user("pepper", 1234));
+    assert!(!hashed.contains("1234"));
+  }
+
+  #[test]
+  fn test_format_tools() {
+    let mut settings = ToolSettings::default();
+    settings.set_enabled(registry::FETCH_URL, true);
+    settings.set_enabled(registry::WEB_SEARCH, true);
+    settings.web_search_blocked_domains = Some(vec!["example.com".into()]);
+
+    let text = EventHandler::format_tools(&settings);
+    assert!(text.contains("✅ `fetch_url`: reads a web page (pages cut to 8000 characters)\n"));
+    assert!(text.contains(
+      "✅ `web_search`: searches the web, billed per search (up to 5 searches per response, never from example.com)\n"
+    ));
+    assert!(text.contains("❌ `code_execution`"));
+    assert!(text.contains("❌ `reminders`"));
+  }
+
+  #[test]
+  fn test_format_reminder() {
+    let reminder = Reminder {
+      id: 1,
+      guild_id: 1,
+      channel_id: 2,
+      user_id: 3,
+      message_id: 4,
+      text: "stretch".into(),
+      due_at: 1_000,
+    };
+
+    assert_eq!(
+      EventHandler::format_reminder(&reminder, 1_060),
+      "<@3> ⏰ stretch"
+    );
+    // delivered late, e.g. after the bot was offline.
+    assert_eq!(
+      EventHandler::format_reminder(&reminder, 10_000),
+      "<@3> ⏰ stretch\n*(Sorry, this was due <t:1000:F>.)*"
+    );
+
+    let reminder = Reminder {
+      text: "@everyone <@&5> stretch".into(),
+      ..reminder
+    };
+    let msg = serde_json::to_value(EventHandler::reminder_message(&reminder, 1_060)).unwrap();
+    assert_eq!(msg["content"], "<@3> ⏰ @everyone <@&5> stretch");
+    assert_eq!(
+      msg["allowed_mentions"],
+      serde_json::json!({ "parse": [], "users": ["3"], "roles": [] })
+    );
+  }
+
+  #[test]
+  fn test_format_citations() {
+    let citations = vec![
+      Citation::WebSearchResult {
+        url: "https://example.com".into(),
+        title: "Example".into(),
+        encrypted_index: "".into(),
+        cited_text: "an example".into(),
+      },
+      Citation::Page {
+        cited_text: "Revenue grew 12% year over year. ".into(),
+        document_index: 0,
+        document_title: Some("report.pdf".into()),
+        start_page_number: 3,
+        end_page_number: 4,
+      },
+      Citation::Page {
+        cited_text: "x".repeat(100),
+        document_index: 0,
+        document_title: None,
+        start_page_number: 5,
+        end_page_number: 7,
+      },
+      Citation::Char {
+        cited_text: "hello".into(),
+        document_index: 1,
+        document_title: Some("notes.txt".into()),
+        start_char_index: 0,
+        end_char_index: 5,
+      },
+    ];
+
+    assert_eq!(
+      EventHandler::format_citations(&citations),
+      format!(
+        "`https://example.com` \"Revenue grew 12% year over year.\" (report.pdf, p. 3) \"{}…\" (document, pp. 5-6) \"hello\" (notes.txt)",
+        "x".repeat(CITED_TEXT_CHARS)
+      )
+    );
+  }
+
+  #[tokio::test]
+  async fn test_dispatch_llm_tool_loop() {
+    let server = FakeServer::start(vec![
+      Scripted::tool_use(vec![
+        Content::text("Let me shout that."),
+        Content::tool_use("toolu_1", "echo", serde_json::json!({ "text": "hello" })),
+      ]),
+      Scripted::message(vec![Content::text("It said HELLO.")]),
+    ])
+    .await;
+
+    let mut channel = Channel::new(ChannelId::new(1), None);
+    channel.user_message(vec![Content::text("Someone: shout hello")]);
+    let mut tools: ToolCollection = vec![echo()];
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut usage = vec![];
+
+    let replies = EventHandler::dispatch_llm(
+      &mut channel,
+      &Turn {
+        prompt: "prompt".into(),
+        text_model: DEFAULT_TEXT_MODEL,
+        vision_model: DEFAULT_VISION_MODEL,
+        options: MessageOptions::default(),
+      },
+      &mut tools,
+      &server.client(),
+      &mut usage,
+      tx,
+    )
+    .await
+    .unwrap();
+
+    let replies = replies.into_iter().map(String::from).collect::<Vec<_>>();
+    assert_eq!(replies, vec!["Let me shout that.", "It said HELLO."]);
+
+    let mut streamed = String::new();
+    while let Ok(text) = rx.try_recv() {
+      streamed.push_str(&text);
+    }
+    assert_eq!(streamed, "Let me shout that.It said HELLO.");
+
+    // the tool result is sent back to claude, and everything lands in the history.
+    let requests = server.requests();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0].body["tools"][0]["name"], "echo");
+    assert_eq!(
+      requests[1].body["messages"][2]["content"][0],
+      serde_json::json!({
+        "type": "tool_result",
+        "tool_use_id": "toolu_1",
+        "content": [
+          { "type": "text", "text": "HELLO", "citations": null },
+          { "type": "image", "source": { "type": "base64", "media_type": "image/png", "data": "iVBORw==" } },
+        ],
+        "is_error": false,
+        "cache_control": { "type": "ephemeral" },
+      })
+    );
+    assert_eq!(channel.history().len(), 4);
+    assert_eq!(usage.len(), 2);
+    assert_eq!(usage[0].0, "claude-haiku-4-5-20251001");
+  }
+
+  #[tokio::test]
+  async fn test_dispatch_llm_forced_tool_is_used_once() {
+    let server = FakeServer::start(vec![
+      Scripted::tool_use(vec![Content::tool_use(
+        "toolu_1",
+        "echo",
+        serde_json::json!({ "text": "hello" }),
+      )]),
+      Scripted::message(vec![Content::text("It said HELLO.")]),
+    ])
+    .await;
+
+    let mut channel = Channel::new(ChannelId::new(1), None);
+    channel.user_message(vec![Content::text("Someone: use-tool echo hello")]);
+    let mut tools: ToolCollection = vec![echo()];
+    let (tx, _rx) = mpsc::unbounded_channel();
+
+    EventHandler::dispatch_llm(
+      &mut channel,
+      &Turn {
+        prompt: "prompt".into(),
+        text_model: DEFAULT_TEXT_MODEL,
+        vision_model: DEFAULT_VISION_MODEL,
+        options: MessageOptions {
+          tool_choice: Some(ToolChoice::parse("echo")),
+          disable_parallel_tool_use: true,
+          ..Default::default()
+        },
+      },
+      &mut tools,
+      &server.client(),
+      &mut vec![],
+      tx,
+    )
+    .await
+    .unwrap();
+
+    // once the forced tool has run, the model is free to answer.
+    let requests = server.requests();
+    assert_eq!(
+      requests[0].body["tool_choice"],
+      serde_json::json!({ "type": "tool", "name": "echo", "disable_parallel_tool_use": true })
+    );
+    assert_eq!(
+      requests[1].body["tool_choice"],
+      serde_json::json!({ "type": "auto", "disable_parallel_tool_use": true })
+    );
+  }
+
+  #[tokio::test]
+  async fn test_dispatch_llm_forced_tool_keeps_thinking_off() {
+    let server = FakeServer::start(vec![
+      Scripted::tool_use(vec![Content::tool_use(
+        "toolu_1",
+        "echo",
+        serde_json::json!({ "text": "hello" }),
+      )]),
+      Scripted::message(vec![Content::text("It said HELLO.")]),
+    ])
+    .await;
+
+    let mut channel = Channel::new(ChannelId::new(1), None);
+    channel.user_message(vec![Content::text("Someone: use-tool echo hello")]);
+    let mut tools: ToolCollection = vec![echo()];
+    let (tx, _rx) = mpsc::unbounded_channel();
+
+    EventHandler::dispatch_llm(
+      &mut channel,
+      &Turn {
+        prompt: "prompt".into(),
+        text_model: DEFAULT_TEXT_MODEL,
+        vision_model: DEFAULT_VISION_MODEL,
+        options: MessageOptions {
+          tool_choice: Some(ToolChoice::parse("echo")),
+          thinking_budget: Some(2_000),
+          ..Default::default()
+        },
+      },
+      &mut tools,
+      &server.client(),
+      &mut vec![],
+      tx,
+    )
+    .await
+    .unwrap();
+
+    // the forced tool_use turn has no thinking block, so the follow-up can't think either.
+    let requests = server.requests();
+    assert!(requests[0].body["thinking"].is_null());
+    assert!(requests[1].body["tool_choice"].is_null());
+    assert!(requests[1].body["thinking"].is_null());
+  }
+
+  #[tokio::test]
+  async fn test_dispatch_llm_continues_truncated_response() {
+    let cut_off = |text: &str| Scripted::Message {
+      content: vec![Content::text(text)],
+      stop_reason: "max_tokens".into(),
+    };
+    let server = FakeServer::start(vec![
+      cut_off("Once upon a "),
+      Scripted::message(vec![Content::text(" time.")]),
+      cut_off("The end"),
+      cut_off(" is"),
+      cut_off(" not"),
+      cut_off(" near"),
+    ])
+    .await;
+    let turn = Turn {
+      prompt: "prompt".into(),
+      text_model: DEFAULT_TEXT_MODEL,
+      vision_model: DEFAULT_VISION_MODEL,
+      options: MessageOptions::default(),
+    };
+
+    let mut channel = Channel::new(ChannelId::new(1), None);
+    channel.user_message(vec![Content::text("Someone: tell me a story")]);
+    let (tx, _rx) = mpsc::unbounded_channel();
+    let replies = EventHandler::dispatch_llm(
+      &mut channel,
+      &turn,
+      &mut vec![],
+      &server.client(),
+      &mut vec![],
+      tx,
+    )
+    .await
+    .unwrap();
+
+    let replies = replies.into_iter().map(String::from).collect::<Vec<_>>();
+    assert_eq!(replies, vec!["Once upon a time."]);
+    // the partial response is sent back as a prefill, minus its trailing whitespace.
+    let requests = server.requests();
+    assert_eq!(
+      requests[1].body["messages"][1]["content"][0]["text"],
+      "Once upon a"
+    );
+    assert_eq!(channel.history().len(), 2);
+
+    // after a few attempts, give up and say so.
+    channel.user_message(vec![Content::text("Someone: and then?")]);
+    let (tx, _rx) = mpsc::unbounded_channel();
+    let replies = EventHandler::dispatch_llm(
+      &mut channel,
+      &turn,
+      &mut vec![],
+      &server.client(),
+      &mut vec![],
+      tx,
+    )
+    .await
+    .unwrap();
+
+    let replies = replies.into_iter().map(String::from).collect::<Vec<_>>();
+    assert_eq!(
+      replies,
+      vec!["The end is not near".to_string(), TRUNCATED_NOTICE.into()]
+    );
+  }
+
+  #[tokio::test]
+  async fn test_dispatch_llm_error_undoes_user_message() {
+    let server = FakeServer::start(vec![Scripted::error(
+      401,
+      "authentication_error",
+      "invalid x-api-key",
+    )])
+    .await;
+
+    let mut channel = Channel::new(ChannelId::new(1), None);
+    channel.user_message(vec![Content::text("Someone: hi")]);
+    let (tx, _rx) = mpsc::unbounded_channel();
+
+    let result = EventHandler::dispatch_llm(
+      &mut channel,
+      &Turn {
+        prompt: "prompt".into(),
+        text_model: DEFAULT_TEXT_MODEL,
+        vision_model: DEFAULT_VISION_MODEL,
+        options: MessageOptions::default(),
+      },
+      &mut vec![],
+      &server.client(),
+      &mut vec![],
+      tx,
+    )
+    .await;
+
+    assert!(result.is_err());
+    assert!(channel.history().is_empty());
+  }
+
+  /// Replays a turn recorded in the cassette `name` through `dispatch_llm`.
+  async fn replay_turn(
+    name: &str,
+    message: &str,
+    mut tools: ToolCollection,
+  ) -> (anyhow::Result<Vec<String>>, Channel, Cassette) {
+    let (client, cassette) = crate::claude::cassette::client(name);
+    let mut channel = Channel::new(ChannelId::new(1), None);
+    channel.user_message(vec![Content::text(message)]);
+    let (tx, _rx) = mpsc::unbounded_channel();
+
+    let result = EventHandler::dispatch_llm(
+      &mut channel,
+      &Turn {
+        prompt: "You are Scrubby, a helpful Discord bot.".into(),
+        text_model: Model::HAIKU_45,
+        vision_model: Model::HAIKU_45,
+        options: MessageOptions::default(),
+      },
+      &mut tools,
+      &client,
+      &mut vec![],
+      tx,
+    )
+    .await
+    .map(|replies| replies.into_iter().map(String::from).collect());
+
+    (result, channel, cassette)
+  }
+
+  #[tokio::test]
+  async fn test_replay_web_search_citations() {
+    let (result, mut channel, _) = replay_turn(
+      "dispatch_web_search",
+      "Someone: when did Rust 1.90 come out?",
+      vec![Box::new(ServerTool(ToolMetadata::web_search(5, None)))],
+    )
+    .await;
+
+    assert_eq!(
+      result.unwrap(),
+      vec![
+        "Here's what I found:",
+        "Rust 1.90 was released on September 18, 2025. [`https://blog.rust-lang.org/2025/09/18/Rust-1.90.0/`]",
+      ]
+    );
+    // the search and its results stay in the history, so follow-ups can refer to them.
+    let reply = &channel.history()[1].content;
+    assert!(
+      matches!(&reply[0], Content::ServerToolUse { input, .. } if input["query"] == "Rust 1.90 release date")
+    );
+    assert!(matches!(&reply[1], Content::WebSearchToolResult { .. }));
+  }
+
+  #[tokio::test]
+  async fn test_replay_code_execution() {
+    let (result, mut channel, _) = replay_turn(
+      "dispatch_code_execution",
+      "Someone: add up the numbers from 1 to 100",
+      vec![Box::new(ServerTool(ToolMetadata::code_execution()))],
+    )
+    .await;
+
+    assert_eq!(
+      result.unwrap(),
+      vec![
+        "I'll calculate that.",
+        "The sum of the numbers from 1 to 100 is **5050**.",
+      ]
+    );
+    let reply = &channel.history()[1].content;
+    assert!(
+      matches!(&reply[2], Content::CodeExecutionToolResult { content, .. } if content["stdout"] == "5050\n")
+    );
+  }
+
+  #[tokio::test]
+  async fn test_replay_tool_loop() {
+    let (result, mut channel, cassette) =
+      replay_turn("dispatch_tool_loop", "Someone: shout hello", vec![echo()]).await;
+
+    assert_eq!(
+      result.unwrap(),
+      vec!["Let me shout that.", "It said HELLO."]
+    );
+    let requests = cassette.requests();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(
+      requests[1].body["messages"][2]["content"][0]["content"][0]["text"],
+      "HELLO"
+    );
+    assert_eq!(channel.history().len(), 4);
+  }
+
+  #[tokio::test]
+  async fn test_replay_overloaded_is_retried() {
+    let (result, _, cassette) = replay_turn("dispatch_overloaded", "Someone: hi", vec![]).await;
+
+    assert_eq!(result.unwrap(), vec!["Hi there!"]);
+    assert_eq!(cassette.requests().len(), 2);
+  }
+
+  #[tokio::test]
+  async fn test_replay_prompt_too_long() {
+    let (result, mut channel, _) = replay_turn(
+      "dispatch_prompt_too_long",
+      "Someone: summarise everything",
+      vec![],
+    )
+    .await;
+
+    let err = result.unwrap_err();
+    let err = err.downcast_ref::<crate::claude::Error>().unwrap();
+    assert_eq!(
+      err.user_message(),
+      "This conversation has gotten too long for me. Try `forget-history` and ask again. (request `req_011CTw2mJ8sQx4LpR7vNcT3k`)"
+    );
+    assert!(channel.history().is_empty());
+  }
+}
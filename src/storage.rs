@@ -1,19 +1,27 @@
+use chrono::{Datelike, Utc};
 use handlebars::Handlebars;
 use log::info;
-use rusqlite::{params, Connection, Result as SqlResult};
+use rusqlite::{Connection, Result as SqlResult, params};
 use serde_json;
 use std::collections::HashMap;
 use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
 
 use crate::PROMPT_TEMPLATE;
+use crate::claude::tools::registry::{self, ToolSettings};
 
 /// Default personality used when guilds don't have custom configuration.
 const DEFAULT_PERSONALITY: &'static str = "Neutral and informative. Feel free to use some good-natured insults or jabs. You can use some emoji sparingly";
 
+/// Largest `max_tokens` or `thinking_budget` a guild can set: no model writes more
+/// than this in one response.
+const MAX_OUTPUT_TOKENS: usize = 64_000;
+
 /// Manages guild configuration persistence using SQLite storage.
 /// Handles bot personality settings and other per-guild customizations.
 pub struct Storage {
-  conn: Connection,
+  /// Shared with tools like reminders, which run alongside the handler.
+  conn: Mutex<Connection>,
 }
 
 /// Represents a Discord guild's configuration stored in the database.
@@ -48,15 +56,279 @@ impl GuildConfig {
 
     tmpl.render("prompt", &map).unwrap()
   }
+
+  /// Returns a raw configuration value as set by the `set-var` command.
+  pub fn var(&self, key: &str) -> Option<&str> {
+    self.config.get(key).and_then(|v| v.as_str())
+  }
+
+  /// Maximum spend in USD per calendar day (UTC), if configured.
+  pub fn daily_budget(&self) -> Option<f64> {
+    self.var("daily_budget").and_then(|v| v.parse().ok())
+  }
+
+  /// Maximum spend in USD per calendar month (UTC), if configured.
+  pub fn monthly_budget(&self) -> Option<f64> {
+    self.var("monthly_budget").and_then(|v| v.parse().ok())
+  }
+
+  /// Extended thinking budget in tokens for every response, if configured.
+  pub fn thinking_budget(&self) -> Option<usize> {
+    self
+      .var("thinking_budget")
+      .and_then(|v| v.parse().ok())
+      .filter(|&budget| budget > 0)
+  }
+
+  /// Tokens for the visible part of each response, if configured.
+  /// Capped at whatever the model in use can produce.
+  pub fn max_tokens(&self) -> Option<usize> {
+    self
+      .var("max_tokens")
+      .and_then(|v| v.parse().ok())
+      .filter(|&tokens| tokens > 0)
+  }
+
+  /// Model ID for text-only turns, if configured.
+  pub fn text_model(&self) -> Option<&str> {
+    self.var("text_model")
+  }
+
+  /// Model ID for turns that include images, if configured.
+  pub fn vision_model(&self) -> Option<&str> {
+    self.var("vision_model")
+  }
+
+  /// Which tools the model may use: `auto`, `any`, `none`, or the name of a tool to force.
+  pub fn tool_choice(&self) -> Option<&str> {
+    self.var("tool_choice")
+  }
+
+  /// Whether the model may request several tools at once. Defaults to allowing it.
+  pub fn parallel_tool_use(&self) -> bool {
+    !matches!(self.var("parallel_tool_use"), Some("false" | "off" | "no"))
+  }
+
+  /// Sampling temperature from 0 to 1, if configured.
+  pub fn temperature(&self) -> Option<f64> {
+    self.unit_interval("temperature")
+  }
+
+  /// Nucleus sampling cutoff from 0 to 1, if configured.
+  pub fn top_p(&self) -> Option<f64> {
+    self.unit_interval("top_p")
+  }
+
+  /// Number of likeliest tokens to sample from, if configured.
+  pub fn top_k(&self) -> Option<usize> {
+    self
+      .var("top_k")
+      .and_then(|v| v.parse().ok())
+      .filter(|&k| k > 0)
+  }
+
+  /// Text that ends a response when the model produces it, separated by `|` in the config.
+  pub fn stop_sequences(&self) -> Vec<String> {
+    self
+      .var("stop_sequences")
+      .map(|v| {
+        v.split('|')
+          .filter(|s| !s.trim().is_empty())
+          .map(String::from)
+          .collect()
+      })
+      .unwrap_or_default()
+  }
+
+  /// Which tools are turned on and how they're configured, falling back to the defaults.
+  /// `tools` is a comma separated list of tool names; `none` turns every tool off.
+  pub fn tool_settings(&self) -> ToolSettings {
+    let list = |key: &str| {
+      self.var(key).map(|v| {
+        v.split(',')
+          .map(str::trim)
+          .filter(|s| !s.is_empty() && *s != "none")
+          .map(String::from)
+          .collect::<Vec<_>>()
+      })
+    };
+    let defaults = ToolSettings::default();
+
+    ToolSettings {
+      enabled: list("tools").unwrap_or(defaults.enabled),
+      fetch_max_chars: self
+        .var("fetch_max_chars")
+        .and_then(|v| v.parse().ok())
+        .filter(|&chars| chars > 0)
+        .unwrap_or(defaults.fetch_max_chars),
+      fetch_allowed_domains: list("fetch_allowed_domains").filter(|d| !d.is_empty()),
+      fetch_blocked_domains: list("fetch_blocked_domains").filter(|d| !d.is_empty()),
+      web_search_max_uses: self
+        .var("web_search_max_uses")
+        .and_then(|v| v.parse().ok())
+        .filter(|&uses| uses > 0)
+        .unwrap_or(defaults.web_search_max_uses),
+      web_search_blocked_domains: list("web_search_blocked_domains").filter(|d| !d.is_empty()),
+    }
+  }
+
+  /// Whether turns go to the local model server rather than the Anthropic API.
+  pub fn uses_local_model(&self) -> bool {
+    self.var("provider") == Some("local")
+  }
+
+  fn unit_interval(&self, key: &str) -> Option<f64> {
+    self
+      .var(key)
+      .and_then(|v| v.parse().ok())
+      .filter(|v| (0.0..=1.0).contains(v))
+  }
+
+  /// Checks a value before `set-var` stores it, so typos are caught right away
+  /// rather than silently ignored. Keys without a known type accept anything.
+  pub fn check_var(key: &str, val: &str) -> Result<(), String> {
+    let number = |min: f64, max: f64| match val.parse::<f64>() {
+      Ok(v) if (min..=max).contains(&v) => Ok(()),
+      _ if max.is_finite() => Err(format!(
+        "`{}` has to be a number from {} to {}.",
+        key, min, max
+      )),
+      _ => Err(format!("`{}` has to be a number of at least {}.", key, min)),
+    };
+    let whole = |max: usize| match val.parse::<usize>() {
+      Ok(v) if v <= max => Ok(()),
+      _ if max < usize::MAX => Err(format!("`{}` has to be a whole number up to {}.", key, max)),
+      _ => Err(format!("`{}` has to be a whole number.", key)),
+    };
+
+    match key {
+      "temperature" | "top_p" => number(0.0, 1.0),
+      "daily_budget" | "monthly_budget" => number(0.0, f64::INFINITY),
+      "max_tokens" | "thinking_budget" => whole(MAX_OUTPUT_TOKENS),
+      "top_k" | "web_search_max_uses" | "fetch_max_chars" => whole(usize::MAX),
+      "provider" if !matches!(val, "anthropic" | "local") => {
+        Err("`provider` has to be `anthropic` or `local`.".into())
+      }
+      "budget_action" if !matches!(val, "refuse" | "downgrade") => {
+        Err("`budget_action` has to be `refuse` or `downgrade`.".into())
+      }
+      "stop_sequences" if val.split('|').all(|s| s.trim().is_empty()) => {
+        Err("`stop_sequences` needs at least one sequence. Separate several with `|`.".into())
+      }
+      _ => Ok(()),
+    }
+  }
+
+  /// Whether only server admins may change `key`: spending limits, settings that
+  /// raise what each response costs, and tool settings.
+  pub fn is_admin_only(key: &str) -> bool {
+    matches!(
+      key,
+      "daily_budget"
+        | "monthly_budget"
+        | "budget_action"
+        | "text_model"
+        | "vision_model"
+        | "thinking_budget"
+        | "max_tokens"
+        | "provider"
+    ) || registry::is_tool_setting(key)
+  }
+
+  /// What to do once a budget has been used up. Defaults to refusing.
+  pub fn budget_action(&self) -> BudgetAction {
+    match self.var("budget_action") {
+      Some("downgrade") => BudgetAction::Downgrade,
+      _ => BudgetAction::Refuse,
+    }
+  }
+}
+
+/// How the bot behaves once a guild has spent its budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BudgetAction {
+  /// Politely decline to answer until the budget resets.
+  Refuse,
+  /// Keep answering, but with the cheapest capable model.
+  Downgrade,
+}
+
+/// A single API call's token usage, attributed to where it came from.
+#[derive(Debug)]
+pub struct UsageRecord {
+  pub guild_id: u64,
+  pub channel_id: u64,
+  pub user_id: u64,
+  pub model: String,
+  pub input_tokens: usize,
+  pub output_tokens: usize,
+  pub cache_creation_tokens: usize,
+  pub cache_read_tokens: usize,
+  pub cost: f64,
+}
+
+/// Aggregated usage for one model over a time window.
+#[derive(Debug)]
+pub struct UsageSummary {
+  pub model: String,
+  pub calls: usize,
+  pub input_tokens: usize,
+  pub output_tokens: usize,
+  pub cache_creation_tokens: usize,
+  pub cache_read_tokens: usize,
+  pub cost: f64,
+}
+
+/// A message Scrubby has been asked to post later, in reply to the message that asked for it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reminder {
+  /// Assigned by the database; ignored when adding a reminder.
+  pub id: i64,
+  pub guild_id: u64,
+  pub channel_id: u64,
+  pub user_id: u64,
+  pub message_id: u64,
+  pub text: String,
+  /// Unix timestamp of when it should be posted.
+  pub due_at: i64,
 }
 
+impl Reminder {
+  fn from_row(row: &rusqlite::Row) -> SqlResult<Self> {
+    Ok(Self {
+      id: row.get(0)?,
+      guild_id: row.get(1)?,
+      channel_id: row.get(2)?,
+      user_id: row.get(3)?,
+      message_id: row.get(4)?,
+      text: row.get(5)?,
+      due_at: row.get(6)?,
+    })
+  }
+}
+
+/// A request in a submitted message batch, and where its result should be posted.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatchJob {
+  pub batch_id: String,
+  /// Identifies the request's result within the batch.
+  pub custom_id: String,
+  pub guild_id: u64,
+  pub channel_id: u64,
+  pub user_id: u64,
+}
+
+const REMINDER_COLUMNS: &str = "id, guild_id, channel_id, user_id, message_id, text, due_at";
+
 impl Storage {
   /// Creates a new storage instance and initializes the SQLite database.
   /// Sets up the database schema and ensures required tables exist.
   pub fn new(p: &Path) -> SqlResult<Self> {
     let db = p.join("storage.sqlite3");
     let conn = Connection::open(db)?;
-    let storage = Self { conn };
+    let storage = Self {
+      conn: Mutex::new(conn),
+    };
 
     storage.ensure()?;
     Ok(storage)
@@ -69,7 +341,7 @@ impl Storage {
     info!("Ensuring guild {:?} exists", id);
 
     self
-      .conn
+      .conn()
       .execute(
         "INSERT INTO guild_config (guild_id, config) VALUES ( ?1, '{}') ON CONFLICT DO NOTHING",
         [id],
@@ -84,7 +356,7 @@ impl Storage {
     // this would be dangerous, but the key is restricted to alphanumeric characters by the cmd_regex.
     let key = format!("$.{}", key);
     self.ensure_config(id);
-    self.conn.execute(
+    self.conn().execute(
       "UPDATE guild_config SET config = json_set(COALESCE(config, '{}'), ?1, ?2) WHERE guild_id = ?3",
       params![key, val, id],
     )?;
@@ -110,7 +382,7 @@ impl Storage {
   /// ensuring the bot can always operate even for new guilds.
   pub fn guild_config(&self, id: u64) -> SqlResult<GuildConfig> {
     self
-      .conn
+      .conn()
       .query_row(
         "SELECT id, guild_id, config FROM guild_config WHERE guild_id = ?1",
         [&id],
@@ -131,11 +403,216 @@ impl Storage {
       })
   }
 
+  /// Stores the token usage of a single API call.
+  pub fn record_usage(&self, record: &UsageRecord) -> SqlResult<()> {
+    self.conn().execute(
+      "INSERT INTO usage (guild_id, channel_id, user_id, model, input_tokens, output_tokens,
+                          cache_creation_tokens, cache_read_tokens, cost, created_at)
+       VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, strftime('%s', 'now'))",
+      params![
+        record.guild_id,
+        record.channel_id,
+        record.user_id,
+        record.model,
+        record.input_tokens,
+        record.output_tokens,
+        record.cache_creation_tokens,
+        record.cache_read_tokens,
+        record.cost
+      ],
+    )?;
+
+    Ok(())
+  }
+
+  /// Summarizes a guild's usage per model since the given unix timestamp.
+  pub fn usage_since(&self, guild_id: u64, since: i64) -> SqlResult<Vec<UsageSummary>> {
+    let conn = self.conn();
+    let mut stmt = conn.prepare(
+      "SELECT model, COUNT(*), SUM(input_tokens), SUM(output_tokens),
+              SUM(cache_creation_tokens), SUM(cache_read_tokens), SUM(cost) FROM usage
+       WHERE guild_id = ?1 AND created_at >= ?2
+       GROUP BY model ORDER BY SUM(cost) DESC",
+    )?;
+
+    stmt
+      .query_map(params![guild_id, since], |row| {
+        Ok(UsageSummary {
+          model: row.get(0)?,
+          calls: row.get(1)?,
+          input_tokens: row.get(2)?,
+          output_tokens: row.get(3)?,
+          cache_creation_tokens: row.get(4)?,
+          cache_read_tokens: row.get(5)?,
+          cost: row.get(6)?,
+        })
+      })?
+      .collect()
+  }
+
+  /// Total USD spent by a guild since the given unix timestamp.
+  pub fn spend_since(&self, guild_id: u64, since: i64) -> SqlResult<f64> {
+    self.conn().query_row(
+      "SELECT COALESCE(SUM(cost), 0) FROM usage WHERE guild_id = ?1 AND created_at >= ?2",
+      params![guild_id, since],
+      |row| row.get(0),
+    )
+  }
+
+  /// Checks whether a guild has used up its daily or monthly budget.
+  /// Guilds without a configured budget are never over budget.
+  pub fn over_budget(&self, config: &GuildConfig) -> SqlResult<bool> {
+    let now = Utc::now();
+    let today = now.date_naive();
+    let day_start = today.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp();
+    let month_start = today
+      .with_day(1)
+      .unwrap()
+      .and_hms_opt(0, 0, 0)
+      .unwrap()
+      .and_utc()
+      .timestamp();
+
+    if let Some(budget) = config.daily_budget()
+      && self.spend_since(config.guild_id, day_start)? >= budget
+    {
+      return Ok(true);
+    }
+
+    if let Some(budget) = config.monthly_budget()
+      && self.spend_since(config.guild_id, month_start)? >= budget
+    {
+      return Ok(true);
+    }
+
+    Ok(false)
+  }
+
+  /// Saves a reminder to be posted later, returning its ID.
+  pub fn add_reminder(&self, reminder: &Reminder) -> SqlResult<i64> {
+    let conn = self.conn();
+    conn.execute(
+      "INSERT INTO reminders (guild_id, channel_id, user_id, message_id, text, due_at, created_at)
+       VALUES (?1, ?2, ?3, ?4, ?5, ?6, strftime('%s', 'now'))",
+      params![
+        reminder.guild_id,
+        reminder.channel_id,
+        reminder.user_id,
+        reminder.message_id,
+        reminder.text,
+        reminder.due_at
+      ],
+    )?;
+
+    Ok(conn.last_insert_rowid())
+  }
+
+  /// A user's reminders in a guild that haven't been posted yet, soonest first.
+  pub fn pending_reminders(&self, guild_id: u64, user_id: u64) -> SqlResult<Vec<Reminder>> {
+    let conn = self.conn();
+    let mut stmt = conn.prepare(&format!(
+      "SELECT {} FROM reminders
+       WHERE guild_id = ?1 AND user_id = ?2 AND delivered_at IS NULL
+       ORDER BY due_at",
+      REMINDER_COLUMNS
+    ))?;
+
+    stmt
+      .query_map(params![guild_id, user_id], Reminder::from_row)?
+      .collect()
+  }
+
+  /// Deletes a reminder that hasn't been posted yet, if it belongs to the given user.
+  /// Returns whether there was one to delete.
+  pub fn cancel_reminder(&self, id: i64, user_id: u64) -> SqlResult<bool> {
+    let deleted = self.conn().execute(
+      "DELETE FROM reminders WHERE id = ?1 AND user_id = ?2 AND delivered_at IS NULL",
+      params![id, user_id],
+    )?;
+
+    Ok(deleted > 0)
+  }
+
+  /// Reminders due by the given unix timestamp that haven't been posted yet,
+  /// including any that came due while the bot was offline.
+  pub fn due_reminders(&self, now: i64) -> SqlResult<Vec<Reminder>> {
+    let conn = self.conn();
+    let mut stmt = conn.prepare(&format!(
+      "SELECT {} FROM reminders WHERE delivered_at IS NULL AND due_at <= ?1 ORDER BY due_at",
+      REMINDER_COLUMNS
+    ))?;
+
+    stmt.query_map([now], Reminder::from_row)?.collect()
+  }
+
+  /// Records that a reminder has been posted, so it isn't posted again.
+  pub fn mark_reminder_delivered(&self, id: i64) -> SqlResult<()> {
+    self.conn().execute(
+      "UPDATE reminders SET delivered_at = strftime('%s', 'now') WHERE id = ?1",
+      [id],
+    )?;
+
+    Ok(())
+  }
+
+  /// Remembers the jobs in a submitted batch, so their results are still posted after a restart.
+  pub fn add_batch_jobs(&self, jobs: &[BatchJob]) -> SqlResult<()> {
+    let conn = self.conn();
+    for job in jobs {
+      conn.execute(
+        "INSERT INTO batch_jobs (batch_id, custom_id, guild_id, channel_id, user_id, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, strftime('%s', 'now'))",
+        params![
+          job.batch_id,
+          job.custom_id,
+          job.guild_id,
+          job.channel_id,
+          job.user_id
+        ],
+      )?;
+    }
+
+    Ok(())
+  }
+
+  /// Every job in a batch whose results haven't been posted yet, oldest batch first.
+  pub fn pending_batch_jobs(&self) -> SqlResult<Vec<BatchJob>> {
+    let conn = self.conn();
+    let mut stmt = conn.prepare(
+      "SELECT batch_id, custom_id, guild_id, channel_id, user_id FROM batch_jobs ORDER BY id",
+    )?;
+
+    stmt
+      .query_map([], |row| {
+        Ok(BatchJob {
+          batch_id: row.get(0)?,
+          custom_id: row.get(1)?,
+          guild_id: row.get(2)?,
+          channel_id: row.get(3)?,
+          user_id: row.get(4)?,
+        })
+      })?
+      .collect()
+  }
+
+  /// Forgets a batch once its results have been posted, or given up on.
+  pub fn remove_batch(&self, batch_id: &str) -> SqlResult<()> {
+    self
+      .conn()
+      .execute("DELETE FROM batch_jobs WHERE batch_id = ?1", [batch_id])?;
+
+    Ok(())
+  }
+
+  fn conn(&self) -> MutexGuard<'_, Connection> {
+    self.conn.lock().unwrap()
+  }
+
   /// Initializes the database schema and creates required tables.
   /// Sets up the guild_config table with proper indexing and creates
   /// a default global configuration (guild_id = 0) for fallback behavior.
   fn ensure(&self) -> SqlResult<()> {
-    self.conn.execute(
+    self.conn().execute(
       "CREATE TABLE IF NOT EXISTS guild_config (
          id INTEGER PRIMARY KEY,
          guild_id INTEGER NOT NULL,
@@ -144,13 +621,337 @@ impl Storage {
       (),
     )?;
 
-    self.conn.execute(
+    self.conn().execute(
       "CREATE UNIQUE INDEX IF NOT EXISTS guild_config_on_guild_id ON guild_config (guild_id)",
       (),
     )?;
 
+    self.conn().execute(
+      "CREATE TABLE IF NOT EXISTS usage (
+         id INTEGER PRIMARY KEY,
+         guild_id INTEGER NOT NULL,
+         channel_id INTEGER NOT NULL,
+         user_id INTEGER NOT NULL,
+         model TEXT NOT NULL,
+         input_tokens INTEGER NOT NULL,
+         output_tokens INTEGER NOT NULL,
+         cache_creation_tokens INTEGER NOT NULL,
+         cache_read_tokens INTEGER NOT NULL,
+         cost REAL NOT NULL,
+         created_at INTEGER NOT NULL
+       )",
+      (),
+    )?;
+
+    self.conn().execute(
+      "CREATE INDEX IF NOT EXISTS usage_on_guild_id_created_at ON usage (guild_id, created_at)",
+      (),
+    )?;
+
+    self.conn().execute(
+      "CREATE TABLE IF NOT EXISTS reminders (
+         id INTEGER PRIMARY KEY,
+         guild_id INTEGER NOT NULL,
+         channel_id INTEGER NOT NULL,
+         user_id INTEGER NOT NULL,
+         message_id INTEGER NOT NULL,
+         text TEXT NOT NULL,
+         due_at INTEGER NOT NULL,
+         created_at INTEGER NOT NULL,
+         delivered_at INTEGER
+       )",
+      (),
+    )?;
+
+    self.conn().execute(
+      "CREATE INDEX IF NOT EXISTS reminders_on_due_at ON reminders (delivered_at, due_at)",
+      (),
+    )?;
+
+    self.conn().execute(
+      "CREATE TABLE IF NOT EXISTS batch_jobs (
+         id INTEGER PRIMARY KEY,
+         batch_id TEXT NOT NULL,
+         custom_id TEXT NOT NULL,
+         guild_id INTEGER NOT NULL,
+         channel_id INTEGER NOT NULL,
+         user_id INTEGER NOT NULL,
+         created_at INTEGER NOT NULL
+       )",
+      (),
+    )?;
+
     self.ensure_config(0);
 
     Ok(())
   }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+  use super::*;
+
+  fn test_dir(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("scrubby-{}-{}", name, std::process::id()))
+  }
+
+  /// A fresh database in a temporary directory, named so tests don't share one.
+  pub(crate) fn storage(name: &str) -> Storage {
+    let dir = test_dir(name);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::remove_file(dir.join("storage.sqlite3")).ok();
+    Storage::new(&dir).unwrap()
+  }
+
+  /// Opens the database `storage(name)` created again, as if the bot had restarted.
+  fn reopen(name: &str) -> Storage {
+    Storage::new(&test_dir(name)).unwrap()
+  }
+
+  fn record(guild_id: u64, model: &str, cost: f64) -> UsageRecord {
+    UsageRecord {
+      guild_id,
+      channel_id: 2,
+      user_id: 3,
+      model: model.into(),
+      input_tokens: 100,
+      output_tokens: 10,
+      cache_creation_tokens: 0,
+      cache_read_tokens: 50,
+      cost,
+    }
+  }
+
+  #[test]
+  fn test_sampling_config() {
+    let storage = storage("sampling");
+    storage.update_config(1, "temperature", "0.3").unwrap();
+    storage.update_config(1, "top_p", "1.5").unwrap();
+    storage
+      .update_config(1, "stop_sequences", "END| STOP |")
+      .unwrap();
+
+    let config = storage.guild_config(1).unwrap();
+    assert_eq!(config.temperature(), Some(0.3));
+    // out of range values are ignored rather than sent.
+    assert_eq!(config.top_p(), None);
+    assert_eq!(config.top_k(), None);
+    assert_eq!(config.stop_sequences(), vec!["END", " STOP "]);
+
+    assert!(GuildConfig::check_var("temperature", "0.7").is_ok());
+    assert_eq!(
+      GuildConfig::check_var("top_p", "2"),
+      Err("`top_p` has to be a number from 0 to 1.".into())
+    );
+    assert!(GuildConfig::check_var("top_k", "-3").is_err());
+    assert!(GuildConfig::check_var("thinking_budget", "16000").is_ok());
+    assert_eq!(
+      GuildConfig::check_var("thinking_budget", "100000"),
+      Err("`thinking_budget` has to be a whole number up to 64000.".into())
+    );
+    assert!(GuildConfig::check_var("max_tokens", "1000000").is_err());
+    assert!(GuildConfig::check_var("daily_budget", "2.50").is_ok());
+    assert!(GuildConfig::check_var("stop_sequences", " | ").is_err());
+    assert!(GuildConfig::check_var("personality", "anything goes").is_ok());
+    assert!(GuildConfig::check_var("provider", "local").is_ok());
+    assert!(GuildConfig::check_var("provider", "openai").is_err());
+    assert!(GuildConfig::check_var("budget_action", "downgrade").is_ok());
+    assert_eq!(
+      GuildConfig::check_var("budget_action", "downgarde"),
+      Err("`budget_action` has to be `refuse` or `downgrade`.".into())
+    );
+  }
+
+  #[test]
+  fn test_is_admin_only() {
+    for key in [
+      "daily_budget",
+      "budget_action",
+      "text_model",
+      "max_tokens",
+      "tools",
+      "fetch_max_chars",
+    ] {
+      assert!(
+        GuildConfig::is_admin_only(key),
+        "{} should need an admin",
+        key
+      );
+    }
+    for key in ["temperature", "tool_choice", "personality"] {
+      assert!(
+        !GuildConfig::is_admin_only(key),
+        "{} shouldn't need an admin",
+        key
+      );
+    }
+  }
+
+  #[test]
+  fn test_tool_settings() {
+    let storage = storage("tools");
+    assert_eq!(
+      storage.guild_config(1).unwrap().tool_settings(),
+      ToolSettings::default()
+    );
+
+    storage
+      .update_config(1, "tools", "web_search, code_execution")
+      .unwrap();
+    storage
+      .update_config(1, "web_search_max_uses", "2")
+      .unwrap();
+    storage.update_config(1, "fetch_max_chars", "2000").unwrap();
+    storage
+      .update_config(1, "fetch_blocked_domains", "internal.example.com")
+      .unwrap();
+    storage
+      .update_config(1, "web_search_blocked_domains", "example.com, example.org")
+      .unwrap();
+    let settings = storage.guild_config(1).unwrap().tool_settings();
+    assert_eq!(settings.enabled, vec!["web_search", "code_execution"]);
+    assert_eq!(settings.web_search_max_uses, 2);
+    assert_eq!(settings.fetch_max_chars, 2000);
+    assert_eq!(settings.fetch_allowed_domains, None);
+    assert_eq!(
+      settings.fetch_blocked_domains,
+      Some(vec!["internal.example.com".into()])
+    );
+    assert_eq!(
+      settings.web_search_blocked_domains,
+      Some(vec!["example.com".into(), "example.org".into()])
+    );
+
+    storage.update_config(1, "tools", "none").unwrap();
+    assert!(
+      storage
+        .guild_config(1)
+        .unwrap()
+        .tool_settings()
+        .enabled
+        .is_empty()
+    );
+  }
+
+  #[test]
+  fn test_usage_and_budget() {
+    let storage = storage("usage");
+    storage.update_config(1, "daily_budget", "0.50").unwrap();
+
+    storage.record_usage(&record(1, "haiku", 0.25)).unwrap();
+    storage.record_usage(&record(1, "haiku", 0.10)).unwrap();
+    storage.record_usage(&record(1, "sonnet", 0.05)).unwrap();
+    storage.record_usage(&record(9, "sonnet", 5.0)).unwrap();
+
+    let summary = storage.usage_since(1, 0).unwrap();
+    assert_eq!(summary.len(), 2);
+    assert_eq!(summary[0].model, "haiku");
+    assert_eq!(summary[0].calls, 2);
+    assert_eq!(summary[0].input_tokens, 200);
+    assert_eq!(summary[0].cache_read_tokens, 100);
+    assert!(
+      !storage
+        .over_budget(&storage.guild_config(1).unwrap())
+        .unwrap()
+    );
+
+    storage.record_usage(&record(1, "sonnet", 0.10)).unwrap();
+    assert!(
+      storage
+        .over_budget(&storage.guild_config(1).unwrap())
+        .unwrap()
+    );
+
+    // guilds without a budget are never limited.
+    assert!(
+      !storage
+        .over_budget(&storage.guild_config(9).unwrap())
+        .unwrap()
+    );
+  }
+
+  #[test]
+  fn test_reminders() {
+    let storage = storage("reminders");
+    let reminder = |user_id: u64, text: &str, due_at: i64| Reminder {
+      id: 0,
+      guild_id: 1,
+      channel_id: 2,
+      user_id,
+      message_id: 4,
+      text: text.into(),
+      due_at,
+    };
+
+    let later = storage.add_reminder(&reminder(3, "later", 2_000)).unwrap();
+    let soon = storage.add_reminder(&reminder(3, "soon", 1_000)).unwrap();
+    let other = storage.add_reminder(&reminder(5, "other", 1_500)).unwrap();
+
+    let pending = storage.pending_reminders(1, 3).unwrap();
+    assert_eq!(
+      pending.iter().map(|r| r.id).collect::<Vec<_>>(),
+      vec![soon, later]
+    );
+    assert_eq!(
+      pending[0],
+      Reminder {
+        id: soon,
+        ..reminder(3, "soon", 1_000)
+      }
+    );
+
+    // only the user who set a reminder can cancel it.
+    assert!(!storage.cancel_reminder(other, 3).unwrap());
+    assert!(storage.cancel_reminder(later, 3).unwrap());
+    assert!(!storage.cancel_reminder(later, 3).unwrap());
+
+    let due = storage.due_reminders(1_500).unwrap();
+    assert_eq!(
+      due.iter().map(|r| r.id).collect::<Vec<_>>(),
+      vec![soon, other]
+    );
+    storage.mark_reminder_delivered(soon).unwrap();
+
+    // undelivered reminders are still there after a restart.
+    drop(storage);
+    let storage = reopen("reminders");
+    let due = storage.due_reminders(1_500).unwrap();
+    assert_eq!(due.iter().map(|r| r.id).collect::<Vec<_>>(), vec![other]);
+    assert!(storage.pending_reminders(1, 3).unwrap().is_empty());
+  }
+
+  #[test]
+  fn test_batch_jobs() {
+    let storage = storage("batches");
+    let job = |batch_id: &str, custom_id: &str| BatchJob {
+      batch_id: batch_id.into(),
+      custom_id: custom_id.into(),
+      guild_id: 1,
+      channel_id: 2,
+      user_id: 3,
+    };
+
+    storage
+      .add_batch_jobs(&[job("batch_1", "job-0"), job("batch_1", "job-1")])
+      .unwrap();
+    storage.add_batch_jobs(&[job("batch_2", "job-0")]).unwrap();
+
+    // submitted batches are still there after a restart.
+    drop(storage);
+    let storage = reopen("batches");
+    assert_eq!(
+      storage.pending_batch_jobs().unwrap(),
+      vec![
+        job("batch_1", "job-0"),
+        job("batch_1", "job-1"),
+        job("batch_2", "job-0")
+      ]
+    );
+
+    storage.remove_batch("batch_1").unwrap();
+    assert_eq!(
+      storage.pending_batch_jobs().unwrap(),
+      vec![job("batch_2", "job-0")]
+    );
+  }
+}